@@ -0,0 +1,138 @@
+//! Admin API for inspecting and changing a running server.
+//!
+//! Routes live under `/_admin` and bypass the error, auth and latency
+//! middleware, so they keep working while the server is configured to fail.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post, put},
+    Json, Router,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::config::{Config, RuntimeState};
+use crate::errors::Provider;
+use crate::journal::{JournalEntry, JournalFilter};
+
+type AppState = Arc<RuntimeState>;
+
+/// Routes for the admin API.
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/_admin/config",
+            get(get_config).put(put_config).patch(patch_config),
+        )
+        .route("/_admin/reset", post(reset))
+        .route("/_admin/requests", get(get_requests).delete(clear_requests))
+        .route("/_admin/providers", get(get_providers))
+        .route("/_admin/providers/{provider}", put(put_provider))
+}
+
+async fn get_config(State(state): State<AppState>) -> Json<Config> {
+    Json(state.config().clone())
+}
+
+/// Replace the whole config. Omitted sections fall back to their defaults.
+async fn put_config(State(state): State<AppState>, Json(config): Json<Config>) -> Json<Config> {
+    state.set_config(config);
+    Json(state.config().clone())
+}
+
+/// Apply a JSON merge patch (RFC 7396) to the live config.
+async fn patch_config(State(state): State<AppState>, Json(patch): Json<Value>) -> Response {
+    let mut merged = serde_json::to_value(&*state.config()).unwrap();
+    merge_patch(&mut merged, patch);
+    match serde_json::from_value::<Config>(merged) {
+        Ok(config) => {
+            state.set_config(config);
+            Json(state.config().clone()).into_response()
+        }
+        Err(e) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({ "error": format!("Invalid config: {e}") })),
+        )
+            .into_response(),
+    }
+}
+
+/// Reset the request counter and clock used by `fail_after_requests` and error
+/// schedules, and the rate limiter windows.
+async fn reset(State(state): State<AppState>) -> Json<Value> {
+    let previous = state.reset_requests();
+    state.rate_limiter.reset();
+    Json(json!({ "request_count": 0, "previous_request_count": previous }))
+}
+
+/// Journaled requests, filtered by query parameters.
+async fn get_requests(
+    State(state): State<AppState>,
+    Query(filter): Query<JournalFilter>,
+) -> Json<Vec<JournalEntry>> {
+    Json(state.requests(&filter))
+}
+
+async fn clear_requests(State(state): State<AppState>) -> Json<Value> {
+    Json(json!({ "cleared": state.clear_requests() }))
+}
+
+async fn get_providers(State(state): State<AppState>) -> Json<Value> {
+    Json(serde_json::to_value(&state.config().providers).unwrap())
+}
+
+#[derive(Debug, Deserialize)]
+struct ProviderToggle {
+    enabled: bool,
+}
+
+async fn put_provider(
+    State(state): State<AppState>,
+    Path(provider): Path<Provider>,
+    Json(toggle): Json<ProviderToggle>,
+) -> Json<Value> {
+    state.update_config(|config| config.providers.set_enabled(provider, toggle.enabled));
+    Json(serde_json::to_value(&state.config().providers).unwrap())
+}
+
+/// Merge `patch` into `target`: objects merge recursively, `null` removes a
+/// field (restoring its default), and anything else replaces the value.
+fn merge_patch(target: &mut Value, patch: Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch;
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(serde_json::Map::new());
+    }
+    let target = target.as_object_mut().unwrap();
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(&key);
+        } else {
+            merge_patch(target.entry(key).or_insert(Value::Null), value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_patch() {
+        let mut target = json!({"errors": {"error_rate": 0.0, "force_error": "none"}, "a": 1});
+        merge_patch(
+            &mut target,
+            json!({"errors": {"force_error": "rate_limit"}, "a": null, "b": [1]}),
+        );
+        assert_eq!(
+            target,
+            json!({"errors": {"error_rate": 0.0, "force_error": "rate_limit"}, "b": [1]})
+        );
+    }
+}
@@ -3,18 +3,19 @@
 //!
 //! Generates responses matching the exact structure of the real Cerebras API.
 
-use crate::generator::ContentGenerator;
+use crate::fixtures::History;
+use crate::generator::{ContentGenerator, StopSequences};
+use crate::schema;
+use crate::timing::Events;
+use crate::tools::{ToolChoice, ToolMode};
+use crate::ProviderRequest;
 use axum::{
-    body::Body,
-    http::{header, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
-use futures::stream::{self, StreamExt};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use tokio::time::sleep;
+use std::collections::HashMap;
 
 /// Request body for chat completions.
 #[derive(Debug, Deserialize)]
@@ -25,7 +26,10 @@ pub struct ChatCompletionRequest {
     pub stream: bool,
     #[serde(default)]
     pub stream_options: Option<StreamOptions>,
+    #[serde(alias = "max_completion_tokens")]
     pub max_tokens: Option<u32>,
+    #[serde(default)]
+    pub stop: Option<StopSequences>,
 
     pub temperature: Option<f32>,
 
@@ -33,12 +37,33 @@ pub struct ChatCompletionRequest {
     pub tools: Option<Vec<Tool>>,
 
     pub tool_choice: Option<Value>,
+    #[serde(default)]
+    pub parallel_tool_calls: Option<bool>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Message {
     pub role: String,
     pub content: Option<String>,
+    /// Tool calls made by a previous assistant message.
+    #[serde(default)]
+    pub tool_calls: Option<Vec<MessageToolCall>>,
+    /// ID of the call a `tool` message responds to.
+    #[serde(default)]
+    pub tool_call_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MessageToolCall {
+    pub id: String,
+    pub function: MessageFunction,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MessageFunction {
+    pub name: String,
+    #[serde(default)]
+    pub arguments: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -128,11 +153,17 @@ pub struct TimeInfo {
 }
 
 /// Main handler for /v1/chat/completions
-pub async fn chat_completions(Json(req): Json<ChatCompletionRequest>) -> Response {
-    let gen = ContentGenerator::new();
-
+pub async fn chat_completions(
+    mut gen: ContentGenerator,
+    Json(req): Json<ChatCompletionRequest>,
+) -> Response {
     // Check if tool calling is requested
-    let wants_tools = req.tools.is_some() && should_call_tool(&req);
+    let wants_tools = gen.scripted_tool_use().unwrap_or_else(|| {
+        !req.tool_names().is_empty()
+            && req
+                .tool_choice()
+                .should_call(|| should_call_tool(&req, &mut gen))
+    });
 
     if req.stream {
         stream_response(req, gen, wants_tools).await
@@ -141,20 +172,101 @@ pub async fn chat_completions(Json(req): Json<ChatCompletionRequest>) -> Respons
     }
 }
 
-/// Decide if we should generate a tool call response.
-fn should_call_tool(req: &ChatCompletionRequest) -> bool {
-    // Simple heuristic: if the last message mentions something tool-like
-    if let Some(last) = req.messages.last() {
-        if let Some(content) = &last.content {
-            let lower = content.to_lowercase();
-            return lower.contains("weather")
-                || lower.contains("search")
-                || lower.contains("calculate")
-                || lower.contains("what is")
-                || lower.contains("find");
-        }
+impl ChatCompletionRequest {
+    /// Custom sequences that end generation.
+    pub fn stop_sequences(&self) -> &[String] {
+        self.stop.as_ref().map_or(&[], StopSequences::as_slice)
+    }
+}
+
+impl ProviderRequest for ChatCompletionRequest {
+    /// Estimated prompt tokens, as reported in `usage.prompt_tokens`.
+    fn input_tokens(&self) -> u32 {
+        self.messages
+            .iter()
+            .filter_map(|m| m.content.as_ref())
+            .map(|c| ContentGenerator::estimate_tokens(c))
+            .sum()
+    }
+
+    /// Requested output token limit.
+    fn max_output_tokens(&self) -> Option<u32> {
+        self.max_tokens
+    }
+
+    /// Tool calling constraints from `tool_choice` and `parallel_tool_calls`.
+    fn tool_choice(&self) -> ToolChoice {
+        let mut choice = match &self.tool_choice {
+            Some(Value::String(mode)) => match mode.as_str() {
+                "none" => ToolChoice::new(ToolMode::None),
+                "required" => ToolChoice::new(ToolMode::Required),
+                _ => ToolChoice::default(),
+            },
+            Some(choice) => match choice["function"]["name"].as_str() {
+                Some(name) => ToolChoice::function(name),
+                None => ToolChoice::default(),
+            },
+            None => ToolChoice::default(),
+        };
+        choice.parallel = self.parallel_tool_calls.unwrap_or(true);
+        choice
     }
-    false
+
+    /// Names of the declared tools.
+    fn tool_names(&self) -> Vec<&str> {
+        self.tools
+            .iter()
+            .flatten()
+            .map(|t| t.function.name.as_str())
+            .collect()
+    }
+
+    /// Text of the most recent user message.
+    fn last_user_text(&self) -> Option<&str> {
+        self.messages
+            .iter()
+            .rev()
+            .filter(|m| m.role == "user")
+            .find_map(|m| m.content.as_deref())
+    }
+
+    /// Turn number and pending tool results, from `tool_calls` / `tool` messages.
+    fn history(&self) -> History {
+        let tool_names: HashMap<&str, &str> = self
+            .messages
+            .iter()
+            .filter_map(|m| m.tool_calls.as_ref())
+            .flatten()
+            .map(|c| (c.id.as_str(), c.function.name.as_str()))
+            .collect();
+
+        History::from_entries(self.messages.iter().map(|m| match m.role.as_str() {
+            "assistant" => (true, None),
+            "tool" => {
+                let id = m.tool_call_id.as_deref().unwrap_or_default();
+                let name = tool_names.get(id).unwrap_or(&"");
+                (false, Some((*name).to_string()))
+            }
+            _ => (false, None),
+        }))
+    }
+}
+
+/// Decide if we should generate a tool call response, from the last message.
+fn should_call_tool(req: &ChatCompletionRequest, gen: &mut ContentGenerator) -> bool {
+    let text = req.messages.last().and_then(|last| last.content.as_deref());
+    let tool_texts = req
+        .tools
+        .iter()
+        .flatten()
+        .flat_map(|t| {
+            [
+                Some(t.function.name.as_str()),
+                t.function.description.as_deref(),
+            ]
+        })
+        .flatten();
+    gen.triggers_tool_call(text, tool_texts)
 }
 
 /// Generate non-streaming response.
@@ -164,50 +276,49 @@ fn non_stream_response(
     wants_tools: bool,
 ) -> Response {
     let id = gen.completion_id();
-    let created = now_unix();
+    let created = gen.now_unix();
     let fingerprint = gen.fingerprint();
 
-    let max_tokens = req.max_tokens.unwrap_or(100);
-    let content = gen.paragraph();
-    let completion_tokens = ContentGenerator::estimate_tokens(&content).min(max_tokens);
-
-    let prompt_tokens: u32 = req
-        .messages
-        .iter()
-        .filter_map(|m| m.content.as_ref())
-        .map(|c| ContentGenerator::estimate_tokens(c))
-        .sum();
-
-    let (message, finish_reason) = if wants_tools {
-        let tool = req.tools.as_ref().and_then(|t| t.first());
-        let tool_name = tool.map(|t| t.function.name.clone()).unwrap_or_default();
-
-        // Extract a location or query from the message
-        let arg_value = extract_argument(&req);
+    let (mut content, _) = gen.reply_text(ContentGenerator::paragraph, req.stop_sequences());
+    let truncated = req
+        .max_tokens
+        .is_some_and(|max_tokens| ContentGenerator::truncate_to_tokens(&mut content, max_tokens));
+
+    let prompt_tokens = req.input_tokens();
+
+    let (message, finish_reason, completion_tokens) = if wants_tools {
+        let (tool_calls, tokens, truncated) = limited_tool_calls(&req, &mut gen);
+        let tool_calls = tool_calls
+            .into_iter()
+            .map(|(tool_name, arguments)| ToolCall {
+                id: gen.tool_call_id(),
+                call_type: "function",
+                function: FunctionCall {
+                    name: tool_name,
+                    arguments,
+                },
+            })
+            .collect();
 
         (
             ResponseMessage {
                 role: "assistant",
                 content: None,
-                tool_calls: Some(vec![ToolCall {
-                    id: gen.tool_call_id(),
-                    call_type: "function",
-                    function: FunctionCall {
-                        name: tool_name,
-                        arguments: json!({ "location": arg_value }).to_string(),
-                    },
-                }]),
+                tool_calls: Some(tool_calls),
             },
-            "tool_calls",
+            if truncated { "length" } else { "tool_calls" },
+            tokens,
         )
     } else {
+        let completion_tokens = ContentGenerator::estimate_tokens(&content);
         (
             ResponseMessage {
                 role: "assistant",
                 content: Some(content),
                 tool_calls: None,
             },
-            "stop",
+            if truncated { "length" } else { "stop" },
+            completion_tokens,
         )
     };
 
@@ -233,7 +344,7 @@ fn non_stream_response(
             prompt_time: 0.003,
             completion_time: 0.005,
             total_time: 0.035,
-            created: now_unix() as f64,
+            created: created as f64,
         },
     };
 
@@ -250,102 +361,100 @@ async fn stream_response(
     let model = req.model.clone();
     let fingerprint = gen.fingerprint();
     let include_usage = req.stream_options.as_ref().is_some_and(|o| o.include_usage);
-    let max_tokens = req.max_tokens.unwrap_or(50) as usize;
 
-    let prompt_tokens: u32 = req
-        .messages
-        .iter()
-        .filter_map(|m| m.content.as_ref())
-        .map(|c| ContentGenerator::estimate_tokens(c))
-        .sum();
+    let prompt_tokens = req.input_tokens();
 
     // Generate chunks
-    let chunks = if wants_tools {
-        generate_tool_chunks(&req, &mut gen, &id, &model, &fingerprint)
+    let (chunks, finish_reason) = if wants_tools {
+        let (chunks, truncated) = generate_tool_chunks(&req, &mut gen, &id, &model, &fingerprint);
+        (chunks, if truncated { "length" } else { "tool_calls" })
     } else {
-        generate_content_chunks(&mut gen, max_tokens, &id, &model, &fingerprint)
+        let (chunks, truncated) =
+            generate_content_chunks(&req, &mut gen, &id, &model, &fingerprint);
+        (chunks, if truncated { "length" } else { "stop" })
     };
 
-    let finish_reason = if wants_tools { "tool_calls" } else { "stop" };
-
     // Calculate total completion tokens
-    let completion_tokens: u32 = chunks
-        .iter()
-        .filter_map(|c| {
-            c.get("choices")
-                .and_then(|ch| ch.get(0))
-                .and_then(|ch| ch.get("delta"))
-                .and_then(|d| d.get("content"))
-                .and_then(|c| c.as_str())
-                .map(ContentGenerator::estimate_tokens)
-        })
-        .sum::<u32>()
-        .max(1);
-
-    // Build the stream
-    let created = now_unix();
-    let stream = stream::iter(chunks)
-        .chain(stream::once(async move {
-            // Final chunk with finish_reason and usage
-            let mut final_chunk = json!({
-                "id": id,
-                "object": "chat.completion.chunk",
-                "created": created,
-                "model": model,
-                "system_fingerprint": fingerprint,
-                "choices": [{
-                    "index": 0,
-                    "delta": {},
-                    "finish_reason": finish_reason
-                }]
+    let text: String = chunks.iter().flat_map(chunk_text).collect();
+    let completion_tokens = ContentGenerator::estimate_tokens(&text).max(1);
+
+    // Final chunk with finish_reason and usage
+    let created = gen.now_unix();
+    let final_chunk = {
+        let mut final_chunk = json!({
+            "id": id,
+            "object": "chat.completion.chunk",
+            "created": created,
+            "model": model,
+            "system_fingerprint": fingerprint,
+            "choices": [{
+                "index": 0,
+                "delta": {},
+                "finish_reason": finish_reason
+            }]
+        });
+
+        if include_usage {
+            final_chunk["usage"] = json!({
+                "prompt_tokens": prompt_tokens,
+                "completion_tokens": completion_tokens,
+                "total_tokens": prompt_tokens + completion_tokens,
+                "prompt_tokens_details": { "cached_tokens": 0 }
             });
+            final_chunk["time_info"] = json!({
+                "queue_time": 0.025,
+                "prompt_time": 0.003,
+                "completion_time": 0.005,
+                "total_time": 0.035,
+                "created": created as f64
+            });
+        }
 
-            if include_usage {
-                final_chunk["usage"] = json!({
-                    "prompt_tokens": prompt_tokens,
-                    "completion_tokens": completion_tokens,
-                    "total_tokens": prompt_tokens + completion_tokens,
-                    "prompt_tokens_details": { "cached_tokens": 0 }
-                });
-                final_chunk["time_info"] = json!({
-                    "queue_time": 0.025,
-                    "prompt_time": 0.003,
-                    "completion_time": 0.005,
-                    "total_time": 0.035,
-                    "created": now_unix() as f64
-                });
-            }
+        final_chunk
+    };
 
-            final_chunk
-        }))
-        .then(|chunk| async move {
-            // Add small delay for realistic streaming
-            sleep(Duration::from_millis(15)).await;
-            format!("data: {chunk}\n\n")
-        })
-        .chain(stream::once(async { "data: [DONE]\n\n".to_string() }))
-        .map(Ok::<_, std::convert::Infallible>);
-
-    let body = Body::from_stream(stream);
-
-    Response::builder()
-        .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, "text/event-stream")
-        .header(header::CACHE_CONTROL, "no-cache")
-        .header(header::CONNECTION, "keep-alive")
-        .body(body)
-        .unwrap()
+    let mut events = Events::new();
+    for chunk in chunks.into_iter().chain([final_chunk]) {
+        let tokens = chunk_tokens(&chunk);
+        events.push_tokens(format!("data: {chunk}\n\n"), tokens);
+    }
+    events.push("data: [DONE]\n\n".to_string());
+    gen.paced_response(events)
+}
+
+/// Output tokens carried by a streamed chunk's delta.
+fn chunk_tokens(chunk: &Value) -> u32 {
+    chunk_text(chunk)
+        .into_iter()
+        .map(ContentGenerator::estimate_tokens)
+        .sum()
 }
 
-/// Generate content chunks for streaming.
+/// Content and tool call argument fragments in a streamed chunk's delta.
+fn chunk_text(chunk: &Value) -> Vec<&str> {
+    let delta = &chunk["choices"][0]["delta"];
+    let arguments = delta["tool_calls"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|call| call["function"]["arguments"].as_str());
+    delta["content"]
+        .as_str()
+        .into_iter()
+        .chain(arguments)
+        .collect()
+}
+
+/// Generate content chunks for streaming, ending at a stop sequence or cut
+/// to `max_tokens`. Also returns whether the content was cut.
 fn generate_content_chunks(
+    req: &ChatCompletionRequest,
     gen: &mut ContentGenerator,
-    max_tokens: usize,
     id: &str,
     model: &str,
     fingerprint: &str,
-) -> Vec<Value> {
-    let created = now_unix();
+) -> (Vec<Value>, bool) {
+    let created = gen.now_unix();
 
     // First chunk: role
     let mut chunks = vec![json!({
@@ -361,9 +470,10 @@ fn generate_content_chunks(
     })];
 
     // Content chunks
-    let content_parts = gen.stream_chunks(max_tokens);
-    for (i, content) in content_parts.into_iter().enumerate() {
-        let prefix = if i > 0 { " " } else { "" };
+    let (text, _) = gen.reply_text(ContentGenerator::paragraph, req.stop_sequences());
+    let mut deltas = gen.chunk_text(&text);
+    let truncated = ContentGenerator::limit_chunks(&mut deltas, req.max_tokens);
+    for content in deltas {
         chunks.push(json!({
             "id": id,
             "object": "chat.completion.chunk",
@@ -372,65 +482,119 @@ fn generate_content_chunks(
             "system_fingerprint": fingerprint,
             "choices": [{
                 "index": 0,
-                "delta": { "content": format!("{}{}", prefix, content) }
+                "delta": { "content": content }
             }]
         }));
     }
 
-    chunks
+    (chunks, truncated)
 }
 
-/// Generate tool call chunks for streaming.
+/// Generate tool call chunks for streaming, cut to `max_tokens`. Also
+/// returns whether the last call's arguments were cut.
 fn generate_tool_chunks(
     req: &ChatCompletionRequest,
     gen: &mut ContentGenerator,
     id: &str,
     model: &str,
     fingerprint: &str,
-) -> Vec<Value> {
-    let created = now_unix();
+) -> (Vec<Value>, bool) {
+    let created = gen.now_unix();
 
-    let tool = req.tools.as_ref().and_then(|t| t.first());
-    let tool_name = tool.map(|t| t.function.name.clone()).unwrap_or_default();
-    let arg_value = extract_argument(req);
-    let tool_call_id = gen.tool_call_id();
+    // First chunk: role
+    let mut chunks = vec![json!({
+        "id": id,
+        "object": "chat.completion.chunk",
+        "created": created,
+        "model": model,
+        "system_fingerprint": fingerprint,
+        "choices": [{
+            "index": 0,
+            "delta": { "role": "assistant" }
+        }]
+    })];
 
-    vec![
-        // First chunk: role
-        json!({
-            "id": id,
-            "object": "chat.completion.chunk",
-            "created": created,
-            "model": model,
-            "system_fingerprint": fingerprint,
-            "choices": [{
-                "index": 0,
-                "delta": { "role": "assistant" }
-            }]
-        }),
-        // Tool call chunk
-        json!({
-            "id": id,
-            "object": "chat.completion.chunk",
-            "created": created,
-            "model": model,
-            "system_fingerprint": fingerprint,
-            "choices": [{
-                "index": 0,
-                "delta": {
-                    "tool_calls": [{
-                        "index": 0,
-                        "id": tool_call_id,
-                        "type": "function",
-                        "function": {
-                            "name": tool_name,
-                            "arguments": json!({ "location": arg_value }).to_string()
-                        }
-                    }]
-                }
-            }]
-        }),
-    ]
+    // Tool call chunks: the call's ID and name, then its arguments in fragments
+    let (tool_calls, _, truncated) = limited_tool_calls(req, gen);
+    for (index, (tool_name, arguments)) in tool_calls.into_iter().enumerate() {
+        let call = json!({
+            "index": index,
+            "id": gen.tool_call_id(),
+            "type": "function",
+            "function": { "name": tool_name, "arguments": "" }
+        });
+        let deltas = gen.argument_deltas(&arguments);
+        let fragments = deltas
+            .into_iter()
+            .map(|arguments| json!({ "index": index, "function": { "arguments": arguments } }));
+        for tool_call in [call].into_iter().chain(fragments) {
+            chunks.push(json!({
+                "id": id,
+                "object": "chat.completion.chunk",
+                "created": created,
+                "model": model,
+                "system_fingerprint": fingerprint,
+                "choices": [{
+                    "index": 0,
+                    "delta": { "tool_calls": [tool_call] }
+                }]
+            }));
+        }
+    }
+
+    (chunks, truncated)
+}
+
+/// Tool calls for a reply, cut to fit in `max_tokens`, with their output
+/// tokens and whether the last call's arguments were cut short.
+fn limited_tool_calls(
+    req: &ChatCompletionRequest,
+    gen: &mut ContentGenerator,
+) -> (Vec<(String, String)>, u32, bool) {
+    let (names, mut arguments): (Vec<String>, Vec<String>) =
+        tool_calls(req, gen).into_iter().unzip();
+    let (tokens, truncated) =
+        ContentGenerator::limit_tool_arguments(&mut arguments, req.max_tokens);
+    (
+        names.into_iter().zip(arguments).collect(),
+        tokens,
+        truncated,
+    )
+}
+
+/// Pick the tool names and JSON arguments for a tool call reply, preferring
+/// scripted calls. Generated calls go to the tools `tool_choice` allows in
+/// order, wrapping around when there are more calls than tools.
+fn tool_calls(req: &ChatCompletionRequest, gen: &mut ContentGenerator) -> Vec<(String, String)> {
+    if let Some(calls) = gen.take_scripted_tool_calls() {
+        return calls
+            .into_iter()
+            .map(|call| (call.name, gen.encode_arguments(&call.arguments)))
+            .collect();
+    }
+
+    // Extract a location or query from the message
+    let arg_value = extract_argument(req);
+    let choice = req.tool_choice();
+    let tools: Vec<&Tool> = req
+        .tools
+        .iter()
+        .flatten()
+        .filter(|tool| choice.allows(&tool.function.name))
+        .collect();
+    let count = gen.tool_call_count(choice.parallel);
+    tools
+        .iter()
+        .cycle()
+        .take(count)
+        .map(|tool| {
+            let arguments = match &tool.function.parameters {
+                Some(parameters) => schema::arguments(parameters, &arg_value, gen),
+                None => json!({ "location": arg_value }),
+            };
+            (tool.function.name.clone(), gen.encode_arguments(&arguments))
+        })
+        .collect()
 }
 
 /// Extract an argument value from the user message.
@@ -452,17 +616,11 @@ fn extract_argument(req: &ChatCompletionRequest) -> String {
         )
 }
 
-/// Get current unix timestamp.
-fn now_unix() -> u64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs()
-}
-
 #[cfg(test)]
+#[allow(clippy::unreadable_literal)]
 mod tests {
     use super::*;
+    use axum::http::StatusCode;
 
     #[test]
     fn test_deserialize_request() {
@@ -523,17 +681,21 @@ mod tests {
             messages: vec![Message {
                 role: "user".to_string(),
                 content: Some("What is the weather in Tokyo?".to_string()),
+                tool_calls: None,
+                tool_call_id: None,
             }],
             stream: false,
             stream_options: None,
             max_tokens: None,
+            stop: None,
             temperature: None,
             top_p: None,
             tools: Some(vec![]),
             tool_choice: None,
+            parallel_tool_calls: None,
         };
 
-        assert!(should_call_tool(&req));
+        assert!(should_call_tool(&req, &mut ContentGenerator::new()));
     }
 
     #[test]
@@ -543,17 +705,21 @@ mod tests {
             messages: vec![Message {
                 role: "user".to_string(),
                 content: Some("Hello there!".to_string()),
+                tool_calls: None,
+                tool_call_id: None,
             }],
             stream: false,
             stream_options: None,
             max_tokens: None,
+            stop: None,
             temperature: None,
             top_p: None,
             tools: Some(vec![]),
             tool_choice: None,
+            parallel_tool_calls: None,
         };
 
-        assert!(!should_call_tool(&req));
+        assert!(!should_call_tool(&req, &mut ContentGenerator::new()));
     }
 
     #[test]
@@ -563,17 +729,43 @@ mod tests {
             messages: vec![Message {
                 role: "user".to_string(),
                 content: Some("Tell me a joke".to_string()),
+                tool_calls: None,
+                tool_call_id: None,
             }],
             stream: false,
             stream_options: None,
             max_tokens: None,
+            stop: None,
             temperature: None,
             top_p: None,
             tools: Some(vec![]),
             tool_choice: None,
+            parallel_tool_calls: None,
         };
 
-        assert!(!should_call_tool(&req));
+        assert!(!should_call_tool(&req, &mut ContentGenerator::new()));
+    }
+
+    #[test]
+    fn test_history_after_tool_message() {
+        let json = r#"{
+            "model": "llama",
+            "messages": [
+                {"role": "system", "content": "Be brief."},
+                {"role": "user", "content": "What is the weather in Rome?"},
+                {"role": "assistant", "content": null, "tool_calls": [{
+                    "id": "call_1", "type": "function",
+                    "function": {"name": "get_weather", "arguments": "{}"}
+                }]},
+                {"role": "tool", "tool_call_id": "call_1", "content": "Sunny"}
+            ]
+        }"#;
+        let req: ChatCompletionRequest = serde_json::from_str(json).unwrap();
+
+        let history = req.history();
+        assert_eq!(history.turn, 2);
+        assert_eq!(history.tool_names, vec!["get_weather".to_string()]);
+        assert_eq!(req.last_user_text(), Some("What is the weather in Rome?"));
     }
 
     #[test]
@@ -583,14 +775,18 @@ mod tests {
             messages: vec![Message {
                 role: "user".to_string(),
                 content: Some("What is the weather in Tokyo?".to_string()),
+                tool_calls: None,
+                tool_call_id: None,
             }],
             stream: false,
             stream_options: None,
             max_tokens: None,
+            stop: None,
             temperature: None,
             top_p: None,
             tools: None,
             tool_choice: None,
+            parallel_tool_calls: None,
         };
 
         let arg = extract_argument(&req);
@@ -604,14 +800,18 @@ mod tests {
             messages: vec![Message {
                 role: "user".to_string(),
                 content: None,
+                tool_calls: None,
+                tool_call_id: None,
             }],
             stream: false,
             stream_options: None,
             max_tokens: None,
+            stop: None,
             temperature: None,
             top_p: None,
             tools: None,
             tool_choice: None,
+            parallel_tool_calls: None,
         };
 
         let arg = extract_argument(&req);
@@ -620,18 +820,35 @@ mod tests {
 
     #[test]
     fn test_now_unix() {
-        let ts = now_unix();
+        let ts = ContentGenerator::new().now_unix();
         assert!(ts > 1700000000); // After 2023
     }
 
     #[test]
     fn test_generate_content_chunks() {
         let mut gen = ContentGenerator::with_seed(42);
-        let chunks = generate_content_chunks(&mut gen, 10, "test-id", "llama", "fp_test");
+        let mut req: ChatCompletionRequest = serde_json::from_value(json!({
+            "model": "llama",
+            "messages": [{"role": "user", "content": "Hello"}]
+        }))
+        .unwrap();
+        let (chunks, truncated) =
+            generate_content_chunks(&req, &mut gen, "test-id", "llama", "fp_test");
         assert!(!chunks.is_empty());
+        assert!(!truncated);
         // First chunk should have role
         let first = &chunks[0];
         assert!(first.to_string().contains("assistant"));
+
+        req.max_tokens = Some(3);
+        let (chunks, truncated) =
+            generate_content_chunks(&req, &mut gen, "test-id", "llama", "fp_test");
+        assert!(truncated);
+        let content: String = chunks
+            .iter()
+            .filter_map(|c| c["choices"][0]["delta"]["content"].as_str())
+            .collect();
+        assert_eq!(ContentGenerator::estimate_tokens(&content), 3);
     }
 
     #[test]
@@ -641,10 +858,13 @@ mod tests {
             messages: vec![Message {
                 role: "user".to_string(),
                 content: Some("What is the weather in Tokyo?".to_string()),
+                tool_calls: None,
+                tool_call_id: None,
             }],
             stream: false,
             stream_options: None,
             max_tokens: None,
+            stop: None,
             temperature: None,
             top_p: None,
             tools: Some(vec![Tool {
@@ -656,13 +876,21 @@ mod tests {
                 },
             }]),
             tool_choice: None,
+            parallel_tool_calls: None,
         };
         let mut gen = ContentGenerator::with_seed(42);
-        let chunks = generate_tool_chunks(&req, &mut gen, "test-id", "llama", "fp_test");
-        assert_eq!(chunks.len(), 2);
-        // Should contain tool_calls
-        let second = &chunks[1];
-        assert!(second.to_string().contains("tool_calls"));
+        let (chunks, _) = generate_tool_chunks(&req, &mut gen, "test-id", "llama", "fp_test");
+        let calls: Vec<&Value> = chunks[1..]
+            .iter()
+            .map(|c| &c["choices"][0]["delta"]["tool_calls"][0])
+            .collect();
+        assert_eq!(calls[0]["function"]["name"], "get_weather");
+        assert!(calls.iter().all(|call| call["index"] == 0));
+        let arguments: String = calls
+            .iter()
+            .filter_map(|call| call["function"]["arguments"].as_str())
+            .collect();
+        assert_eq!(arguments, r#"{"location":"Tokyo"}"#);
     }
 
     #[tokio::test]
@@ -672,17 +900,21 @@ mod tests {
             messages: vec![Message {
                 role: "user".to_string(),
                 content: Some("Hello".to_string()),
+                tool_calls: None,
+                tool_call_id: None,
             }],
             stream: false,
             stream_options: None,
             max_tokens: Some(50),
+            stop: None,
             temperature: None,
             top_p: None,
             tools: None,
             tool_choice: None,
+            parallel_tool_calls: None,
         };
 
-        let response = chat_completions(Json(req)).await;
+        let response = chat_completions(ContentGenerator::new(), Json(req)).await;
         assert_eq!(response.status(), StatusCode::OK);
     }
 
@@ -693,17 +925,21 @@ mod tests {
             messages: vec![Message {
                 role: "user".to_string(),
                 content: Some("Hello".to_string()),
+                tool_calls: None,
+                tool_call_id: None,
             }],
             stream: true,
             stream_options: None,
             max_tokens: Some(50),
+            stop: None,
             temperature: None,
             top_p: None,
             tools: None,
             tool_choice: None,
+            parallel_tool_calls: None,
         };
 
-        let response = chat_completions(Json(req)).await;
+        let response = chat_completions(ContentGenerator::new(), Json(req)).await;
         assert_eq!(response.status(), StatusCode::OK);
         assert_eq!(
             response.headers().get("content-type").unwrap(),
@@ -718,10 +954,13 @@ mod tests {
             messages: vec![Message {
                 role: "user".to_string(),
                 content: Some("What is the weather in Paris?".to_string()),
+                tool_calls: None,
+                tool_call_id: None,
             }],
             stream: false,
             stream_options: None,
             max_tokens: Some(50),
+            stop: None,
             temperature: None,
             top_p: None,
             tools: Some(vec![Tool {
@@ -733,9 +972,10 @@ mod tests {
                 },
             }]),
             tool_choice: None,
+            parallel_tool_calls: None,
         };
 
-        let response = chat_completions(Json(req)).await;
+        let response = chat_completions(ContentGenerator::new(), Json(req)).await;
         assert_eq!(response.status(), StatusCode::OK);
     }
 }
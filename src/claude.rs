@@ -6,18 +6,19 @@
 //! Endpoints:
 //! - POST /v1/messages - Non-streaming and streaming
 
+use crate::fixtures::History;
 use crate::generator::ContentGenerator;
+use crate::schema;
+use crate::timing::Events;
+use crate::tools::{ToolChoice, ToolMode};
+use crate::ProviderRequest;
 use axum::{
-    body::Body,
-    http::{header, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
-use futures::stream::{self, StreamExt};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::time::Duration;
-use tokio::time::sleep;
+use std::collections::HashMap;
 
 /// Request body for messages endpoint.
 #[derive(Debug, Deserialize)]
@@ -30,10 +31,14 @@ pub struct MessagesRequest {
     #[serde(default)]
     pub system: Option<String>,
     #[serde(default)]
+    pub stop_sequences: Option<Vec<String>>,
+    #[serde(default)]
     pub temperature: Option<f32>,
     #[serde(default)]
     pub tools: Option<Vec<Tool>>,
     #[serde(default)]
+    pub tool_choice: Option<ToolChoiceParam>,
+    #[serde(default)]
     pub thinking: Option<ThinkingConfig>,
 }
 
@@ -44,6 +49,16 @@ pub struct Message {
     pub content: MessageContent,
 }
 
+impl Message {
+    /// Content blocks of the message (empty for plain text content).
+    fn blocks(&self) -> &[ContentBlock] {
+        match &self.content {
+            MessageContent::Blocks(blocks) => blocks,
+            MessageContent::Text(_) => &[],
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(untagged)]
 pub enum MessageContent {
@@ -65,7 +80,8 @@ pub enum ContentBlock {
     #[serde(rename = "tool_result")]
     ToolResult {
         tool_use_id: String,
-        content: String,
+        #[serde(default)]
+        content: Option<Value>,
     },
     #[serde(rename = "thinking")]
     Thinking { thinking: String, signature: String },
@@ -80,6 +96,17 @@ pub struct Tool {
     pub input_schema: Option<Value>,
 }
 
+/// `tool_choice`: `auto`, `any`, `tool` (with `name`) or `none`.
+#[derive(Debug, Deserialize)]
+pub struct ToolChoiceParam {
+    #[serde(rename = "type")]
+    pub choice_type: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub disable_parallel_tool_use: bool,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct ThinkingConfig {
     #[serde(rename = "type")]
@@ -112,8 +139,13 @@ pub enum ResponseContent {
         name: String,
         input: Value,
     },
+    /// `signature` is left out when the thinking is cut off at `max_tokens`.
     #[serde(rename = "thinking")]
-    Thinking { thinking: String, signature: String },
+    Thinking {
+        thinking: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        signature: Option<String>,
+    },
 }
 
 #[derive(Debug, Serialize, Clone)]
@@ -127,9 +159,13 @@ pub struct Usage {
 }
 
 /// Main handler for POST /v1/messages
-pub async fn messages(Json(req): Json<MessagesRequest>) -> Response {
-    let gen = ContentGenerator::new();
-    let wants_tools = req.tools.is_some() && should_call_tool(&req);
+pub async fn messages(mut gen: ContentGenerator, Json(req): Json<MessagesRequest>) -> Response {
+    let wants_tools = gen.scripted_tool_use().unwrap_or_else(|| {
+        !req.tool_names().is_empty()
+            && req
+                .tool_choice()
+                .should_call(|| should_call_tool(&req, &mut gen))
+    });
     let wants_thinking = req.thinking.is_some();
 
     if req.stream {
@@ -139,26 +175,117 @@ pub async fn messages(Json(req): Json<MessagesRequest>) -> Response {
     }
 }
 
-/// Decide if we should generate a tool call response.
-fn should_call_tool(req: &MessagesRequest) -> bool {
-    if let Some(last) = req.messages.last() {
-        let text = match &last.content {
-            MessageContent::Text(t) => Some(t.as_str()),
-            MessageContent::Blocks(blocks) => blocks.iter().find_map(|b| match b {
-                ContentBlock::Text { text } => Some(text.as_str()),
-                _ => None,
-            }),
+impl MessagesRequest {
+    /// Custom sequences that end generation.
+    pub fn stop_sequences(&self) -> &[String] {
+        self.stop_sequences.as_deref().unwrap_or_default()
+    }
+}
+
+impl ProviderRequest for MessagesRequest {
+    /// Estimated prompt tokens, as reported in `usage.input_tokens`.
+    fn input_tokens(&self) -> u32 {
+        count_input_tokens(self)
+    }
+
+    /// Requested output token limit.
+    fn max_output_tokens(&self) -> Option<u32> {
+        Some(self.max_tokens)
+    }
+
+    /// Tool calling constraints from `tool_choice`.
+    fn tool_choice(&self) -> ToolChoice {
+        let Some(param) = &self.tool_choice else {
+            return ToolChoice::default();
         };
-        if let Some(text) = text {
-            let lower = text.to_lowercase();
-            return lower.contains("weather")
-                || lower.contains("search")
-                || lower.contains("calculate")
-                || lower.contains("what is")
-                || lower.contains("find");
-        }
+        let mut choice = match (param.choice_type.as_str(), &param.name) {
+            ("none", _) => ToolChoice::new(ToolMode::None),
+            ("any", _) => ToolChoice::new(ToolMode::Required),
+            ("tool", Some(name)) => ToolChoice::function(name),
+            // No declared tool has an empty name, so the choice is rejected.
+            ("tool", None) => ToolChoice::function(""),
+            _ => ToolChoice::default(),
+        };
+        choice.parallel = !param.disable_parallel_tool_use;
+        choice
+    }
+
+    /// Names of the declared tools.
+    fn tool_names(&self) -> Vec<&str> {
+        self.tools
+            .iter()
+            .flatten()
+            .map(|t| t.name.as_str())
+            .collect()
+    }
+
+    /// Text of the most recent user message.
+    fn last_user_text(&self) -> Option<&str> {
+        self.messages
+            .iter()
+            .rev()
+            .filter(|m| m.role == "user")
+            .find_map(|m| match &m.content {
+                MessageContent::Text(t) => Some(t.as_str()),
+                MessageContent::Blocks(blocks) => blocks.iter().find_map(|b| match b {
+                    ContentBlock::Text { text } => Some(text.as_str()),
+                    _ => None,
+                }),
+            })
+    }
+
+    /// Turn number and pending tool results, from `tool_use` / `tool_result` blocks.
+    fn history(&self) -> History {
+        let tool_names: HashMap<&str, &str> = self
+            .messages
+            .iter()
+            .flat_map(Message::blocks)
+            .filter_map(|b| match b {
+                ContentBlock::ToolUse { id, name, .. } => Some((id.as_str(), name.as_str())),
+                _ => None,
+            })
+            .collect();
+
+        History::from_entries(self.messages.iter().flat_map(|m| {
+            if m.role == "assistant" {
+                return vec![(true, None)];
+            }
+            let results: Vec<_> = m
+                .blocks()
+                .iter()
+                .filter_map(|b| match b {
+                    ContentBlock::ToolResult { tool_use_id, .. } => {
+                        let name = tool_names.get(tool_use_id.as_str()).unwrap_or(&"");
+                        Some((false, Some((*name).to_string())))
+                    }
+                    _ => None,
+                })
+                .collect();
+            if results.is_empty() {
+                vec![(false, None)]
+            } else {
+                results
+            }
+        }))
     }
-    false
+}
+
+/// Decide if we should generate a tool call response, from the last message.
+fn should_call_tool(req: &MessagesRequest, gen: &mut ContentGenerator) -> bool {
+    let text = req.messages.last().and_then(|last| match &last.content {
+        MessageContent::Text(t) => Some(t.as_str()),
+        MessageContent::Blocks(blocks) => blocks.iter().find_map(|b| match b {
+            ContentBlock::Text { text } => Some(text.as_str()),
+            _ => None,
+        }),
+    });
+    let tool_texts = req
+        .tools
+        .iter()
+        .flatten()
+        .flat_map(|t| [Some(t.name.as_str()), t.description.as_deref()])
+        .flatten();
+    gen.triggers_tool_call(text, tool_texts)
 }
 
 /// Generate a fake message ID.
@@ -227,36 +354,54 @@ fn non_stream_response(
 
     let mut content = Vec::new();
     let mut output_tokens = 0u32;
+    let mut truncated = false;
+    let mut stop_sequence = None;
 
     // Add thinking block if requested
     if wants_thinking {
-        let thinking_text = gen.paragraph();
+        let mut thinking_text = gen.paragraph();
+        truncated = ContentGenerator::truncate_to_tokens(&mut thinking_text, req.max_tokens);
         output_tokens += ContentGenerator::estimate_tokens(&thinking_text);
+        let signature = generate_signature(&mut gen);
         content.push(ResponseContent::Thinking {
             thinking: thinking_text,
-            signature: generate_signature(&mut gen),
+            signature: (!truncated).then_some(signature),
         });
     }
 
-    let stop_reason = if wants_tools {
-        let tool = req.tools.as_ref().and_then(|t| t.first());
-        let tool_name = tool
-            .map(|t| t.name.clone())
-            .unwrap_or_else(|| "unknown".to_string());
-        let arg_value = extract_argument(&req);
-
-        output_tokens += 50; // Approximate tool call tokens
-        content.push(ResponseContent::ToolUse {
-            id: generate_tool_use_id(&mut gen),
-            name: tool_name,
-            input: json!({ "location": arg_value }),
-        });
-        "tool_use"
+    let stop_reason = if truncated {
+        "max_tokens"
+    } else if wants_tools {
+        let mut stop_reason = "tool_use";
+        for (tool_name, input) in tool_calls(&req, &mut gen) {
+            let tokens = ContentGenerator::estimate_tokens(&gen.encode_arguments(&input));
+            let cut = output_tokens + tokens > req.max_tokens;
+            output_tokens = (output_tokens + tokens).min(req.max_tokens);
+            content.push(ResponseContent::ToolUse {
+                id: generate_tool_use_id(&mut gen),
+                name: tool_name,
+                // A call cut off at `max_tokens` comes back incomplete.
+                input: if cut { json!({}) } else { input },
+            });
+            if cut {
+                stop_reason = "max_tokens";
+                break;
+            }
+        }
+        stop_reason
     } else {
-        let text = gen.paragraph();
+        let (mut text, stop) = gen.reply_text(ContentGenerator::paragraph, req.stop_sequences());
+        truncated = ContentGenerator::truncate_to_tokens(&mut text, req.max_tokens - output_tokens);
         output_tokens += ContentGenerator::estimate_tokens(&text);
         content.push(ResponseContent::Text { text });
-        "end_turn"
+        if truncated {
+            "max_tokens"
+        } else if stop.is_some() {
+            stop_sequence = stop;
+            "stop_sequence"
+        } else {
+            "end_turn"
+        }
     };
 
     let response = MessagesResponse {
@@ -266,7 +411,7 @@ fn non_stream_response(
         model: req.model,
         content,
         stop_reason: stop_reason.to_string(),
-        stop_sequence: None,
+        stop_sequence,
         usage: Usage {
             input_tokens,
             output_tokens,
@@ -289,9 +434,11 @@ async fn stream_response(
     let model = req.model.clone();
     let input_tokens = count_input_tokens(&req);
 
-    let mut events: Vec<String> = Vec::new();
+    let mut events = Events::new();
     let mut output_tokens = 0u32;
     let mut content_index = 0u32;
+    let mut truncated = false;
+    let mut stop_sequence = None;
 
     // message_start
     events.push(format!(
@@ -320,7 +467,6 @@ async fn stream_response(
     if wants_thinking {
         let thinking_text = gen.paragraph();
         let signature = generate_signature(&mut gen);
-        output_tokens += ContentGenerator::estimate_tokens(&thinking_text);
 
         // content_block_start for thinking
         events.push(format!(
@@ -334,27 +480,36 @@ async fn stream_response(
 
         // Stream thinking in chunks
         let words: Vec<&str> = thinking_text.split_whitespace().collect();
-        for chunk in words.chunks(3) {
+        let mut chunks: Vec<String> = words.chunks(3).map(|c| c.join(" ") + " ").collect();
+        truncated = ContentGenerator::limit_chunks(&mut chunks, Some(req.max_tokens));
+        output_tokens += ContentGenerator::estimate_tokens(&chunks.concat());
+        for thinking in chunks {
+            let tokens = ContentGenerator::estimate_tokens(&thinking);
+            events.push_tokens(
+                format!(
+                    "event: content_block_delta\ndata: {}\n\n",
+                    json!({
+                        "type": "content_block_delta",
+                        "index": content_index,
+                        "delta": { "type": "thinking_delta", "thinking": thinking }
+                    })
+                ),
+                tokens,
+            );
+        }
+
+        // Signature delta, unless the thinking was cut off
+        if !truncated {
             events.push(format!(
                 "event: content_block_delta\ndata: {}\n\n",
                 json!({
                     "type": "content_block_delta",
                     "index": content_index,
-                    "delta": { "type": "thinking_delta", "thinking": chunk.join(" ") + " " }
+                    "delta": { "type": "signature_delta", "signature": signature }
                 })
             ));
         }
 
-        // Signature delta
-        events.push(format!(
-            "event: content_block_delta\ndata: {}\n\n",
-            json!({
-                "type": "content_block_delta",
-                "index": content_index,
-                "delta": { "type": "signature_delta", "signature": signature }
-            })
-        ));
-
         events.push(format!(
             "event: content_block_stop\ndata: {}\n\n",
             json!({ "type": "content_block_stop", "index": content_index })
@@ -363,49 +518,66 @@ async fn stream_response(
         content_index += 1;
     }
 
-    let stop_reason = if wants_tools {
-        let tool = req.tools.as_ref().and_then(|t| t.first());
-        let tool_name = tool
-            .map(|t| t.name.clone())
-            .unwrap_or_else(|| "unknown".to_string());
-        let arg_value = extract_argument(&req);
-        let tool_id = generate_tool_use_id(&mut gen);
-        output_tokens += 50;
+    let stop_reason = if truncated {
+        "max_tokens"
+    } else if wants_tools {
+        let mut stop_reason = "tool_use";
+        for (tool_name, input) in tool_calls(&req, &mut gen) {
+            let tool_id = generate_tool_use_id(&mut gen);
 
-        // content_block_start for tool_use
-        events.push(format!(
-            "event: content_block_start\ndata: {}\n\n",
-            json!({
-                "type": "content_block_start",
-                "index": content_index,
-                "content_block": {
-                    "type": "tool_use",
-                    "id": tool_id,
-                    "name": tool_name,
-                    "input": {}
-                }
-            })
-        ));
+            // content_block_start for tool_use
+            events.push(format!(
+                "event: content_block_start\ndata: {}\n\n",
+                json!({
+                    "type": "content_block_start",
+                    "index": content_index,
+                    "content_block": {
+                        "type": "tool_use",
+                        "id": tool_id,
+                        "name": tool_name,
+                        "input": {}
+                    }
+                })
+            ));
 
-        // input_json_delta
-        events.push(format!(
-            "event: content_block_delta\ndata: {}\n\n",
-            json!({
-                "type": "content_block_delta",
-                "index": content_index,
-                "delta": {
-                    "type": "input_json_delta",
-                    "partial_json": json!({ "location": arg_value }).to_string()
-                }
-            })
-        ));
+            // input_json_delta for each fragment of the input JSON, cut
+            // off at `max_tokens`
+            let input_json = gen.encode_arguments(&input);
+            let mut deltas = gen.argument_deltas(&input_json);
+            let cut =
+                ContentGenerator::limit_chunks(&mut deltas, Some(req.max_tokens - output_tokens));
+            output_tokens += ContentGenerator::estimate_tokens(&deltas.concat());
+            for partial_json in deltas {
+                let tokens = ContentGenerator::estimate_tokens(&partial_json);
+                events.push_tokens(
+                    format!(
+                        "event: content_block_delta\ndata: {}\n\n",
+                        json!({
+                            "type": "content_block_delta",
+                            "index": content_index,
+                            "delta": {
+                                "type": "input_json_delta",
+                                "partial_json": partial_json
+                            }
+                        })
+                    ),
+                    tokens,
+                );
+            }
 
-        events.push(format!(
-            "event: content_block_stop\ndata: {}\n\n",
-            json!({ "type": "content_block_stop", "index": content_index })
-        ));
+            events.push(format!(
+                "event: content_block_stop\ndata: {}\n\n",
+                json!({ "type": "content_block_stop", "index": content_index })
+            ));
+
+            content_index += 1;
+            if cut {
+                stop_reason = "max_tokens";
+                break;
+            }
+        }
 
-        "tool_use"
+        stop_reason
     } else {
         // Text content
         events.push(format!(
@@ -417,21 +589,25 @@ async fn stream_response(
             })
         ));
 
-        let max_tokens = req.max_tokens.min(100) as usize;
-        let chunks = gen.stream_chunks(max_tokens);
-        for (i, chunk) in chunks.iter().enumerate() {
-            let prefix = if i > 0 { " " } else { "" };
-            let text = format!("{prefix}{chunk}");
-            output_tokens += ContentGenerator::estimate_tokens(&text);
-
-            events.push(format!(
-                "event: content_block_delta\ndata: {}\n\n",
-                json!({
-                    "type": "content_block_delta",
-                    "index": content_index,
-                    "delta": { "type": "text_delta", "text": text }
-                })
-            ));
+        let (text, stop) = gen.reply_text(ContentGenerator::paragraph, req.stop_sequences());
+        let mut chunks = gen.chunk_text(&text);
+        truncated =
+            ContentGenerator::limit_chunks(&mut chunks, Some(req.max_tokens - output_tokens));
+        output_tokens += ContentGenerator::estimate_tokens(&chunks.concat());
+        for text in chunks {
+            let tokens = ContentGenerator::estimate_tokens(&text);
+
+            events.push_tokens(
+                format!(
+                    "event: content_block_delta\ndata: {}\n\n",
+                    json!({
+                        "type": "content_block_delta",
+                        "index": content_index,
+                        "delta": { "type": "text_delta", "text": text }
+                    })
+                ),
+                tokens,
+            );
         }
 
         events.push(format!(
@@ -439,7 +615,14 @@ async fn stream_response(
             json!({ "type": "content_block_stop", "index": content_index })
         ));
 
-        "end_turn"
+        if truncated {
+            "max_tokens"
+        } else if stop.is_some() {
+            stop_sequence = stop;
+            "stop_sequence"
+        } else {
+            "end_turn"
+        }
     };
 
     // message_delta with stop_reason and usage
@@ -447,7 +630,7 @@ async fn stream_response(
         "event: message_delta\ndata: {}\n\n",
         json!({
             "type": "message_delta",
-            "delta": { "stop_reason": stop_reason, "stop_sequence": null },
+            "delta": { "stop_reason": stop_reason, "stop_sequence": stop_sequence },
             "usage": {
                 "input_tokens": input_tokens,
                 "cache_creation_input_tokens": 0,
@@ -460,23 +643,40 @@ async fn stream_response(
     // message_stop
     events.push("event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n".to_string());
 
-    // Build the stream with delays
-    let stream = stream::iter(events)
-        .then(|event| async move {
-            sleep(Duration::from_millis(15)).await;
-            event
-        })
-        .map(Ok::<_, std::convert::Infallible>);
-
-    let body = Body::from_stream(stream);
+    gen.paced_response(events)
+}
 
-    Response::builder()
-        .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, "text/event-stream")
-        .header(header::CACHE_CONTROL, "no-cache")
-        .header(header::CONNECTION, "keep-alive")
-        .body(body)
-        .unwrap()
+/// Pick the tool names and inputs for a tool use reply, preferring scripted
+/// calls. Generated calls go to the tools `tool_choice` allows in order,
+/// wrapping around when there are more calls than tools.
+fn tool_calls(req: &MessagesRequest, gen: &mut ContentGenerator) -> Vec<(String, Value)> {
+    if let Some(calls) = gen.take_scripted_tool_calls() {
+        return calls
+            .into_iter()
+            .map(|call| (call.name, call.arguments))
+            .collect();
+    }
+    let argument = extract_argument(req);
+    let choice = req.tool_choice();
+    let tools: Vec<&Tool> = req
+        .tools
+        .iter()
+        .flatten()
+        .filter(|tool| choice.allows(&tool.name))
+        .collect();
+    let count = gen.tool_call_count(choice.parallel);
+    tools
+        .iter()
+        .cycle()
+        .take(count)
+        .map(|tool| {
+            let input = match &tool.input_schema {
+                Some(input_schema) => schema::arguments(input_schema, &argument, gen),
+                None => json!({ "location": argument }),
+            };
+            (tool.name.clone(), input)
+        })
+        .collect()
 }
 
 /// Extract an argument value from the user message.
@@ -505,6 +705,7 @@ fn extract_argument(req: &MessagesRequest) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use axum::http::StatusCode;
 
     #[test]
     fn test_deserialize_text_message() {
@@ -614,7 +815,7 @@ mod tests {
             content: vec![
                 ResponseContent::Thinking {
                     thinking: "Let me think...".to_string(),
-                    signature: "EtUB...==".to_string(),
+                    signature: Some("EtUB...==".to_string()),
                 },
                 ResponseContent::Text {
                     text: "The answer is 4.".to_string(),
@@ -646,16 +847,18 @@ mod tests {
             max_tokens: 100,
             stream: false,
             system: None,
+            stop_sequences: None,
             temperature: None,
             tools: Some(vec![Tool {
                 name: "get_weather".to_string(),
                 description: None,
                 input_schema: None,
             }]),
+            tool_choice: None,
             thinking: None,
         };
 
-        assert!(should_call_tool(&req));
+        assert!(should_call_tool(&req, &mut ContentGenerator::new()));
     }
 
     #[test]
@@ -669,12 +872,40 @@ mod tests {
             max_tokens: 100,
             stream: false,
             system: None,
+            stop_sequences: None,
             temperature: None,
             tools: Some(vec![]),
+            tool_choice: None,
             thinking: None,
         };
 
-        assert!(!should_call_tool(&req));
+        assert!(!should_call_tool(&req, &mut ContentGenerator::new()));
+    }
+
+    #[test]
+    fn test_history_after_tool_result() {
+        let json = r#"{
+            "model": "claude",
+            "max_tokens": 100,
+            "messages": [
+                {"role": "user", "content": "What is the weather in Tokyo?"},
+                {"role": "assistant", "content": [
+                    {"type": "text", "text": "Let me check."},
+                    {"type": "tool_use", "id": "toolu_1", "name": "get_weather", "input": {}}
+                ]},
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "toolu_1",
+                     "content": [{"type": "text", "text": "Sunny"}]}
+                ]}
+            ]
+        }"#;
+        let req: MessagesRequest = serde_json::from_str(json).unwrap();
+
+        let history = req.history();
+        assert_eq!(history.turn, 2);
+        assert!(history.tool_result);
+        assert_eq!(history.tool_names, vec!["get_weather".to_string()]);
+        assert_eq!(req.last_user_text(), Some("What is the weather in Tokyo?"));
     }
 
     #[test]
@@ -701,8 +932,10 @@ mod tests {
             max_tokens: 100,
             stream: false,
             system: None,
+            stop_sequences: None,
             temperature: None,
             tools: None,
+            tool_choice: None,
             thinking: None,
         };
 
@@ -721,8 +954,10 @@ mod tests {
             max_tokens: 100,
             stream: false,
             system: Some("You are helpful.".to_string()),
+            stop_sequences: None,
             temperature: None,
             tools: None,
+            tool_choice: None,
             thinking: None,
         };
 
@@ -762,12 +997,14 @@ mod tests {
             max_tokens: 100,
             stream: false,
             system: None,
+            stop_sequences: None,
             temperature: None,
             tools: None,
+            tool_choice: None,
             thinking: None,
         };
 
-        let response = messages(Json(req)).await;
+        let response = messages(ContentGenerator::new(), Json(req)).await;
         assert_eq!(response.status(), StatusCode::OK);
     }
 
@@ -782,12 +1019,14 @@ mod tests {
             max_tokens: 100,
             stream: true,
             system: None,
+            stop_sequences: None,
             temperature: None,
             tools: None,
+            tool_choice: None,
             thinking: None,
         };
 
-        let response = messages(Json(req)).await;
+        let response = messages(ContentGenerator::new(), Json(req)).await;
         assert_eq!(response.status(), StatusCode::OK);
         assert_eq!(
             response.headers().get("content-type").unwrap(),
@@ -806,15 +1045,17 @@ mod tests {
             max_tokens: 2000,
             stream: false,
             system: None,
+            stop_sequences: None,
             temperature: None,
             tools: None,
+            tool_choice: None,
             thinking: Some(ThinkingConfig {
                 thinking_type: "enabled".to_string(),
                 budget_tokens: 1024,
             }),
         };
 
-        let response = messages(Json(req)).await;
+        let response = messages(ContentGenerator::new(), Json(req)).await;
         assert_eq!(response.status(), StatusCode::OK);
     }
 
@@ -829,16 +1070,18 @@ mod tests {
             max_tokens: 100,
             stream: false,
             system: None,
+            stop_sequences: None,
             temperature: None,
             tools: Some(vec![Tool {
                 name: "get_weather".to_string(),
                 description: Some("Get weather".to_string()),
                 input_schema: None,
             }]),
+            tool_choice: None,
             thinking: None,
         };
 
-        let response = messages(Json(req)).await;
+        let response = messages(ContentGenerator::new(), Json(req)).await;
         assert_eq!(response.status(), StatusCode::OK);
     }
 }
@@ -1,12 +1,25 @@
 //! Configuration management for TokenIpsum.
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::Path;
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::time::{Duration, Instant};
+
+use crate::errors::Provider;
+use crate::fixtures::{Fixtures, FixturesConfig};
+use crate::generator::{Clock, ContentGenerator, FixedClock, SystemClock};
+use crate::journal::{Journal, JournalConfig, JournalEntry, JournalFilter};
+use crate::proxy::{Proxy, ProxyConfig};
+use crate::ratelimit::{Cost, LimitKey, Limits, RateLimitStatus, RateLimiter};
+use crate::sse::{StreamFault, StreamFaultConfig, StreamFaultKind};
+use crate::timeout::{Timeout, TimeoutConfig, TimeoutMode};
+use crate::timing::{TimingConfig, TimingModel};
+use crate::tools::{ArgumentStreaming, ToolTrigger};
 
 /// Main configuration structure.
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct Config {
     pub server: ServerConfig,
@@ -15,31 +28,73 @@ pub struct Config {
     pub auth: AuthConfig,
     pub providers: ProviderConfig,
     pub content: ContentConfig,
+    pub fixtures: FixturesConfig,
+    pub proxy: ProxyConfig,
+    pub journal: JournalConfig,
+    pub stream_faults: StreamFaultConfig,
+    pub timeouts: TimeoutConfig,
+    pub timing: TimingConfig,
+    pub tool_trigger: ToolTrigger,
+    pub argument_streaming: ArgumentStreaming,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct ServerConfig {
     pub port: u16,
     pub latency_ms: u64,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct RateLimitConfig {
+    /// Enforce `requests_per_minute` and `tokens_per_minute`.
     pub enabled: bool,
+    /// Requests per minute for each API key, provider and model (0 = unlimited).
     pub requests_per_minute: u32,
+    /// Input plus requested output tokens per minute (0 = unlimited).
+    pub tokens_per_minute: u64,
+    /// Estimated prompt tokens per minute (0 = unlimited).
+    pub input_tokens_per_minute: u64,
+    /// Requested output tokens (`max_tokens`, or a default reply's length)
+    /// per minute (0 = unlimited).
+    pub output_tokens_per_minute: u64,
     pub fail_after_requests: u64,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct ErrorConfig {
     pub error_rate: f32,
     pub force_error: ForceError,
+    /// Errors returned for particular requests or time windows.
+    pub schedule: Vec<ScheduledError>,
 }
 
-#[derive(Debug, Clone, Deserialize, Default, PartialEq)]
+/// An error returned for the requests a rule selects. Requests are numbered
+/// from 1 and time is measured from startup, both restarting on `/_admin/reset`.
+/// All conditions given must hold.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledError {
+    pub error: ErrorType,
+    /// Skip this many requests before the rule applies.
+    #[serde(default)]
+    pub after_requests: u64,
+    /// Number of requests the rule fails, e.g. a burst (default: unlimited).
+    #[serde(default)]
+    pub count: Option<u64>,
+    /// Fail only every nth request, counted from `after_requests` (0 = every request).
+    #[serde(default)]
+    pub every: u64,
+    /// Start of the time window, in seconds.
+    #[serde(default)]
+    pub from_secs: Option<f64>,
+    /// End of the time window, in seconds.
+    #[serde(default)]
+    pub until_secs: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
 #[serde(rename_all = "snake_case")]
 pub enum ForceError {
     #[default]
@@ -48,16 +103,48 @@ pub enum ForceError {
     RateLimit,
     ServerError,
     Timeout,
+    InvalidRequest,
+    PermissionDenied,
+    NotFound,
+    RequestTooLarge,
+    Overloaded,
+    InsufficientQuota,
+    FailedPrecondition,
+    Unavailable,
+    ContextLengthExceeded,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct AuthConfig {
     pub require_auth: bool,
     pub valid_keys: Vec<String>,
+    /// Keys with their own policy. These are valid keys as well.
+    pub keys: BTreeMap<String, KeyPolicy>,
+}
+
+/// What an API key may do and how requests made with it behave.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeyPolicy {
+    /// Providers the key works with (empty = all). Other providers treat it as invalid.
+    pub providers: Vec<Provider>,
+    /// Models the key may use (empty = all).
+    pub models: Vec<String>,
+    /// Limits for this key, overriding `[rate_limit]` and applying even when it is disabled.
+    pub requests_per_minute: Option<u32>,
+    pub tokens_per_minute: Option<u64>,
+    pub input_tokens_per_minute: Option<u64>,
+    pub output_tokens_per_minute: Option<u64>,
+    /// Error returned for every request made with the key.
+    pub force_error: ForceError,
+    /// Latency added on top of `server.latency_ms`.
+    pub latency_ms: u64,
+    /// Reject requests with the provider's billing / quota error.
+    pub out_of_credits: bool,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct ProviderConfig {
     pub cerebras: bool,
@@ -66,11 +153,18 @@ pub struct ProviderConfig {
     pub openai: bool,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct ContentConfig {
     pub deterministic: bool,
     pub seed: u64,
+    /// Unix timestamp reported in responses when `deterministic` is set.
+    pub fixed_timestamp: u64,
+    /// Chance (0.0 - 1.0) that generated text ends at one of the request's
+    /// stop sequences.
+    pub stop_sequence_rate: f64,
+    /// Number of parallel calls in a generated tool call reply.
+    pub parallel_tool_calls: usize,
 }
 
 impl Default for Config {
@@ -82,6 +176,14 @@ impl Default for Config {
             auth: AuthConfig::default(),
             providers: ProviderConfig::default(),
             content: ContentConfig::default(),
+            fixtures: FixturesConfig::default(),
+            proxy: ProxyConfig::default(),
+            journal: JournalConfig::default(),
+            stream_faults: StreamFaultConfig::default(),
+            timeouts: TimeoutConfig::default(),
+            timing: TimingConfig::default(),
+            tool_trigger: ToolTrigger::default(),
+            argument_streaming: ArgumentStreaming::default(),
         }
     }
 }
@@ -100,6 +202,9 @@ impl Default for RateLimitConfig {
         Self {
             enabled: false,
             requests_per_minute: 60,
+            tokens_per_minute: 0,
+            input_tokens_per_minute: 0,
+            output_tokens_per_minute: 0,
             fail_after_requests: 0,
         }
     }
@@ -110,6 +215,7 @@ impl Default for ErrorConfig {
         Self {
             error_rate: 0.0,
             force_error: ForceError::None,
+            schedule: Vec::new(),
         }
     }
 }
@@ -119,6 +225,7 @@ impl Default for AuthConfig {
         Self {
             require_auth: false,
             valid_keys: vec![],
+            keys: BTreeMap::new(),
         }
     }
 }
@@ -139,6 +246,31 @@ impl Default for ContentConfig {
         Self {
             deterministic: false,
             seed: 42,
+            fixed_timestamp: 1_700_000_000,
+            stop_sequence_rate: 0.0,
+            parallel_tool_calls: 1,
+        }
+    }
+}
+
+impl ProviderConfig {
+    /// Whether routes for `provider` are served.
+    pub fn enabled(&self, provider: Provider) -> bool {
+        match provider {
+            Provider::Cerebras => self.cerebras,
+            Provider::Gemini => self.gemini,
+            Provider::Claude => self.claude,
+            Provider::OpenAI => self.openai,
+        }
+    }
+
+    /// Enable or disable routes for `provider`.
+    pub fn set_enabled(&mut self, provider: Provider, enabled: bool) {
+        match provider {
+            Provider::Cerebras => self.cerebras = enabled,
+            Provider::Gemini => self.gemini = enabled,
+            Provider::Claude => self.claude = enabled,
+            Provider::OpenAI => self.openai = enabled,
         }
     }
 }
@@ -166,15 +298,34 @@ impl Config {
 }
 
 /// Runtime state for tracking requests and errors.
+///
+/// The config can be replaced while the server is running (see the admin API),
+/// so it sits behind a lock; fixtures are reloaded whenever it changes.
 #[derive(Debug)]
 pub struct RuntimeState {
-    pub config: Config,
+    config: RwLock<Config>,
+    fixtures: RwLock<Fixtures>,
+    pub proxy: Proxy,
+    pub journal: Journal,
+    pub rate_limiter: RateLimiter,
     pub request_count: AtomicU64,
+    /// When the request count was last reset, for scheduled errors.
+    started: std::sync::Mutex<Instant>,
     rng: std::sync::Mutex<fastrand::Rng>,
+    clock: Option<Arc<dyn Clock>>,
 }
 
 impl RuntimeState {
     pub fn new(config: Config) -> Arc<Self> {
+        Self::build(config, None)
+    }
+
+    /// Create runtime state with a custom clock for response timestamps.
+    pub fn with_clock(config: Config, clock: Arc<dyn Clock>) -> Arc<Self> {
+        Self::build(config, Some(clock))
+    }
+
+    fn build(config: Config, clock: Option<Arc<dyn Clock>>) -> Arc<Self> {
         let seed = if config.content.deterministic {
             config.content.seed
         } else {
@@ -182,12 +333,135 @@ impl RuntimeState {
         };
 
         Arc::new(Self {
-            config,
+            fixtures: RwLock::new(Fixtures::load(&config.fixtures)),
+            proxy: Proxy::new(),
+            journal: Journal::new(),
+            rate_limiter: RateLimiter::new(),
+            config: RwLock::new(config),
             request_count: AtomicU64::new(0),
+            started: std::sync::Mutex::new(Instant::now()),
             rng: std::sync::Mutex::new(fastrand::Rng::with_seed(seed)),
+            clock,
         })
     }
 
+    /// The live config. Don't hold the guard across an `.await`.
+    pub fn config(&self) -> RwLockReadGuard<'_, Config> {
+        self.config.read().unwrap()
+    }
+
+    /// Replace the live config, reloading fixtures.
+    pub fn set_config(&self, config: Config) {
+        let fixtures = Fixtures::load(&config.fixtures);
+        *self.fixtures.write().unwrap() = fixtures;
+        *self.config.write().unwrap() = config;
+    }
+
+    /// Modify the live config in place, reloading fixtures if their files or
+    /// rules changed. Concurrent updates apply one after the other.
+    pub fn update_config(&self, update: impl FnOnce(&mut Config)) {
+        let mut config = self.config.write().unwrap();
+        let fixtures = serde_json::to_value(&config.fixtures).ok();
+        update(&mut config);
+        if serde_json::to_value(&config.fixtures).ok() != fixtures {
+            *self.fixtures.write().unwrap() = Fixtures::load(&config.fixtures);
+        }
+    }
+
+    /// Fixture rules loaded from the live config.
+    pub fn fixtures(&self) -> RwLockReadGuard<'_, Fixtures> {
+        self.fixtures.read().unwrap()
+    }
+
+    /// Check and count a request against the sliding-window rate limits.
+    /// Returns `None` when rate limiting is disabled.
+    pub fn check_rate_limit(&self, key: LimitKey, cost: Cost) -> Option<RateLimitStatus> {
+        let limits = {
+            let config = self.config();
+            let global = &config.rate_limit;
+            let policy = config.auth.keys.get(&key.api_key);
+            if !global.enabled && !policy.is_some_and(KeyPolicy::has_limits) {
+                return None;
+            }
+            // Key limits win; otherwise the global ones apply if enabled.
+            let pick = |own: Option<u64>, global_limit: u64| {
+                own.unwrap_or(if global.enabled { global_limit } else { 0 })
+            };
+            let policy = policy.cloned().unwrap_or_default();
+            Limits {
+                requests_per_minute: pick(
+                    policy.requests_per_minute.map(u64::from),
+                    u64::from(global.requests_per_minute),
+                ),
+                tokens_per_minute: pick(policy.tokens_per_minute, global.tokens_per_minute),
+                input_tokens_per_minute: pick(
+                    policy.input_tokens_per_minute,
+                    global.input_tokens_per_minute,
+                ),
+                output_tokens_per_minute: pick(
+                    policy.output_tokens_per_minute,
+                    global.output_tokens_per_minute,
+                ),
+            }
+        };
+        Some(
+            self.rate_limiter
+                .check(key, cost, limits, std::time::Instant::now()),
+        )
+    }
+
+    /// Journaled requests matching `filter`, oldest first.
+    pub fn requests(&self, filter: &JournalFilter) -> Vec<JournalEntry> {
+        self.journal.entries(filter)
+    }
+
+    /// Empty the request journal, returning how many entries were removed.
+    pub fn clear_requests(&self) -> usize {
+        self.journal.clear()
+    }
+
+    /// Reset the request counter, returning the previous count.
+    pub fn reset_requests(&self) -> u64 {
+        *self.started.lock().unwrap() = Instant::now();
+        self.request_count.swap(0, Ordering::SeqCst)
+    }
+
+    /// Clock for response timestamps: the custom clock if one was given,
+    /// otherwise fixed in deterministic mode and the system clock elsewise.
+    fn clock(&self, content: &ContentConfig) -> Arc<dyn Clock> {
+        match &self.clock {
+            Some(clock) => clock.clone(),
+            None if content.deterministic => Arc::new(FixedClock(content.fixed_timestamp)),
+            None => Arc::new(SystemClock),
+        }
+    }
+
+    /// Create a content generator for a single request.
+    ///
+    /// In deterministic mode the generator is seeded from the configured seed
+    /// and the request body, so identical requests yield identical responses
+    /// regardless of how many requests the server has already handled.
+    /// A `seed_override` (e.g. from a request header) makes that single
+    /// request deterministic even when the server is not.
+    pub fn generator(&self, seed_override: Option<u64>, body: &[u8]) -> ContentGenerator {
+        let config = self.config();
+        let content = &config.content;
+        let mut gen = match seed_override {
+            Some(seed) => ContentGenerator::with_seed(derive_seed(seed, body))
+                .with_clock(Arc::new(FixedClock(content.fixed_timestamp))),
+            None if content.deterministic => {
+                ContentGenerator::with_seed(derive_seed(content.seed, body))
+                    .with_clock(self.clock(content))
+            }
+            None => ContentGenerator::with_seed(fastrand::u64(..)).with_clock(self.clock(content)),
+        };
+        gen.stop_sequence_rate = content.stop_sequence_rate;
+        gen.tool_calls = content.parallel_tool_calls;
+        gen.tool_trigger = config.tool_trigger.clone();
+        gen.argument_streaming = config.argument_streaming.clone();
+        gen
+    }
+
     /// Increment request count and return current count.
     pub fn increment_requests(&self) -> u64 {
         self.request_count.fetch_add(1, Ordering::SeqCst) + 1
@@ -195,27 +469,39 @@ impl RuntimeState {
 
     /// Check if we should return an error based on config.
     pub fn should_error(&self) -> Option<ErrorType> {
+        let config = self.config();
+
         // Check forced error
-        match &self.config.errors.force_error {
-            ForceError::None => {}
-            ForceError::Unauthorized => return Some(ErrorType::Unauthorized),
-            ForceError::RateLimit => return Some(ErrorType::RateLimit),
-            ForceError::ServerError => return Some(ErrorType::ServerError),
-            ForceError::Timeout => return Some(ErrorType::Timeout),
+        if let Some(error) = config.errors.force_error.error_type() {
+            return Some(error);
+        }
+
+        // Check scheduled errors
+        if !config.errors.schedule.is_empty() {
+            let request = self.request_count.load(Ordering::SeqCst);
+            let elapsed = self.started.lock().unwrap().elapsed();
+            if let Some(rule) = config
+                .errors
+                .schedule
+                .iter()
+                .find(|rule| rule.applies(request, elapsed))
+            {
+                return Some(rule.error);
+            }
         }
 
         // Check rate limit
-        if self.config.rate_limit.fail_after_requests > 0 {
+        if config.rate_limit.fail_after_requests > 0 {
             let count = self.request_count.load(Ordering::SeqCst);
-            if count >= self.config.rate_limit.fail_after_requests {
+            if count >= config.rate_limit.fail_after_requests {
                 return Some(ErrorType::RateLimit);
             }
         }
 
         // Check random error rate
-        if self.config.errors.error_rate > 0.0 {
+        if config.errors.error_rate > 0.0 {
             let mut rng = self.rng.lock().unwrap();
-            if rng.f32() < self.config.errors.error_rate {
+            if rng.f32() < config.errors.error_rate {
                 // Random error type
                 return Some(match rng.u8(0..3) {
                     0 => ErrorType::Unauthorized,
@@ -230,28 +516,160 @@ impl RuntimeState {
 
     /// Check if API key is valid.
     pub fn is_valid_key(&self, key: Option<&str>) -> bool {
-        if !self.config.auth.require_auth {
+        let config = self.config();
+        if !config.auth.require_auth {
             return true;
         }
 
         match key {
-            Some(k) => self.config.auth.valid_keys.iter().any(|valid| valid == k),
+            Some(k) => {
+                config.auth.valid_keys.iter().any(|valid| valid == k)
+                    || config.auth.keys.contains_key(k)
+            }
             None => false,
         }
     }
 
+    /// Policy configured for an API key, if any.
+    pub fn key_policy(&self, key: &str) -> Option<KeyPolicy> {
+        self.config().auth.keys.get(key).cloned()
+    }
+
+    /// Fault to inject into a streamed response, per `[stream_faults]`.
+    pub fn stream_fault(&self) -> Option<StreamFault> {
+        let config = &self.config().stream_faults;
+        if config.fault == StreamFaultKind::None {
+            return None;
+        }
+        if config.rate < 1.0 && self.rng.lock().unwrap().f32() >= config.rate {
+            return None;
+        }
+        Some(StreamFault {
+            kind: config.fault,
+            after_events: config.after_events,
+        })
+    }
+
+    /// Timeout to apply to a request for `provider`, per `[timeouts]`.
+    pub fn timeout(&self, provider: Provider) -> Option<Timeout> {
+        let config = self.config();
+        let rule = config.timeouts.rule(provider);
+        if rule.mode == TimeoutMode::None {
+            return None;
+        }
+        if rule.rate < 1.0 && self.rng.lock().unwrap().f32() >= rule.rate {
+            return None;
+        }
+        Some(Timeout {
+            mode: rule.mode,
+            drip_interval: Duration::from_millis(rule.drip_interval_ms),
+        })
+    }
+
+    /// Streaming speed for a request, per `[timing]`.
+    pub fn timing(&self, provider: Provider, model: &str) -> TimingModel {
+        self.config().timing.model(provider, model).clone()
+    }
+
     /// Get latency to add (in ms).
     pub fn latency_ms(&self) -> u64 {
-        self.config.server.latency_ms
+        self.config().server.latency_ms
+    }
+}
+
+/// Mix a seed with request bytes using FNV-1a, which is stable across builds.
+pub(crate) fn derive_seed(seed: u64, data: &[u8]) -> u64 {
+    const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in seed.to_le_bytes().iter().chain(data) {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+impl ForceError {
+    /// The error to return, or `None` when nothing is forced.
+    pub fn error_type(&self) -> Option<ErrorType> {
+        match self {
+            ForceError::None => None,
+            ForceError::Unauthorized => Some(ErrorType::Unauthorized),
+            ForceError::RateLimit => Some(ErrorType::RateLimit),
+            ForceError::ServerError => Some(ErrorType::ServerError),
+            ForceError::Timeout => Some(ErrorType::Timeout),
+            ForceError::InvalidRequest => Some(ErrorType::InvalidRequest),
+            ForceError::PermissionDenied => Some(ErrorType::PermissionDenied),
+            ForceError::NotFound => Some(ErrorType::NotFound),
+            ForceError::RequestTooLarge => Some(ErrorType::RequestTooLarge),
+            ForceError::Overloaded => Some(ErrorType::Overloaded),
+            ForceError::InsufficientQuota => Some(ErrorType::InsufficientQuota),
+            ForceError::FailedPrecondition => Some(ErrorType::FailedPrecondition),
+            ForceError::Unavailable => Some(ErrorType::Unavailable),
+            ForceError::ContextLengthExceeded => Some(ErrorType::ContextLengthExceeded),
+        }
+    }
+}
+
+impl ScheduledError {
+    /// Whether the rule fails request number `request`, made `elapsed` after the start.
+    pub fn applies(&self, request: u64, elapsed: Duration) -> bool {
+        let Some(n) = request.checked_sub(self.after_requests).filter(|n| *n > 0) else {
+            return false;
+        };
+        let secs = elapsed.as_secs_f64();
+        self.count.is_none_or(|count| n <= count)
+            && (self.every == 0 || n % self.every == 0)
+            && self.from_secs.is_none_or(|from| secs >= from)
+            && self.until_secs.is_none_or(|until| secs < until)
+    }
+}
+
+impl KeyPolicy {
+    /// Whether the key has any limit of its own.
+    pub fn has_limits(&self) -> bool {
+        self.requests_per_minute.is_some()
+            || self.tokens_per_minute.is_some()
+            || self.input_tokens_per_minute.is_some()
+            || self.output_tokens_per_minute.is_some()
+    }
+
+    /// Whether the key may be used with `provider`.
+    pub fn allows_provider(&self, provider: Provider) -> bool {
+        self.providers.is_empty() || self.providers.contains(&provider)
+    }
+
+    /// Whether the key may be used with `model`.
+    pub fn allows_model(&self, model: &str) -> bool {
+        self.models.is_empty() || self.models.iter().any(|m| m == model)
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ErrorType {
     Unauthorized,
     RateLimit,
     ServerError,
     Timeout,
+    /// 400 for a malformed request.
+    InvalidRequest,
+    /// 403 for a key that may not use the resource.
+    PermissionDenied,
+    /// 404 for an unknown model.
+    NotFound,
+    /// 413 (400 on Gemini) for an oversized request body.
+    RequestTooLarge,
+    /// Anthropic's 529 `overloaded_error`; 503 elsewhere.
+    Overloaded,
+    /// OpenAI's `insufficient_quota`, Anthropic's `billing_error`.
+    InsufficientQuota,
+    /// Gemini's `FAILED_PRECONDITION` for an unsupported location, and the
+    /// other providers' region errors.
+    FailedPrecondition,
+    /// 503 service unavailable.
+    Unavailable,
+    /// 400 for a prompt longer than the model's context window.
+    ContextLengthExceeded,
 }
 
 #[cfg(test)]
@@ -289,6 +707,37 @@ mod tests {
         assert_eq!(config.errors.force_error, ForceError::RateLimit);
     }
 
+    #[test]
+    fn test_parse_key_policies() {
+        let toml = r#"
+            [auth]
+            require_auth = true
+            valid_keys = ["sk-plain"]
+
+            [auth.keys.sk-throttled]
+            requests_per_minute = 1
+            models = ["gpt-4o"]
+
+            [auth.keys.sk-broke]
+            providers = ["claude"]
+            out_of_credits = true
+        "#;
+
+        let config: Config = toml::from_str(toml).unwrap();
+        let state = RuntimeState::new(config);
+        assert!(state.is_valid_key(Some("sk-plain")));
+        assert!(state.is_valid_key(Some("sk-broke")));
+        assert!(!state.is_valid_key(Some("sk-other")));
+
+        let throttled = state.key_policy("sk-throttled").unwrap();
+        assert!(throttled.has_limits());
+        assert!(throttled.allows_model("gpt-4o"));
+        assert!(!throttled.allows_model("gpt-4o-mini"));
+        let broke = state.key_policy("sk-broke").unwrap();
+        assert!(broke.allows_provider(Provider::Claude));
+        assert!(!broke.allows_provider(Provider::OpenAI));
+    }
+
     #[test]
     fn test_runtime_state() {
         let config = Config::default();
@@ -298,6 +747,34 @@ mod tests {
         assert_eq!(state.increment_requests(), 2);
     }
 
+    #[test]
+    fn test_concurrent_config_updates() {
+        let state = RuntimeState::new(Config::default());
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    for _ in 0..100 {
+                        state.update_config(|config| config.rate_limit.requests_per_minute += 1);
+                    }
+                });
+            }
+        });
+        assert_eq!(state.config().rate_limit.requests_per_minute, 60 + 800);
+
+        state.update_config(|config| {
+            config.fixtures.rules = toml::from_str::<FixturesConfig>(
+                r#"
+                [[rules]]
+                match = { contains = "Hello" }
+                response = { text = "Hi" }
+                "#,
+            )
+            .unwrap()
+            .rules;
+        });
+        assert_eq!(state.fixtures().len(), 1);
+    }
+
     #[test]
     fn test_force_error() {
         let mut config = Config::default();
@@ -306,4 +783,91 @@ mod tests {
         let state = RuntimeState::new(config);
         assert!(matches!(state.should_error(), Some(ErrorType::RateLimit)));
     }
+
+    #[test]
+    fn test_error_schedule() {
+        let toml = r#"
+            [[errors.schedule]]
+            error = "overloaded"
+            count = 2
+
+            [[errors.schedule]]
+            error = "server_error"
+            every = 5
+
+            [[errors.schedule]]
+            error = "rate_limit"
+            after_requests = 100
+            count = 3
+
+            [[errors.schedule]]
+            error = "unavailable"
+            from_secs = 30
+            until_secs = 60
+        "#;
+        let config: Config = toml::from_str(toml).unwrap();
+        let [first, every, burst, window] = &config.errors.schedule[..] else {
+            panic!("expected four rules");
+        };
+        let start = Duration::ZERO;
+
+        assert!(first.applies(1, start) && first.applies(2, start));
+        assert!(!first.applies(3, start));
+        assert!(every.applies(5, start) && every.applies(10, start));
+        assert!(!every.applies(6, start));
+        assert!(!burst.applies(100, start));
+        assert!(burst.applies(101, start) && burst.applies(103, start));
+        assert!(!burst.applies(104, start));
+        assert!(!window.applies(1, Duration::from_secs(29)));
+        assert!(window.applies(1, Duration::from_secs(30)));
+        assert!(!window.applies(1, Duration::from_mins(1)));
+
+        // Rules are checked in order against the live request count.
+        let state = RuntimeState::new(config);
+        let errors: Vec<_> = (0..6)
+            .map(|_| {
+                state.increment_requests();
+                state.should_error()
+            })
+            .collect();
+        assert!(matches!(errors[0], Some(ErrorType::Overloaded)));
+        assert!(matches!(errors[1], Some(ErrorType::Overloaded)));
+        assert!(errors[2].is_none() && errors[3].is_none());
+        assert!(matches!(errors[4], Some(ErrorType::ServerError)));
+        assert!(errors[5].is_none());
+
+        state.reset_requests();
+        state.increment_requests();
+        assert!(matches!(state.should_error(), Some(ErrorType::Overloaded)));
+    }
+
+    #[test]
+    fn test_deterministic_generator() {
+        let mut config = Config::default();
+        config.content.deterministic = true;
+        let state = RuntimeState::new(config);
+
+        let mut gen1 = state.generator(None, b"{\"model\":\"a\"}");
+        let mut gen2 = state.generator(None, b"{\"model\":\"a\"}");
+        let mut gen3 = state.generator(None, b"{\"model\":\"b\"}");
+
+        let first = gen1.paragraph();
+        assert_eq!(first, gen2.paragraph());
+        assert_ne!(first, gen3.paragraph());
+        assert_eq!(gen1.now_unix(), 1_700_000_000);
+    }
+
+    #[test]
+    fn test_seed_override() {
+        let state = RuntimeState::new(Config::default());
+
+        let mut gen1 = state.generator(Some(1), b"{}");
+        let mut gen2 = state.generator(Some(1), b"{}");
+        let mut gen3 = state.generator(Some(2), b"{}");
+
+        let first = gen1.paragraph();
+        assert_eq!(first, gen2.paragraph());
+        assert_ne!(first, gen3.paragraph());
+        assert_eq!(gen1.now_unix(), 1_700_000_000);
+    }
 }
@@ -5,21 +5,45 @@ use axum::{
     response::{IntoResponse, Response},
     Json,
 };
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 
 use crate::config::ErrorType;
+use crate::ratelimit::{format_duration, LimitKind, RateLimitStatus};
+use crate::tools::ToolChoiceError;
 
-/// Generate an error response for a specific provider.
-pub fn error_response(error: ErrorType, provider: Provider) -> Response {
+/// Generate an error response for a specific provider. `model` is named in
+/// errors that mention it, when known.
+pub fn error_response(error: ErrorType, provider: Provider, model: Option<&str>) -> Response {
     match error {
         ErrorType::Unauthorized => unauthorized(provider),
-        ErrorType::RateLimit => rate_limit(provider),
+        ErrorType::RateLimit => rate_limit(provider, None),
         ErrorType::ServerError => server_error(provider),
         ErrorType::Timeout => timeout(provider),
+        ErrorType::InvalidRequest => invalid_request(provider),
+        ErrorType::PermissionDenied => permission_denied(provider),
+        ErrorType::NotFound => model_not_found(provider, model.unwrap_or(default_model(provider))),
+        ErrorType::RequestTooLarge => request_too_large(provider),
+        ErrorType::Overloaded => overloaded(provider),
+        ErrorType::InsufficientQuota => insufficient_quota(provider),
+        ErrorType::FailedPrecondition => failed_precondition(provider),
+        ErrorType::Unavailable => unavailable(provider),
+        ErrorType::ContextLengthExceeded => context_length_exceeded(provider),
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+/// Model named in errors when the request's model isn't known.
+fn default_model(provider: Provider) -> &'static str {
+    match provider {
+        Provider::Cerebras => "llama3.1-8b",
+        Provider::Gemini => "gemini-2.0-flash",
+        Provider::Claude => "claude-sonnet-4-20250514",
+        Provider::OpenAI => "gpt-4o",
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Provider {
     Cerebras,
     Gemini,
@@ -33,7 +57,7 @@ fn unauthorized(provider: Provider) -> Response {
             StatusCode::UNAUTHORIZED,
             json!({
                 "error": {
-                    "message": "Invalid API key provided. You can find your API key at https://platform.example.com/account/api-keys.",
+                    "message": "Invalid API key provided. You can find your API key at https://platform.openai.com/account/api-keys.",
                     "type": "invalid_request_error",
                     "param": null,
                     "code": "invalid_api_key"
@@ -70,7 +94,238 @@ fn unauthorized(provider: Provider) -> Response {
     (status, Json(body)).into_response()
 }
 
-fn rate_limit(provider: Provider) -> Response {
+/// Why a request failed authentication.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthFailure {
+    /// No API key where the provider expects one.
+    MissingKey,
+    /// An API key that isn't in `valid_keys`.
+    InvalidKey(String),
+}
+
+/// Authentication error exactly as the provider's API reports it.
+pub fn auth_error(provider: Provider, failure: &AuthFailure) -> Response {
+    let (status, body) = match (provider, failure) {
+        (Provider::Cerebras | Provider::OpenAI, AuthFailure::MissingKey) => (
+            StatusCode::UNAUTHORIZED,
+            json!({
+                "error": {
+                    "message": "You didn't provide an API key. You need to provide your API key in an Authorization header using Bearer auth (i.e. Authorization: Bearer YOUR_KEY).",
+                    "type": "invalid_request_error",
+                    "param": null,
+                    "code": null
+                }
+            }),
+        ),
+        (Provider::Cerebras | Provider::OpenAI, AuthFailure::InvalidKey(key)) => (
+            StatusCode::UNAUTHORIZED,
+            json!({
+                "error": {
+                    "message": format!("Incorrect API key provided: {}. You can find your API key at https://platform.openai.com/account/api-keys.", mask_key(key)),
+                    "type": "invalid_request_error",
+                    "param": null,
+                    "code": "invalid_api_key"
+                }
+            }),
+        ),
+        // Gemini rejects anonymous callers as a permission problem, and a bad
+        // key as a bad argument.
+        (Provider::Gemini, AuthFailure::MissingKey) => (
+            StatusCode::FORBIDDEN,
+            json!({
+                "error": {
+                    "code": 403,
+                    "message": "Method doesn't allow unregistered callers (callers without established identity). Please use API Key or other form of API consumer identity to call this API.",
+                    "status": "PERMISSION_DENIED"
+                }
+            }),
+        ),
+        (Provider::Gemini, AuthFailure::InvalidKey(_)) => (
+            StatusCode::BAD_REQUEST,
+            json!({
+                "error": {
+                    "code": 400,
+                    "message": "API key not valid. Please pass a valid API key.",
+                    "status": "INVALID_ARGUMENT",
+                    "details": [{
+                        "@type": "type.googleapis.com/google.rpc.ErrorInfo",
+                        "reason": "API_KEY_INVALID",
+                        "domain": "googleapis.com",
+                        "metadata": {"service": "generativelanguage.googleapis.com"}
+                    }]
+                }
+            }),
+        ),
+        (Provider::Claude, failure) => (
+            StatusCode::UNAUTHORIZED,
+            json!({
+                "type": "error",
+                "error": {
+                    "type": "authentication_error",
+                    "message": match failure {
+                        AuthFailure::MissingKey => "x-api-key header is required",
+                        AuthFailure::InvalidKey(_) => "invalid x-api-key",
+                    }
+                }
+            }),
+        ),
+    };
+
+    (status, Json(body)).into_response()
+}
+
+/// Error for an account that has run out of credits: OpenAI's
+/// `insufficient_quota`, Anthropic's `billing_error`.
+pub fn insufficient_quota(provider: Provider) -> Response {
+    let (status, body) = match provider {
+        Provider::Cerebras | Provider::OpenAI => (
+            StatusCode::TOO_MANY_REQUESTS,
+            json!({
+                "error": {
+                    "message": "You exceeded your current quota, please check your plan and billing details.",
+                    "type": "insufficient_quota",
+                    "param": null,
+                    "code": "insufficient_quota"
+                }
+            }),
+        ),
+        Provider::Gemini => (
+            StatusCode::TOO_MANY_REQUESTS,
+            json!({
+                "error": {
+                    "code": 429,
+                    "message": "You exceeded your current quota, please check your plan and billing details.",
+                    "status": "RESOURCE_EXHAUSTED"
+                }
+            }),
+        ),
+        Provider::Claude => (
+            StatusCode::PAYMENT_REQUIRED,
+            json!({
+                "type": "error",
+                "error": {
+                    "type": "billing_error",
+                    "message": "Your credit balance is too low to access the Anthropic API. Please go to Plans & Billing to upgrade or purchase credits."
+                }
+            }),
+        ),
+    };
+
+    (status, Json(body)).into_response()
+}
+
+/// 404 for a model that doesn't exist or that the key can't use.
+pub fn model_not_found(provider: Provider, model: &str) -> Response {
+    let body = match provider {
+        Provider::Cerebras | Provider::OpenAI => json!({
+            "error": {
+                "message": format!("The model `{model}` does not exist or you do not have access to it."),
+                "type": "invalid_request_error",
+                "param": null,
+                "code": "model_not_found"
+            }
+        }),
+        Provider::Gemini => json!({
+            "error": {
+                "code": 404,
+                "message": format!("models/{model} is not found for API version v1beta, or is not supported for generateContent. Call ListModels to see the list of available models and their supported methods."),
+                "status": "NOT_FOUND"
+            }
+        }),
+        Provider::Claude => json!({
+            "type": "error",
+            "error": {
+                "type": "not_found_error",
+                "message": format!("model: {model}")
+            }
+        }),
+    };
+
+    (StatusCode::NOT_FOUND, Json(body)).into_response()
+}
+
+/// 400 for an Anthropic request without the `anthropic-version` header.
+pub fn missing_anthropic_version() -> Response {
+    let body = json!({
+        "type": "error",
+        "error": {
+            "type": "invalid_request_error",
+            "message": "anthropic-version: header is required"
+        }
+    });
+    (StatusCode::BAD_REQUEST, Json(body)).into_response()
+}
+
+/// 400 for a `tool_choice` / `tool_config` naming a tool the request doesn't
+/// declare, or requiring a call without any tools.
+pub fn invalid_tool_choice(provider: Provider, error: &ToolChoiceError) -> Response {
+    let body = match (provider, error) {
+        (Provider::Cerebras | Provider::OpenAI, ToolChoiceError::Undeclared(name)) => json!({
+            "error": {
+                "message": format!("Invalid value for 'tool_choice': function '{name}' is not declared in 'tools'."),
+                "type": "invalid_request_error",
+                "param": "tool_choice",
+                "code": "invalid_value"
+            }
+        }),
+        (Provider::Cerebras | Provider::OpenAI, ToolChoiceError::NoTools) => json!({
+            "error": {
+                "message": "Invalid value for 'tool_choice': 'tool_choice' is only allowed when 'tools' are specified.",
+                "type": "invalid_request_error",
+                "param": "tool_choice",
+                "code": null
+            }
+        }),
+        (Provider::Gemini, ToolChoiceError::Undeclared(name)) => json!({
+            "error": {
+                "code": 400,
+                "message": format!("* GenerateContentRequest.tool_config.function_calling_config.allowed_function_names: function {name} is not declared in tools.\n"),
+                "status": "INVALID_ARGUMENT"
+            }
+        }),
+        (Provider::Gemini, ToolChoiceError::NoTools) => json!({
+            "error": {
+                "code": 400,
+                "message": "* GenerateContentRequest.tool_config.function_calling_config.mode: specified ANY mode without function declarations.\n",
+                "status": "INVALID_ARGUMENT"
+            }
+        }),
+        (Provider::Claude, ToolChoiceError::Undeclared(name)) => json!({
+            "type": "error",
+            "error": {
+                "type": "invalid_request_error",
+                "message": format!("tool_choice.name: Tool '{name}' not found in provided tools")
+            }
+        }),
+        (Provider::Claude, ToolChoiceError::NoTools) => json!({
+            "type": "error",
+            "error": {
+                "type": "invalid_request_error",
+                "message": "tool_choice: tool_choice may only be specified while providing tools"
+            }
+        }),
+    };
+    (StatusCode::BAD_REQUEST, Json(body)).into_response()
+}
+
+/// Hide all but the ends of a key, as OpenAI does when echoing it back.
+fn mask_key(key: &str) -> String {
+    let chars: Vec<char> = key.chars().collect();
+    if chars.len() <= 8 {
+        return "*".repeat(chars.len());
+    }
+    let head: String = chars[..3].iter().collect();
+    let tail: String = chars[chars.len() - 4..].iter().collect();
+    format!("{head}{}{tail}", "*".repeat(chars.len() - 7))
+}
+
+/// 429 response. With a limiter `status`, the body and headers describe the
+/// exceeded limit; without one, a generic rate limit error is returned.
+pub fn rate_limit(provider: Provider, status: Option<&RateLimitStatus>) -> Response {
+    if let Some(status) = status {
+        return limiter_rate_limit(provider, status);
+    }
+
     let (status, body, headers) = match provider {
         Provider::Cerebras | Provider::OpenAI => (
             StatusCode::TOO_MANY_REQUESTS,
@@ -131,6 +386,98 @@ fn rate_limit(provider: Provider) -> Response {
     response
 }
 
+fn limiter_rate_limit(provider: Provider, status: &RateLimitStatus) -> Response {
+    let kind = status.exceeded.unwrap_or(LimitKind::Requests);
+    let quota = status.exceeded_quota();
+    let limit = quota.map_or(0, |q| q.limit);
+    let requested = status.requested_amount();
+    let unit = match kind {
+        LimitKind::Requests => "requests",
+        LimitKind::Tokens => "tokens",
+        LimitKind::InputTokens => "input tokens",
+        LimitKind::OutputTokens => "output tokens",
+    };
+    // OpenAI only has request and (total) token limits.
+    let (openai_unit, abbr) = match kind {
+        LimitKind::Requests => ("requests", "RPM"),
+        _ => ("tokens", "TPM"),
+    };
+
+    let body = match provider {
+        Provider::Cerebras | Provider::OpenAI => json!({
+            "error": {
+                "message": format!(
+                    "Rate limit reached for {} on {openai_unit} per min ({abbr}): Limit {limit}, Used {}, Requested {requested}. Please try again in {}.",
+                    status.model,
+                    limit - quota.map_or(0, |q| q.remaining),
+                    format_duration(status.retry_after),
+                ),
+                "type": openai_unit,
+                "param": null,
+                "code": "rate_limit_exceeded"
+            }
+        }),
+        Provider::Gemini => {
+            let (metric, quota_id) = match kind {
+                LimitKind::Requests => (
+                    "generate_content_requests",
+                    "GenerateRequestsPerMinutePerProjectPerModel",
+                ),
+                LimitKind::Tokens | LimitKind::InputTokens => (
+                    "generate_content_input_token_count",
+                    "GenerateContentInputTokensPerModelPerMinute",
+                ),
+                LimitKind::OutputTokens => (
+                    "generate_content_output_token_count",
+                    "GenerateContentOutputTokensPerModelPerMinute",
+                ),
+            };
+            json!({
+                "error": {
+                    "code": 429,
+                    "message": format!(
+                        "You exceeded your current quota, please check your plan and billing details. Quota exceeded for metric: generativelanguage.googleapis.com/{metric}, limit: {limit}, model: {}. Please retry in {}s.",
+                        status.model,
+                        status.retry_after_secs()
+                    ),
+                    "status": "RESOURCE_EXHAUSTED",
+                    "details": [
+                        {
+                            "@type": "type.googleapis.com/google.rpc.QuotaFailure",
+                            "violations": [{
+                                "quotaMetric": format!("generativelanguage.googleapis.com/{metric}"),
+                                "quotaId": quota_id,
+                                "quotaDimensions": {"location": "global", "model": status.model},
+                                "quotaValue": limit.to_string()
+                            }]
+                        },
+                        {
+                            "@type": "type.googleapis.com/google.rpc.RetryInfo",
+                            "retryDelay": format!("{}s", status.retry_after_secs())
+                        }
+                    ]
+                }
+            })
+        }
+        Provider::Claude => json!({
+            "type": "error",
+            "error": {
+                "type": "rate_limit_error",
+                "message": format!(
+                    "This request would exceed the rate limit for your organization of {limit} {unit} per minute. Please retry after {} seconds.",
+                    status.retry_after_secs()
+                )
+            }
+        }),
+    };
+
+    let mut response = (StatusCode::TOO_MANY_REQUESTS, Json(body)).into_response();
+    let headers = response.headers_mut();
+    status.apply_headers(provider, headers);
+    headers.insert("retry-after", status.retry_after_secs().into());
+    response
+}
+
 fn server_error(provider: Provider) -> Response {
     let (status, body) = match provider {
         Provider::Cerebras | Provider::OpenAI => (
@@ -207,6 +554,255 @@ fn timeout(provider: Provider) -> Response {
     (status, Json(body)).into_response()
 }
 
+fn invalid_request(provider: Provider) -> Response {
+    let (status, body) = match provider {
+        Provider::Cerebras | Provider::OpenAI => (
+            StatusCode::BAD_REQUEST,
+            json!({
+                "error": {
+                    "message": "Invalid value for 'temperature': expected a number between 0 and 2.",
+                    "type": "invalid_request_error",
+                    "param": "temperature",
+                    "code": "invalid_value"
+                }
+            }),
+        ),
+        Provider::Gemini => (
+            StatusCode::BAD_REQUEST,
+            json!({
+                "error": {
+                    "code": 400,
+                    "message": "Request contains an invalid argument.",
+                    "status": "INVALID_ARGUMENT"
+                }
+            }),
+        ),
+        Provider::Claude => (
+            StatusCode::BAD_REQUEST,
+            json!({
+                "type": "error",
+                "error": {
+                    "type": "invalid_request_error",
+                    "message": "messages: at least one message is required"
+                }
+            }),
+        ),
+    };
+
+    (status, Json(body)).into_response()
+}
+
+fn permission_denied(provider: Provider) -> Response {
+    let body = match provider {
+        Provider::Cerebras | Provider::OpenAI => json!({
+            "error": {
+                "message": "You have insufficient permissions for this operation.",
+                "type": "invalid_request_error",
+                "param": null,
+                "code": "insufficient_permissions"
+            }
+        }),
+        Provider::Gemini => json!({
+            "error": {
+                "code": 403,
+                "message": "The caller does not have permission",
+                "status": "PERMISSION_DENIED"
+            }
+        }),
+        Provider::Claude => json!({
+            "type": "error",
+            "error": {
+                "type": "permission_error",
+                "message": "Your API key does not have permission to use the specified resource."
+            }
+        }),
+    };
+
+    (StatusCode::FORBIDDEN, Json(body)).into_response()
+}
+
+fn request_too_large(provider: Provider) -> Response {
+    let (status, body) = match provider {
+        Provider::Cerebras | Provider::OpenAI => (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            json!({
+                "error": {
+                    "message": "Request too large. The request body exceeds the maximum allowed size.",
+                    "type": "invalid_request_error",
+                    "param": null,
+                    "code": "request_too_large"
+                }
+            }),
+        ),
+        // Gemini reports oversized payloads as an invalid argument.
+        Provider::Gemini => (
+            StatusCode::BAD_REQUEST,
+            json!({
+                "error": {
+                    "code": 400,
+                    "message": "Request payload size exceeds the limit: 20971520 bytes.",
+                    "status": "INVALID_ARGUMENT"
+                }
+            }),
+        ),
+        Provider::Claude => (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            json!({
+                "type": "error",
+                "error": {
+                    "type": "request_too_large",
+                    "message": "Request exceeds the maximum allowed number of bytes."
+                }
+            }),
+        ),
+    };
+
+    (status, Json(body)).into_response()
+}
+
+fn overloaded(provider: Provider) -> Response {
+    let (status, body) = match provider {
+        Provider::Cerebras | Provider::OpenAI => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            json!({
+                "error": {
+                    "message": "The engine is currently overloaded, please try again later.",
+                    "type": "server_error",
+                    "param": null,
+                    "code": null
+                }
+            }),
+        ),
+        Provider::Gemini => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            json!({
+                "error": {
+                    "code": 503,
+                    "message": "The model is overloaded. Please try again later.",
+                    "status": "UNAVAILABLE"
+                }
+            }),
+        ),
+        Provider::Claude => (
+            // Anthropic's non-standard "overloaded" status.
+            StatusCode::from_u16(529).unwrap(),
+            json!({
+                "type": "error",
+                "error": {
+                    "type": "overloaded_error",
+                    "message": "Overloaded"
+                }
+            }),
+        ),
+    };
+
+    (status, Json(body)).into_response()
+}
+
+fn failed_precondition(provider: Provider) -> Response {
+    let (status, body) = match provider {
+        Provider::Cerebras | Provider::OpenAI => (
+            StatusCode::FORBIDDEN,
+            json!({
+                "error": {
+                    "message": "Country, region, or territory not supported",
+                    "type": "request_forbidden",
+                    "param": null,
+                    "code": "unsupported_country_region_territory"
+                }
+            }),
+        ),
+        Provider::Gemini => (
+            StatusCode::BAD_REQUEST,
+            json!({
+                "error": {
+                    "code": 400,
+                    "message": "User location is not supported for the API use.",
+                    "status": "FAILED_PRECONDITION"
+                }
+            }),
+        ),
+        Provider::Claude => (
+            StatusCode::FORBIDDEN,
+            json!({
+                "type": "error",
+                "error": {
+                    "type": "forbidden",
+                    "message": "Request not allowed"
+                }
+            }),
+        ),
+    };
+
+    (status, Json(body)).into_response()
+}
+
+fn unavailable(provider: Provider) -> Response {
+    let body = match provider {
+        Provider::Cerebras | Provider::OpenAI => json!({
+            "error": {
+                "message": "The server is overloaded or not ready yet.",
+                "type": "server_error",
+                "param": null,
+                "code": "service_unavailable"
+            }
+        }),
+        Provider::Gemini => json!({
+            "error": {
+                "code": 503,
+                "message": "The service is currently unavailable.",
+                "status": "UNAVAILABLE"
+            }
+        }),
+        Provider::Claude => json!({
+            "type": "error",
+            "error": {
+                "type": "api_error",
+                "message": "Service unavailable. Please try again later."
+            }
+        }),
+    };
+
+    (StatusCode::SERVICE_UNAVAILABLE, Json(body)).into_response()
+}
+
+fn context_length_exceeded(provider: Provider) -> Response {
+    let body = match provider {
+        Provider::Cerebras => json!({
+            "error": {
+                "message": "This model's maximum context length is 8192 tokens. However, your messages resulted in 9120 tokens. Please reduce the length of the messages.",
+                "type": "invalid_request_error",
+                "param": "messages",
+                "code": "context_length_exceeded"
+            }
+        }),
+        Provider::OpenAI => json!({
+            "error": {
+                "message": "Your input exceeds the context window of this model. Please adjust your input and try again.",
+                "type": "invalid_request_error",
+                "param": "input",
+                "code": "context_length_exceeded"
+            }
+        }),
+        Provider::Gemini => json!({
+            "error": {
+                "code": 400,
+                "message": "The input token count (1200000) exceeds the maximum number of tokens allowed (1048576).",
+                "status": "INVALID_ARGUMENT"
+            }
+        }),
+        Provider::Claude => json!({
+            "type": "error",
+            "error": {
+                "type": "invalid_request_error",
+                "message": "prompt is too long: 208310 tokens > 200000 maximum"
+            }
+        }),
+    };
+
+    (StatusCode::BAD_REQUEST, Json(body)).into_response()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -226,25 +822,88 @@ mod tests {
         assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
     }
 
+    #[test]
+    fn test_auth_error_responses() {
+        let resp = auth_error(Provider::Gemini, &AuthFailure::MissingKey);
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let resp = auth_error(Provider::Gemini, &AuthFailure::InvalidKey("bad".into()));
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let resp = auth_error(Provider::Claude, &AuthFailure::MissingKey);
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let resp = auth_error(Provider::OpenAI, &AuthFailure::InvalidKey("bad".into()));
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            missing_anthropic_version().status(),
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[test]
+    fn test_key_policy_responses() {
+        let resp = insufficient_quota(Provider::Claude);
+        assert_eq!(resp.status(), StatusCode::PAYMENT_REQUIRED);
+        let resp = insufficient_quota(Provider::OpenAI);
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        let resp = model_not_found(Provider::Gemini, "gemini-x");
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_mask_key() {
+        assert_eq!(mask_key("sk-test-1234567890"), "sk-***********7890");
+        assert_eq!(mask_key("short"), "*****");
+    }
+
     #[test]
     fn test_rate_limit_responses() {
-        let resp = rate_limit(Provider::Cerebras);
+        let resp = rate_limit(Provider::Cerebras, None);
         assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
         assert!(resp.headers().contains_key("retry-after"));
 
-        let resp = rate_limit(Provider::Gemini);
+        let resp = rate_limit(Provider::Gemini, None);
         assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
         assert!(resp.headers().contains_key("retry-after"));
 
-        let resp = rate_limit(Provider::Claude);
+        let resp = rate_limit(Provider::Claude, None);
         assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
         assert!(resp.headers().contains_key("retry-after"));
 
-        let resp = rate_limit(Provider::OpenAI);
+        let resp = rate_limit(Provider::OpenAI, None);
         assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
         assert!(resp.headers().contains_key("retry-after"));
     }
 
+    #[test]
+    fn test_limiter_rate_limit_responses() {
+        use crate::ratelimit::{Cost, LimitKey, Limits, RateLimiter};
+        use std::time::Instant;
+
+        let limiter = RateLimiter::new();
+        let limits = Limits {
+            requests_per_minute: 10,
+            tokens_per_minute: 100,
+            ..Limits::default()
+        };
+        let key = LimitKey {
+            api_key: String::new(),
+            provider: Provider::Gemini,
+            model: "gemini-2.0-flash".to_string(),
+        };
+        let cost = Cost {
+            input_tokens: 500,
+            output_tokens: 0,
+        };
+        let status = limiter.check(key, cost, limits, Instant::now());
+
+        let resp = rate_limit(Provider::Gemini, Some(&status));
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(resp.headers()["retry-after"], "60");
+
+        let resp = rate_limit(Provider::OpenAI, Some(&status));
+        assert_eq!(resp.headers()["x-ratelimit-remaining-tokens"], "100");
+        assert_eq!(resp.headers()["x-ratelimit-remaining-requests"], "10");
+    }
+
     #[test]
     fn test_server_error_responses() {
         let resp = server_error(Provider::Cerebras);
@@ -277,16 +936,68 @@ mod tests {
 
     #[test]
     fn test_error_response_dispatch() {
-        let resp = error_response(ErrorType::Unauthorized, Provider::Claude);
+        let resp = error_response(ErrorType::Unauthorized, Provider::Claude, None);
         assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
 
-        let resp = error_response(ErrorType::RateLimit, Provider::Gemini);
+        let resp = error_response(ErrorType::RateLimit, Provider::Gemini, None);
         assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
 
-        let resp = error_response(ErrorType::ServerError, Provider::OpenAI);
+        let resp = error_response(ErrorType::ServerError, Provider::OpenAI, None);
         assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
 
-        let resp = error_response(ErrorType::Timeout, Provider::Cerebras);
+        let resp = error_response(ErrorType::Timeout, Provider::Cerebras, None);
         assert_eq!(resp.status(), StatusCode::GATEWAY_TIMEOUT);
     }
+
+    #[test]
+    fn test_error_taxonomy_statuses() {
+        let cases = [
+            (ErrorType::InvalidRequest, [400, 400, 400, 400]),
+            (ErrorType::PermissionDenied, [403, 403, 403, 403]),
+            (ErrorType::NotFound, [404, 404, 404, 404]),
+            (ErrorType::RequestTooLarge, [413, 400, 413, 413]),
+            (ErrorType::Overloaded, [503, 503, 529, 503]),
+            (ErrorType::InsufficientQuota, [429, 429, 402, 429]),
+            (ErrorType::FailedPrecondition, [403, 400, 403, 403]),
+            (ErrorType::Unavailable, [503, 503, 503, 503]),
+            (ErrorType::ContextLengthExceeded, [400, 400, 400, 400]),
+        ];
+        let providers = [
+            Provider::Cerebras,
+            Provider::Gemini,
+            Provider::Claude,
+            Provider::OpenAI,
+        ];
+        for (error, statuses) in cases {
+            for (provider, status) in providers.into_iter().zip(statuses) {
+                let resp = error_response(error, provider, None);
+                assert_eq!(resp.status().as_u16(), status, "{error:?} {provider:?}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_error_taxonomy_bodies() {
+        use http_body_util::BodyExt;
+
+        async fn body(error: ErrorType, provider: Provider) -> serde_json::Value {
+            let resp = error_response(error, provider, Some("my-model"));
+            let bytes = resp.into_body().collect().await.unwrap().to_bytes();
+            serde_json::from_slice(&bytes).unwrap()
+        }
+
+        let claude = body(ErrorType::Overloaded, Provider::Claude).await;
+        assert_eq!(claude["error"]["type"], "overloaded_error");
+        let openai = body(ErrorType::InsufficientQuota, Provider::OpenAI).await;
+        assert_eq!(openai["error"]["code"], "insufficient_quota");
+        let gemini = body(ErrorType::FailedPrecondition, Provider::Gemini).await;
+        assert_eq!(gemini["error"]["status"], "FAILED_PRECONDITION");
+        let cerebras = body(ErrorType::ContextLengthExceeded, Provider::Cerebras).await;
+        assert_eq!(cerebras["error"]["code"], "context_length_exceeded");
+        let not_found = body(ErrorType::NotFound, Provider::OpenAI).await;
+        assert!(not_found["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("`my-model`"));
+    }
 }
@@ -0,0 +1,451 @@
+//! Scripted response fixtures.
+//!
+//! Fixture rules match incoming requests by provider, model, path, headers or
+//! the text of the last user message, and replace the generated lorem ipsum
+//! with a canned reply. Replies are rendered in each provider's native shape
+//! by the regular response builders.
+//!
+//! Multi-turn scripts list one reply per assistant turn. The turn is derived
+//! from the message history in the request itself, not from server state, so
+//! concurrent conversations don't interfere with each other.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use axum::http::HeaderMap;
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+
+use crate::config::ErrorType;
+use crate::errors::Provider;
+
+/// Fixture settings in the main config.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FixturesConfig {
+    /// Fixture files to load (TOML, JSON or YAML, chosen by extension).
+    pub files: Vec<String>,
+    /// Rules declared inline in the config file.
+    pub rules: Vec<Rule>,
+}
+
+/// Contents of a fixture file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct FixtureFile {
+    rules: Vec<Rule>,
+}
+
+/// A single fixture: when `matcher` matches, respond with `response`, or
+/// with the entry of `turns` for the current assistant turn.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rule {
+    #[serde(rename = "match", default)]
+    pub matcher: Matcher,
+    #[serde(default)]
+    pub response: Option<Reply>,
+    /// Replies for assistant turns 1, 2, ... of a conversation.
+    #[serde(default)]
+    pub turns: Vec<Reply>,
+}
+
+/// Conditions a request must meet for a rule to apply. Empty fields match anything.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Matcher {
+    pub provider: Option<Provider>,
+    pub model: Option<String>,
+    pub path: Option<String>,
+    /// Header values that must be present (names are case-insensitive).
+    pub headers: HashMap<String, String>,
+    /// Substring of the last user message.
+    pub contains: Option<String>,
+    /// Regular expression matched against the last user message.
+    pub regex: Option<Pattern>,
+    /// Assistant turn being generated (1 for the first reply).
+    pub turn: Option<u32>,
+    /// Whether the latest user turn carries tool results.
+    pub tool_result: Option<bool>,
+    /// Name of a tool whose result arrived in the latest user turn.
+    pub tool_result_for: Option<String>,
+}
+
+/// Compiled regular expression that deserializes from a string.
+#[derive(Debug, Clone)]
+pub struct Pattern(pub Regex);
+
+impl Serialize for Pattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.0.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        Regex::new(&source)
+            .map(Pattern)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Canned reply returned instead of generated content.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reply {
+    /// Assistant text.
+    Text(String),
+    /// A call to one of the declared tools.
+    ToolCall(ToolCall),
+    /// Several tool calls made in parallel, in order.
+    ToolCalls(Vec<ToolCall>),
+    /// A provider-formatted error response.
+    Error(ErrorType),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub name: String,
+    #[serde(default = "empty_object")]
+    pub arguments: Value,
+}
+
+fn empty_object() -> Value {
+    Value::Object(serde_json::Map::new())
+}
+
+/// The parts of a request that fixture rules can match on.
+#[derive(Debug, Clone)]
+pub struct RequestInfo<'a> {
+    pub provider: Provider,
+    pub model: &'a str,
+    pub path: &'a str,
+    pub headers: &'a HeaderMap,
+    pub last_user_text: Option<&'a str>,
+    pub history: History,
+}
+
+/// Shape of the conversation in a request.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct History {
+    /// Assistant turn being generated: previous assistant turns + 1.
+    pub turn: u32,
+    /// Whether the messages after the last assistant turn contain tool results.
+    pub tool_result: bool,
+    /// Names of the tools those results belong to, where they can be resolved.
+    pub tool_names: Vec<String>,
+}
+
+impl History {
+    /// Build a history from `(is_assistant, tool_result_name)` entries in order.
+    ///
+    /// Consecutive assistant entries (e.g. a message followed by its tool calls)
+    /// count as a single turn. A tool result entry is `Some(name)`, with an empty
+    /// name when the tool can't be resolved.
+    pub fn from_entries<I>(entries: I) -> Self
+    where
+        I: IntoIterator<Item = (bool, Option<String>)>,
+    {
+        let mut history = Self {
+            turn: 1,
+            ..Self::default()
+        };
+        let mut in_assistant = false;
+        for (is_assistant, tool_result) in entries {
+            if is_assistant {
+                if !in_assistant {
+                    history.turn += 1;
+                    history.tool_result = false;
+                    history.tool_names.clear();
+                }
+            } else if let Some(name) = tool_result {
+                history.tool_result = true;
+                if !name.is_empty() {
+                    history.tool_names.push(name);
+                }
+            }
+            in_assistant = is_assistant;
+        }
+        history
+    }
+}
+
+/// Loaded fixture rules, checked in declaration order.
+#[derive(Debug, Clone, Default)]
+pub struct Fixtures {
+    rules: Vec<Rule>,
+}
+
+impl Fixtures {
+    /// Build fixtures from inline rules followed by rules from each file.
+    pub fn load(config: &FixturesConfig) -> Self {
+        let mut rules = config.rules.clone();
+        for file in &config.files {
+            match load_file(Path::new(file)) {
+                Ok(file_rules) => rules.extend(file_rules),
+                Err(e) => tracing::warn!("Failed to load fixtures from {}: {}", file, e),
+            }
+        }
+        Self { rules }
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Find the reply of the first rule matching the request.
+    pub fn find(&self, info: &RequestInfo) -> Option<&Reply> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.matcher.matches(info))
+            .find_map(|rule| rule.reply(&info.history))
+    }
+}
+
+impl Rule {
+    /// Reply for the given history: the scripted turn if `turns` is set.
+    fn reply(&self, history: &History) -> Option<&Reply> {
+        if self.turns.is_empty() {
+            self.response.as_ref()
+        } else {
+            let index = history.turn.checked_sub(1)? as usize;
+            self.turns.get(index)
+        }
+    }
+}
+
+impl Matcher {
+    fn matches(&self, info: &RequestInfo) -> bool {
+        if self.provider.is_some_and(|p| p != info.provider) {
+            return false;
+        }
+        if self.model.as_ref().is_some_and(|m| m != info.model) {
+            return false;
+        }
+        if self.path.as_ref().is_some_and(|p| p != info.path) {
+            return false;
+        }
+        if self.turn.is_some_and(|t| t != info.history.turn) {
+            return false;
+        }
+        if self
+            .tool_result
+            .is_some_and(|t| t != info.history.tool_result)
+        {
+            return false;
+        }
+        if self
+            .tool_result_for
+            .as_ref()
+            .is_some_and(|name| !info.history.tool_names.contains(name))
+        {
+            return false;
+        }
+
+        let headers_match = self.headers.iter().all(|(name, expected)| {
+            info.headers
+                .get(name.as_str())
+                .and_then(|v| v.to_str().ok())
+                .is_some_and(|v| v == expected)
+        });
+        if !headers_match {
+            return false;
+        }
+
+        if self.contains.is_none() && self.regex.is_none() {
+            return true;
+        }
+        let Some(text) = info.last_user_text else {
+            return false;
+        };
+        self.contains
+            .as_ref()
+            .is_none_or(|c| text.contains(c.as_str()))
+            && self.regex.as_ref().is_none_or(|r| r.0.is_match(text))
+    }
+}
+
+fn load_file(path: &Path) -> Result<Vec<Rule>, String> {
+    let content = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let file: FixtureFile = match path.extension().and_then(|e| e.to_str()) {
+        Some("json") => serde_json::from_str(&content).map_err(|e| e.to_string())?,
+        // serde_yaml expects YAML tags for enums, so go through a JSON value to
+        // accept the same `response: { text: ... }` shape as the other formats.
+        Some("yaml" | "yml") => serde_yaml::from_str::<Value>(&content)
+            .map_err(|e| e.to_string())
+            .and_then(|value| serde_json::from_value(value).map_err(|e| e.to_string()))?,
+        _ => toml::from_str(&content).map_err(|e| e.to_string())?,
+    };
+    Ok(file.rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info<'a>(headers: &'a HeaderMap, text: Option<&'a str>) -> RequestInfo<'a> {
+        RequestInfo {
+            provider: Provider::Claude,
+            model: "claude-3-haiku",
+            path: "/v1/messages",
+            headers,
+            last_user_text: text,
+            history: History {
+                turn: 1,
+                ..History::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_parse_toml_rules() {
+        let toml = r#"
+            [[rules]]
+            match = { provider = "claude", contains = "weather" }
+            response = { tool_call = { name = "get_weather", arguments = { location = "Tokyo" } } }
+
+            [[rules]]
+            match = { regex = "(?i)^hello" }
+            response = { text = "Hi there!" }
+
+            [[rules]]
+            match = { headers = { "x-test-case" = "fail" } }
+            response = { error = "rate_limit" }
+        "#;
+
+        let file: FixtureFile = toml::from_str(toml).unwrap();
+        let fixtures = Fixtures { rules: file.rules };
+        let headers = HeaderMap::new();
+
+        let reply = fixtures.find(&info(&headers, Some("What's the weather?")));
+        assert!(matches!(reply, Some(Reply::ToolCall(call)) if call.name == "get_weather"));
+
+        let reply = fixtures.find(&info(&headers, Some("Hello!")));
+        assert!(matches!(reply, Some(Reply::Text(t)) if t == "Hi there!"));
+
+        assert!(fixtures.find(&info(&headers, Some("Goodbye"))).is_none());
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-test-case", "fail".parse().unwrap());
+        let reply = fixtures.find(&info(&headers, Some("Goodbye")));
+        assert!(matches!(reply, Some(Reply::Error(ErrorType::RateLimit))));
+    }
+
+    #[test]
+    fn test_parse_json_and_yaml_rules() {
+        let json = r#"{"rules": [{"match": {"model": "gpt-4o"}, "response": {"text": "json"}}]}"#;
+        let file: FixtureFile = serde_json::from_str(json).unwrap();
+        assert_eq!(file.rules.len(), 1);
+
+        let yaml = "rules:\n  - match:\n      provider: gemini\n    response:\n      text: yaml\n";
+        let value: Value = serde_yaml::from_str(yaml).unwrap();
+        let file: FixtureFile = serde_json::from_value(value).unwrap();
+        assert!(matches!(
+            file.rules[0].matcher.provider,
+            Some(Provider::Gemini)
+        ));
+    }
+
+    #[test]
+    fn test_provider_mismatch() {
+        let rule: Rule = toml::from_str(
+            r#"
+            match = { provider = "openai" }
+            response = { text = "nope" }
+            "#,
+        )
+        .unwrap();
+        let fixtures = Fixtures { rules: vec![rule] };
+        let headers = HeaderMap::new();
+        assert!(fixtures.find(&info(&headers, Some("Hello"))).is_none());
+    }
+
+    #[test]
+    fn test_history_from_entries() {
+        let history = History::from_entries(Vec::new());
+        assert_eq!(history.turn, 1);
+        assert!(!history.tool_result);
+
+        let history = History::from_entries(vec![
+            (false, None),
+            (true, None),
+            (true, None),
+            (false, Some("get_weather".to_string())),
+            (false, Some(String::new())),
+        ]);
+        assert_eq!(history.turn, 2);
+        assert!(history.tool_result);
+        assert_eq!(history.tool_names, vec!["get_weather".to_string()]);
+
+        let history = History::from_entries(vec![
+            (false, None),
+            (true, None),
+            (false, Some("get_weather".to_string())),
+            (true, None),
+            (false, None),
+        ]);
+        assert_eq!(history.turn, 3);
+        assert!(!history.tool_result);
+    }
+
+    #[test]
+    fn test_multi_turn_script() {
+        let rule: Rule = toml::from_str(
+            r#"
+            match = { contains = "weather" }
+            turns = [
+                { tool_call = { name = "get_weather", arguments = { location = "Tokyo" } } },
+                { text = "It is sunny in Tokyo." },
+            ]
+            "#,
+        )
+        .unwrap();
+        let after_tool: Rule = toml::from_str(
+            r#"
+            match = { tool_result_for = "lookup" }
+            response = { text = "Looked it up." }
+            "#,
+        )
+        .unwrap();
+        let fixtures = Fixtures {
+            rules: vec![after_tool, rule],
+        };
+        let headers = HeaderMap::new();
+        let mut request = info(&headers, Some("What's the weather in Tokyo?"));
+
+        let reply = fixtures.find(&request);
+        assert!(matches!(reply, Some(Reply::ToolCall(call)) if call.name == "get_weather"));
+
+        request.history = History::from_entries(vec![
+            (false, None),
+            (true, None),
+            (false, Some("get_weather".to_string())),
+        ]);
+        let reply = fixtures.find(&request);
+        assert!(matches!(reply, Some(Reply::Text(t)) if t == "It is sunny in Tokyo."));
+
+        request.history.tool_names = vec!["lookup".to_string()];
+        let reply = fixtures.find(&request);
+        assert!(matches!(reply, Some(Reply::Text(t)) if t == "Looked it up."));
+
+        request.history.turn = 3;
+        request.history.tool_names.clear();
+        assert!(fixtures.find(&request).is_none());
+    }
+
+    #[test]
+    fn test_invalid_regex_rejected() {
+        let result: Result<Rule, _> = toml::from_str(
+            r#"
+            match = { regex = "(" }
+            response = { text = "x" }
+            "#,
+        );
+        assert!(result.is_err());
+    }
+}
@@ -7,19 +7,21 @@
 //! - POST /v1beta/models/{model}:generateContent - Non-streaming
 //! - POST /v1beta/models/{model}:streamGenerateContent?alt=sse - Streaming
 
+use crate::fixtures::History;
 use crate::generator::ContentGenerator;
+use crate::schema;
+use crate::timing::Events;
+use crate::tools::{ToolChoice, ToolMode};
+use crate::ProviderRequest;
 use axum::{
     body::Body,
     extract::Path,
-    http::{header, StatusCode},
+    http::StatusCode,
     response::{IntoResponse, Response},
     Json,
 };
-use futures::stream::{self, StreamExt};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::time::Duration;
-use tokio::time::sleep;
 
 /// Request body for generateContent.
 #[derive(Debug, Deserialize)]
@@ -128,6 +130,7 @@ pub struct UsageMetadata {
 /// Unified handler for /v1beta/models/{model_action}
 /// Parses model:action format and dispatches accordingly.
 pub async fn handle_model_action(
+    mut gen: ContentGenerator,
     Path(model_action): Path<String>,
     Json(req): Json<GenerateContentRequest>,
 ) -> Response {
@@ -142,8 +145,12 @@ pub async fn handle_model_action(
         }
     };
 
-    let gen = ContentGenerator::new();
-    let wants_tools = should_call_tool(&req);
+    let wants_tools = gen.scripted_tool_use().unwrap_or_else(|| {
+        !req.tool_names().is_empty()
+            && req
+                .tool_choice()
+                .should_call(|| should_call_tool(&req, &mut gen))
+    });
 
     match action {
         "generateContent" => non_stream_response(model, req, gen, wants_tools),
@@ -155,26 +162,125 @@ pub async fn handle_model_action(
     }
 }
 
-/// Decide if we should generate a tool call response.
-fn should_call_tool(req: &GenerateContentRequest) -> bool {
-    if req.tools.is_none() {
-        return false;
+impl GenerateContentRequest {
+    /// Custom sequences that end generation.
+    pub fn stop_sequences(&self) -> &[String] {
+        self.generation_config
+            .as_ref()
+            .and_then(|c| c.stop_sequences.as_deref())
+            .unwrap_or_default()
     }
+}
 
-    // Check last content for tool-triggering keywords
-    if let Some(last) = req.contents.last() {
-        for part in &last.parts {
-            if let Some(text) = &part.text {
-                let lower = text.to_lowercase();
-                return lower.contains("weather")
-                    || lower.contains("search")
-                    || lower.contains("calculate")
-                    || lower.contains("what is")
-                    || lower.contains("find");
-            }
+impl ProviderRequest for GenerateContentRequest {
+    /// Estimated prompt tokens, as reported in `usageMetadata.promptTokenCount`.
+    fn input_tokens(&self) -> u32 {
+        self.contents
+            .iter()
+            .flat_map(|c| &c.parts)
+            .filter_map(|p| p.text.as_ref())
+            .map(|t| ContentGenerator::estimate_tokens(t))
+            .sum()
+    }
+
+    /// Requested output token limit.
+    fn max_output_tokens(&self) -> Option<u32> {
+        self.generation_config
+            .as_ref()
+            .and_then(|c| c.max_output_tokens)
+    }
+
+    /// Tool calling constraints from `toolConfig.functionCallingConfig`:
+    /// `mode` (`AUTO`, `ANY`, `NONE`) and `allowedFunctionNames`.
+    fn tool_choice(&self) -> ToolChoice {
+        let Some(tool_config) = &self.tool_config else {
+            return ToolChoice::default();
+        };
+        let config = tool_config
+            .get("functionCallingConfig")
+            .or_else(|| tool_config.get("function_calling_config"))
+            .unwrap_or(&Value::Null);
+        let mode = match config["mode"]
+            .as_str()
+            .map(str::to_ascii_uppercase)
+            .as_deref()
+        {
+            Some("ANY") => ToolMode::Required,
+            Some("NONE") => ToolMode::None,
+            _ => ToolMode::Auto,
+        };
+        let choice = ToolChoice::new(mode);
+        let allowed = config
+            .get("allowedFunctionNames")
+            .or_else(|| config.get("allowed_function_names"))
+            .and_then(Value::as_array);
+        match allowed {
+            Some(names) => choice.allowing(
+                names
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(str::to_string)
+                    .collect(),
+            ),
+            None => choice,
         }
     }
-    false
+
+    /// Names of the declared functions.
+    fn tool_names(&self) -> Vec<&str> {
+        functions(self)
+            .into_iter()
+            .map(|f| f.name.as_str())
+            .collect()
+    }
+
+    /// Text of the most recent user turn.
+    fn last_user_text(&self) -> Option<&str> {
+        self.contents
+            .iter()
+            .rev()
+            .filter(|c| c.role.as_deref().is_none_or(|r| r == "user"))
+            .find_map(|c| c.parts.iter().find_map(|p| p.text.as_deref()))
+    }
+
+    /// Turn number and pending tool results, from `functionCall` / `functionResponse` parts.
+    fn history(&self) -> History {
+        History::from_entries(self.contents.iter().flat_map(|c| {
+            if c.role.as_deref() == Some("model") {
+                return vec![(true, None)];
+            }
+            let results: Vec<_> = c
+                .parts
+                .iter()
+                .filter_map(|p| p.function_response.as_ref())
+                .map(|r| {
+                    let name = r.get("name").and_then(Value::as_str).unwrap_or_default();
+                    (false, Some(name.to_string()))
+                })
+                .collect();
+            if results.is_empty() {
+                vec![(false, None)]
+            } else {
+                results
+            }
+        }))
+    }
+}
+
+/// Decide if we should generate a tool call response, from the last content.
+fn should_call_tool(req: &GenerateContentRequest, gen: &mut ContentGenerator) -> bool {
+    if req.tools.is_none() {
+        return false;
+    }
+    let text = req
+        .contents
+        .last()
+        .and_then(|last| last.parts.iter().find_map(|part| part.text.as_deref()));
+    let tool_texts = functions(req)
+        .into_iter()
+        .flat_map(|f| [Some(f.name.as_str()), f.description.as_deref()])
+        .flatten();
+    gen.triggers_tool_call(text, tool_texts)
 }
 
 /// Generate non-streaming response.
@@ -184,44 +290,30 @@ fn non_stream_response(
     mut gen: ContentGenerator,
     wants_tools: bool,
 ) -> Response {
-    let max_tokens = req
-        .generation_config
-        .as_ref()
-        .and_then(|c| c.max_output_tokens)
-        .unwrap_or(100);
-
-    let prompt_tokens: u32 = req
-        .contents
-        .iter()
-        .flat_map(|c| &c.parts)
-        .filter_map(|p| p.text.as_ref())
-        .map(|t| ContentGenerator::estimate_tokens(t))
-        .sum();
+    let prompt_tokens = req.input_tokens();
 
     let (parts, finish_reason, completion_tokens) = if wants_tools {
-        let func_name = get_first_function_name(&req);
-        let arg_value = extract_argument(&req);
-
-        (
-            vec![ResponsePart {
+        let (calls, tokens, truncated) = limited_function_calls(&req, &mut gen);
+        let parts: Vec<ResponsePart> = calls
+            .into_iter()
+            .map(|(name, args)| ResponsePart {
                 text: None,
-                function_call: Some(FunctionCall {
-                    name: func_name,
-                    args: json!({ "location": arg_value }),
-                }),
-            }],
-            "STOP",
-            12u32,
-        )
+                function_call: Some(FunctionCall { name, args }),
+            })
+            .collect();
+        (parts, if truncated { "MAX_TOKENS" } else { "STOP" }, tokens)
     } else {
-        let content = gen.paragraph();
-        let tokens = ContentGenerator::estimate_tokens(&content).min(max_tokens);
+        let (mut content, _) = gen.reply_text(ContentGenerator::paragraph, req.stop_sequences());
+        let truncated = req
+            .max_output_tokens()
+            .is_some_and(|max| ContentGenerator::truncate_to_tokens(&mut content, max));
+        let tokens = ContentGenerator::estimate_tokens(&content);
         (
             vec![ResponsePart {
                 text: Some(content),
                 function_call: None,
             }],
-            "STOP",
+            if truncated { "MAX_TOKENS" } else { "STOP" },
             tokens,
         )
     };
@@ -254,44 +346,30 @@ async fn stream_response(
     mut gen: ContentGenerator,
     wants_tools: bool,
 ) -> Response {
-    let max_tokens = req
-        .generation_config
-        .as_ref()
-        .and_then(|c| c.max_output_tokens)
-        .unwrap_or(50) as usize;
-
-    let prompt_tokens: u32 = req
-        .contents
-        .iter()
-        .flat_map(|c| &c.parts)
-        .filter_map(|p| p.text.as_ref())
-        .map(|t| ContentGenerator::estimate_tokens(t))
-        .sum();
+    let prompt_tokens = req.input_tokens();
 
     // Generate chunks
     let chunks: Vec<Value> = if wants_tools {
-        let func_name = get_first_function_name(&req);
-        let arg_value = extract_argument(&req);
+        let (calls, tokens, truncated) = limited_function_calls(&req, &mut gen);
+        let parts: Vec<Value> = calls
+            .into_iter()
+            .map(|(name, args)| json!({ "functionCall": { "name": name, "args": args } }))
+            .collect();
 
         vec![
-            // Single chunk with function call
+            // Single chunk with all function calls
             json!({
                 "candidates": [{
                     "content": {
-                        "parts": [{
-                            "functionCall": {
-                                "name": func_name,
-                                "args": { "location": arg_value }
-                            }
-                        }],
+                        "parts": parts,
                         "role": "model"
                     },
                     "index": 0
                 }],
                 "usageMetadata": {
                     "promptTokenCount": prompt_tokens,
-                    "candidatesTokenCount": 12,
-                    "totalTokenCount": prompt_tokens + 12
+                    "candidatesTokenCount": tokens,
+                    "totalTokenCount": prompt_tokens + tokens
                 },
                 "modelVersion": &model
             }),
@@ -302,28 +380,29 @@ async fn stream_response(
                         "parts": [],
                         "role": "model"
                     },
-                    "finishReason": "STOP",
+                    "finishReason": if truncated { "MAX_TOKENS" } else { "STOP" },
                     "index": 0
                 }],
                 "usageMetadata": {
                     "promptTokenCount": prompt_tokens,
-                    "candidatesTokenCount": 12,
-                    "totalTokenCount": prompt_tokens + 12
+                    "candidatesTokenCount": tokens,
+                    "totalTokenCount": prompt_tokens + tokens
                 },
                 "modelVersion": &model
             }),
         ]
     } else {
-        let content_parts = gen.stream_chunks(max_tokens);
+        let (text, _) = gen.reply_text(ContentGenerator::paragraph, req.stop_sequences());
+        let mut deltas = gen.chunk_text(&text);
+        let truncated = ContentGenerator::limit_chunks(&mut deltas, req.max_output_tokens());
+        let mut streamed = String::new();
         let mut total_tokens = 0u32;
 
-        let mut result: Vec<Value> = content_parts
+        let mut result: Vec<Value> = deltas
             .into_iter()
-            .enumerate()
-            .map(|(i, content)| {
-                let prefix = if i > 0 { " " } else { "" };
-                let text = format!("{prefix}{content}");
-                total_tokens += ContentGenerator::estimate_tokens(&text);
+            .map(|text| {
+                streamed.push_str(&text);
+                total_tokens = ContentGenerator::estimate_tokens(&streamed);
 
                 json!({
                     "candidates": [{
@@ -350,7 +429,7 @@ async fn stream_response(
                     "parts": [],
                     "role": "model"
                 },
-                "finishReason": "STOP",
+                "finishReason": if truncated { "MAX_TOKENS" } else { "STOP" },
                 "index": 0
             }],
             "usageMetadata": {
@@ -364,34 +443,88 @@ async fn stream_response(
         result
     };
 
-    // Build the SSE stream
-    let stream = stream::iter(chunks)
-        .then(|chunk| async move {
-            sleep(Duration::from_millis(15)).await;
-            format!("data: {chunk}\n\n")
+    let mut events = Events::new();
+    for chunk in chunks {
+        let tokens = chunk_tokens(&chunk);
+        events.push_tokens(format!("data: {chunk}\n\n"), tokens);
+    }
+    gen.paced_response(events)
+}
+
+/// Output tokens carried by a streamed chunk's parts.
+fn chunk_tokens(chunk: &Value) -> u32 {
+    chunk["candidates"][0]["content"]["parts"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|part| match part["text"].as_str() {
+            Some(text) => ContentGenerator::estimate_tokens(text),
+            None => ContentGenerator::estimate_tokens(&part["functionCall"].to_string()),
         })
-        .map(Ok::<_, std::convert::Infallible>);
+        .sum()
+}
 
-    let body = Body::from_stream(stream);
+/// Pick the function names and args for a function call reply, preferring
+/// scripted calls. Generated calls go to the functions `toolConfig` allows in
+/// order, wrapping around when there are more calls than functions.
+fn function_calls(
+    req: &GenerateContentRequest,
+    gen: &mut ContentGenerator,
+) -> Vec<(String, Value)> {
+    if let Some(calls) = gen.take_scripted_tool_calls() {
+        return calls
+            .into_iter()
+            .map(|call| (call.name, call.arguments))
+            .collect();
+    }
+    let argument = extract_argument(req);
+    let choice = req.tool_choice();
+    let functions: Vec<&FunctionDeclaration> = functions(req)
+        .into_iter()
+        .filter(|function| choice.allows(&function.name))
+        .collect();
+    let count = gen.tool_call_count(choice.parallel);
+    functions
+        .iter()
+        .cycle()
+        .take(count)
+        .map(|function| {
+            let args = match &function.parameters {
+                Some(parameters) => schema::arguments(parameters, &argument, gen),
+                None => json!({ "location": argument }),
+            };
+            (function.name.clone(), args)
+        })
+        .collect()
+}
 
-    Response::builder()
-        .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, "text/event-stream")
-        .header(header::CACHE_CONTROL, "no-cache")
-        .header(header::CONNECTION, "keep-alive")
-        .body(body)
-        .unwrap()
+/// Function calls for a reply, cut to fit in `maxOutputTokens`, with their
+/// output tokens and whether the last was cut off (it then has empty args).
+fn limited_function_calls(
+    req: &GenerateContentRequest,
+    gen: &mut ContentGenerator,
+) -> (Vec<(String, Value)>, u32, bool) {
+    let mut calls = function_calls(req, gen);
+    let mut arguments: Vec<String> = calls.iter().map(|(_, args)| args.to_string()).collect();
+    let (tokens, truncated) =
+        ContentGenerator::limit_tool_arguments(&mut arguments, req.max_output_tokens());
+    calls.truncate(arguments.len());
+    if truncated {
+        if let Some((_, args)) = calls.last_mut() {
+            *args = json!({});
+        }
+    }
+    (calls, tokens, truncated)
 }
 
-/// Get the first function name from tools.
-fn get_first_function_name(req: &GenerateContentRequest) -> String {
+/// All functions declared in tools, in order.
+fn functions(req: &GenerateContentRequest) -> Vec<&FunctionDeclaration> {
     req.tools
-        .as_ref()
-        .and_then(|tools| tools.first())
-        .and_then(|t| t.function_declarations.as_ref())
-        .and_then(|fns| fns.first())
-        .map(|f| f.name.clone())
-        .unwrap_or_else(|| "unknown_function".to_string())
+        .iter()
+        .flatten()
+        .filter_map(|t| t.function_declarations.as_ref())
+        .flatten()
+        .collect()
 }
 
 /// Extract an argument value from the user message.
@@ -490,7 +623,7 @@ mod tests {
             tool_config: None,
         };
 
-        assert!(should_call_tool(&req));
+        assert!(should_call_tool(&req, &mut ContentGenerator::new()));
     }
 
     #[test]
@@ -542,27 +675,50 @@ mod tests {
             tool_config: None,
         };
 
-        assert!(!should_call_tool(&req));
+        assert!(!should_call_tool(&req, &mut ContentGenerator::new()));
+    }
+
+    #[test]
+    fn test_history_after_function_response() {
+        let json = r#"{
+            "contents": [
+                {"role": "user", "parts": [{"text": "What is the weather in Oslo?"}]},
+                {"role": "model", "parts": [{"functionCall": {"name": "get_weather", "args": {}}}]},
+                {"role": "user", "parts": [
+                    {"functionResponse": {"name": "get_weather", "response": {"temp": 3}}}
+                ]}
+            ]
+        }"#;
+        let req: GenerateContentRequest = serde_json::from_str(json).unwrap();
+
+        let history = req.history();
+        assert_eq!(history.turn, 2);
+        assert_eq!(history.tool_names, vec!["get_weather".to_string()]);
+        assert_eq!(req.last_user_text(), Some("What is the weather in Oslo?"));
     }
 
     #[test]
-    fn test_get_first_function_name() {
+    fn test_functions() {
+        let declaration = |name: &str| ToolDeclaration {
+            function_declarations: Some(vec![FunctionDeclaration {
+                name: name.to_string(),
+                description: None,
+                parameters: None,
+            }]),
+        };
         let req = GenerateContentRequest {
             contents: vec![],
             system_instruction: None,
             generation_config: None,
-            tools: Some(vec![ToolDeclaration {
-                function_declarations: Some(vec![FunctionDeclaration {
-                    name: "my_function".to_string(),
-                    description: None,
-                    parameters: None,
-                }]),
-            }]),
+            tools: Some(vec![
+                declaration("my_function"),
+                declaration("other_function"),
+            ]),
             tool_config: None,
         };
 
-        let name = get_first_function_name(&req);
-        assert_eq!(name, "my_function");
+        let names: Vec<&str> = functions(&req).iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["my_function", "other_function"]);
     }
 
     #[test]
@@ -608,8 +764,12 @@ mod tests {
             tool_config: None,
         };
 
-        let response =
-            handle_model_action(Path("gemini-pro:generateContent".to_string()), Json(req)).await;
+        let response = handle_model_action(
+            ContentGenerator::new(),
+            Path("gemini-pro:generateContent".to_string()),
+            Json(req),
+        )
+        .await;
         assert_eq!(response.status(), StatusCode::OK);
     }
 
@@ -636,6 +796,7 @@ mod tests {
         };
 
         let response = handle_model_action(
+            ContentGenerator::new(),
             Path("gemini-pro:streamGenerateContent".to_string()),
             Json(req),
         )
@@ -670,8 +831,12 @@ mod tests {
             tool_config: None,
         };
 
-        let response =
-            handle_model_action(Path("gemini-pro:generateContent".to_string()), Json(req)).await;
+        let response = handle_model_action(
+            ContentGenerator::new(),
+            Path("gemini-pro:generateContent".to_string()),
+            Json(req),
+        )
+        .await;
         assert_eq!(response.status(), StatusCode::OK);
     }
 }
@@ -1,6 +1,15 @@
 //! Token and content generators for mock responses.
 
+use std::fmt;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use fastrand::Rng;
+use serde::Deserialize;
+
+use crate::fixtures::{Reply, ToolCall};
+use crate::timing::{Events, TimingModel};
+use crate::tools::{ArgumentStreaming, ToolTrigger};
 
 /// Lorem ipsum style word list for generating fake content.
 const WORDS: &[&str] = &[
@@ -108,34 +117,116 @@ const WORDS: &[&str] = &[
     "batch",
 ];
 
+/// Source of wall-clock time for response timestamps.
+pub trait Clock: Send + Sync + fmt::Debug {
+    /// Current time in seconds since the Unix epoch.
+    fn now_unix(&self) -> u64;
+}
+
+/// Clock backed by the system time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_unix(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+}
+
+/// Clock frozen at a fixed Unix timestamp, for reproducible output.
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock(pub u64);
+
+impl Clock for FixedClock {
+    fn now_unix(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Stop sequences in OpenAI-style requests: a single string or a list.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum StopSequences {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl StopSequences {
+    pub fn as_slice(&self) -> &[String] {
+        match self {
+            Self::One(stop) => std::slice::from_ref(stop),
+            Self::Many(stops) => stops,
+        }
+    }
+}
+
 /// Generator for fake content with configurable behavior.
 pub struct ContentGenerator {
     rng: Rng,
+    clock: Arc<dyn Clock>,
     /// Average tokens per chunk for streaming.
     pub tokens_per_chunk: usize,
-    /// Delay between chunks in ms (for realistic streaming).
-    #[allow(dead_code)]
-    pub chunk_delay_ms: u64,
+    /// Pacing of streamed responses.
+    pub timing: TimingModel,
+    /// Scripted reply (from a fixture) that replaces generated content.
+    pub script: Option<Reply>,
+    /// Chance (0.0 - 1.0) that generated text ends at one of the request's
+    /// stop sequences.
+    pub stop_sequence_rate: f64,
+    /// Exact length of generated reply text, in estimated tokens.
+    pub target_tokens: Option<u32>,
+    /// Number of parallel calls in a generated tool call reply.
+    pub tool_calls: usize,
+    /// When replies call tools, if the request leaves it to the model.
+    pub tool_trigger: ToolTrigger,
+    /// How tool call arguments are written and cut into streaming deltas.
+    pub argument_streaming: ArgumentStreaming,
 }
 
 impl ContentGenerator {
     pub fn new() -> Self {
         Self {
             rng: Rng::new(),
+            clock: Arc::new(SystemClock),
             tokens_per_chunk: 3,
-            chunk_delay_ms: 20,
+            timing: TimingModel::default(),
+            script: None,
+            stop_sequence_rate: 0.0,
+            target_tokens: None,
+            tool_calls: 1,
+            tool_trigger: ToolTrigger::default(),
+            argument_streaming: ArgumentStreaming::default(),
         }
     }
 
-    #[cfg(test)]
+    /// Create a generator whose output is fully determined by `seed`.
     pub fn with_seed(seed: u64) -> Self {
         Self {
             rng: Rng::with_seed(seed),
-            tokens_per_chunk: 3,
-            chunk_delay_ms: 20,
+            ..Self::new()
         }
     }
 
+    /// Use the given clock for timestamps.
+    #[must_use]
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Random source of the generator, for content built elsewhere.
+    pub fn rng(&mut self) -> &mut Rng {
+        &mut self.rng
+    }
+
+    /// Current Unix timestamp according to the generator's clock.
+    pub fn now_unix(&self) -> u64 {
+        self.clock.now_unix()
+    }
+
     /// Generate a random word.
     pub fn word(&mut self) -> &'static str {
         WORDS[self.rng.usize(..WORDS.len())]
@@ -161,6 +252,9 @@ impl ContentGenerator {
         s
     }
 
+    /// Typical length of a [`Self::paragraph`] in estimated tokens.
+    pub const PARAGRAPH_TOKENS: u32 = 36;
+
     /// Generate a paragraph (2-5 sentences).
     pub fn paragraph(&mut self) -> String {
         let count = self.rng.usize(2..5);
@@ -170,6 +264,24 @@ impl ContentGenerator {
             .join(" ")
     }
 
+    /// Generate sentences totalling exactly `tokens` estimated tokens.
+    pub fn text_of_tokens(&mut self, tokens: u32) -> String {
+        let len = tokens as usize * 4;
+        let mut text = String::with_capacity(len + 100);
+        while text.len() < len {
+            if !text.is_empty() {
+                text.push(' ');
+            }
+            text.push_str(&self.sentence());
+        }
+        text.truncate(len);
+        if text.ends_with(' ') {
+            text.pop();
+            text.push('.');
+        }
+        text
+    }
+
     /// Generate content chunks for streaming.
     /// Returns an iterator of (content, is_last) pairs.
     pub fn stream_chunks(&mut self, total_tokens: usize) -> Vec<String> {
@@ -190,6 +302,193 @@ impl ContentGenerator {
         chunks
     }
 
+    /// Split text into streaming deltas of 1..=`tokens_per_chunk` words.
+    /// Deltas keep their leading whitespace, so they concatenate back to `text`.
+    pub fn chunk_text(&mut self, text: &str) -> Vec<String> {
+        let mut words = Vec::new();
+        let mut start = 0;
+        let mut prev_space = false;
+        for (i, ch) in text.char_indices() {
+            let space = ch.is_whitespace();
+            if space && !prev_space && i > 0 {
+                words.push(&text[start..i]);
+                start = i;
+            }
+            prev_space = space;
+        }
+        if start < text.len() {
+            words.push(&text[start..]);
+        }
+
+        let mut chunks = Vec::new();
+        let mut rest = words.as_slice();
+        while !rest.is_empty() {
+            let size = rest
+                .len()
+                .min(self.rng.usize(1..=self.tokens_per_chunk.max(1)));
+            chunks.push(rest[..size].concat());
+            rest = &rest[size..];
+        }
+        chunks
+    }
+
+    /// Text of a reply: the scripted text, text of `target_tokens` if set,
+    /// or text from `generate`.
+    ///
+    /// The text ends before the first of `stops` it contains. Generated text
+    /// may also end early at a random stop sequence, as if the model had
+    /// produced it, per `stop_sequence_rate`. Also returns the stop sequence
+    /// the text ended at.
+    pub fn reply_text(
+        &mut self,
+        generate: impl FnOnce(&mut Self) -> String,
+        stops: &[String],
+    ) -> (String, Option<String>) {
+        let (mut text, scripted) = match self.take_scripted_text() {
+            Some(text) => (text, true),
+            None => match self.target_tokens {
+                Some(tokens) => (self.text_of_tokens(tokens), false),
+                None => (generate(self), false),
+            },
+        };
+        let stops: Vec<&String> = stops.iter().filter(|stop| !stop.is_empty()).collect();
+
+        let found = stops
+            .iter()
+            .filter_map(|stop| text.find(stop.as_str()).map(|i| (i, *stop)))
+            .min_by_key(|(i, _)| *i);
+        if let Some((i, stop)) = found {
+            text.truncate(i);
+            return (text, Some(stop.clone()));
+        }
+        if scripted || stops.is_empty() || self.rng.f64() >= self.stop_sequence_rate {
+            return (text, None);
+        }
+
+        let stop = stops[self.rng.usize(..stops.len())].clone();
+        let ends: Vec<usize> = text
+            .match_indices(' ')
+            .map(|(i, _)| i)
+            .chain([text.len()])
+            .collect();
+        text.truncate(ends[self.rng.usize(..ends.len())]);
+        (text, Some(stop))
+    }
+
+    /// Cut `text` so its estimated tokens fit in `max_tokens`.
+    /// Returns whether anything was cut.
+    pub fn truncate_to_tokens(text: &mut String, max_tokens: u32) -> bool {
+        truncate_to_len(text, max_tokens as usize * 4)
+    }
+
+    /// Fit the encoded arguments of a reply's tool calls in `max_tokens` (no
+    /// limit if `None`), as a model running out of tokens mid-call would: the
+    /// arguments of the first call that doesn't fit are cut short and the
+    /// calls after it dropped. Returns the output tokens of the calls and
+    /// whether one was cut, which is then the last.
+    pub fn limit_tool_arguments(
+        arguments: &mut Vec<String>,
+        max_tokens: Option<u32>,
+    ) -> (u32, bool) {
+        let mut used = 0;
+        for i in 0..arguments.len() {
+            let tokens = Self::estimate_tokens(&arguments[i]);
+            match max_tokens {
+                Some(max_tokens) if used + tokens > max_tokens => {
+                    Self::truncate_to_tokens(&mut arguments[i], max_tokens - used);
+                    arguments.truncate(i + 1);
+                    return (max_tokens, true);
+                }
+                _ => used += tokens,
+            }
+        }
+        (used, false)
+    }
+
+    /// Drop and cut streaming deltas so the text they make up fits in
+    /// `max_tokens` (no limit if `None`). Returns whether anything was cut.
+    pub fn limit_chunks(chunks: &mut Vec<String>, max_tokens: Option<u32>) -> bool {
+        let Some(max_tokens) = max_tokens else {
+            return false;
+        };
+        let mut budget = max_tokens as usize * 4;
+        let mut keep = None;
+        for (i, chunk) in chunks.iter_mut().enumerate() {
+            if truncate_to_len(chunk, budget) {
+                keep = Some(if chunk.is_empty() { i } else { i + 1 });
+                break;
+            }
+            budget -= chunk.len();
+        }
+        match keep {
+            Some(keep) => {
+                chunks.truncate(keep);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Whether the scripted reply is a tool call, or `None` if nothing is scripted.
+    pub fn scripted_tool_use(&self) -> Option<bool> {
+        self.script
+            .as_ref()
+            .map(|reply| matches!(reply, Reply::ToolCall(_) | Reply::ToolCalls(_)))
+    }
+
+    /// Take the scripted text, if the script is a text reply.
+    pub fn take_scripted_text(&mut self) -> Option<String> {
+        match self.script.take() {
+            Some(Reply::Text(text)) => Some(text),
+            other => {
+                self.script = other;
+                None
+            }
+        }
+    }
+
+    /// Take the scripted tool calls, if the script is a tool call reply.
+    pub fn take_scripted_tool_calls(&mut self) -> Option<Vec<ToolCall>> {
+        match self.script.take() {
+            Some(Reply::ToolCall(call)) => Some(vec![call]),
+            Some(Reply::ToolCalls(calls)) => Some(calls),
+            other => {
+                self.script = other;
+                None
+            }
+        }
+    }
+
+    /// Whether `prompt` triggers a call to the tools described by
+    /// `tool_texts` (their names and descriptions), per `tool_trigger`.
+    pub fn triggers_tool_call<'a>(
+        &mut self,
+        prompt: Option<&str>,
+        tool_texts: impl IntoIterator<Item = &'a str>,
+    ) -> bool {
+        self.tool_trigger.fires(prompt, tool_texts, &mut self.rng)
+    }
+
+    /// Argument JSON of a tool call, per `argument_streaming`.
+    pub fn encode_arguments(&self, arguments: &serde_json::Value) -> String {
+        self.argument_streaming.encode(arguments)
+    }
+
+    /// Cut argument JSON into streaming deltas, per `argument_streaming`.
+    pub fn argument_deltas(&mut self, arguments: &str) -> Vec<String> {
+        self.argument_streaming.split(arguments, &mut self.rng)
+    }
+
+    /// Number of calls to generate for a tool call reply: `tool_calls`, or
+    /// one if the request disallows parallel calls.
+    pub fn tool_call_count(&self, parallel: bool) -> usize {
+        if parallel {
+            self.tool_calls.max(1)
+        } else {
+            1
+        }
+    }
+
     /// Generate a random tool call ID.
     pub fn tool_call_id(&mut self) -> String {
         format!("{:011x}", self.rng.u64(..))
@@ -197,7 +496,8 @@ impl ContentGenerator {
 
     /// Generate a random chat completion ID.
     pub fn completion_id(&mut self) -> String {
-        format!("chatcmpl-{}", uuid::Uuid::new_v4())
+        let uuid = uuid::Builder::from_random_bytes(self.rng.u128(..).to_le_bytes()).into_uuid();
+        format!("chatcmpl-{uuid}")
     }
 
     /// Generate a system fingerprint.
@@ -205,12 +505,32 @@ impl ContentGenerator {
         format!("fp_{:016x}", self.rng.u64(..))
     }
 
+    /// Stream `events` as an SSE response paced by the timing model.
+    pub fn paced_response(&mut self, events: Events) -> axum::response::Response {
+        let delays = self.timing.delays(&events.tokens(), &mut self.rng);
+        events.into_response(delays)
+    }
+
     /// Estimate token count from text (rough: ~4 chars per token).
     pub fn estimate_tokens(text: &str) -> u32 {
         ((text.len() as f32) / 4.0).ceil() as u32
     }
 }
 
+/// Cut `text` to at most `len` bytes, on a character boundary.
+/// Returns whether anything was cut.
+fn truncate_to_len(text: &mut String, len: usize) -> bool {
+    if text.len() <= len {
+        return false;
+    }
+    let mut end = len;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text.truncate(end);
+    true
+}
+
 impl Default for ContentGenerator {
     fn default() -> Self {
         Self::new()
@@ -246,6 +566,113 @@ mod tests {
         assert!(chunks.last().unwrap().ends_with('.'));
     }
 
+    #[test]
+    fn test_chunk_text_roundtrip() {
+        let mut gen = ContentGenerator::with_seed(1);
+        let text = "It is  sunny in Tokyo today.\nEnjoy!";
+        let chunks = gen.chunk_text(text);
+        assert!(chunks.len() > 1);
+        assert_eq!(chunks.concat(), text);
+        assert!(gen.chunk_text("").is_empty());
+    }
+
+    #[test]
+    fn test_limit_tool_arguments() {
+        let call = r#"{"location":"Tokyo"}"#.to_string();
+        let mut arguments = vec![call.clone(), call.clone(), call.clone()];
+        assert_eq!(
+            ContentGenerator::limit_tool_arguments(&mut arguments, None),
+            (15, false)
+        );
+        assert_eq!(
+            ContentGenerator::limit_tool_arguments(&mut arguments, Some(15)),
+            (15, false)
+        );
+        assert_eq!(
+            ContentGenerator::limit_tool_arguments(&mut arguments, Some(7)),
+            (7, true)
+        );
+        assert_eq!(arguments, [call, r#"{"locati"#.to_string()]);
+    }
+
+    #[test]
+    fn test_truncate_to_tokens() {
+        let mut text = "one two three four".to_string();
+        assert!(!ContentGenerator::truncate_to_tokens(&mut text, 5));
+        assert!(ContentGenerator::truncate_to_tokens(&mut text, 2));
+        assert_eq!(text, "one two ");
+
+        let mut text = "héllo wörld".to_string();
+        assert!(ContentGenerator::truncate_to_tokens(&mut text, 1));
+        assert_eq!(text, "hél");
+
+        let mut chunks = vec![
+            "one two".to_string(),
+            " three".to_string(),
+            " four".to_string(),
+        ];
+        assert!(!ContentGenerator::limit_chunks(&mut chunks, None));
+        assert!(ContentGenerator::limit_chunks(&mut chunks, Some(3)));
+        assert_eq!(chunks, vec!["one two".to_string(), " thre".to_string()]);
+        assert_eq!(ContentGenerator::estimate_tokens(&chunks.concat()), 3);
+    }
+
+    #[test]
+    fn test_text_of_tokens() {
+        let mut gen = ContentGenerator::with_seed(3);
+        for tokens in [0, 1, 5, 4000] {
+            let text = gen.text_of_tokens(tokens);
+            assert_eq!(ContentGenerator::estimate_tokens(&text), tokens);
+            assert!(!text.ends_with(' '));
+        }
+
+        gen.target_tokens = Some(7);
+        let (text, _) = gen.reply_text(ContentGenerator::paragraph, &[]);
+        assert_eq!(ContentGenerator::estimate_tokens(&text), 7);
+        let chunks = gen.chunk_text(&text);
+        assert_eq!(ContentGenerator::estimate_tokens(&chunks.concat()), 7);
+    }
+
+    #[test]
+    fn test_reply_text_stop_sequences() {
+        let stops = vec!["END".to_string(), "\n\n".to_string()];
+
+        let mut gen = ContentGenerator::with_seed(1);
+        gen.script = Some(Reply::Text("First part.\n\nSecond END".to_string()));
+        let (text, stop) = gen.reply_text(ContentGenerator::paragraph, &stops);
+        assert_eq!(text, "First part.");
+        assert_eq!(stop.as_deref(), Some("\n\n"));
+
+        gen.script = Some(Reply::Text("No stop here.".to_string()));
+        let (text, stop) = gen.reply_text(ContentGenerator::paragraph, &stops);
+        assert_eq!(text, "No stop here.");
+        assert!(stop.is_none());
+
+        let (text, stop) = gen.reply_text(|gen| gen.words(20), &stops);
+        assert_eq!(text.split(' ').count(), 20);
+        assert!(stop.is_none());
+
+        gen.stop_sequence_rate = 1.0;
+        let (text, stop) = gen.reply_text(|gen| gen.words(20), &stops);
+        assert!(stop.is_some());
+        assert!(text.split(' ').count() <= 20);
+
+        let many: StopSequences = serde_json::from_str(r#"["a", "b"]"#).unwrap();
+        let one: StopSequences = serde_json::from_str(r#""a""#).unwrap();
+        assert_eq!(many.as_slice().len(), 2);
+        assert_eq!(one.as_slice(), ["a".to_string()]);
+    }
+
+    #[test]
+    fn test_scripted_text() {
+        let mut gen = ContentGenerator::new();
+        gen.script = Some(Reply::Text("Scripted".to_string()));
+        assert_eq!(gen.scripted_tool_use(), Some(false));
+        assert!(gen.take_scripted_tool_calls().is_none());
+        assert_eq!(gen.take_scripted_text().as_deref(), Some("Scripted"));
+        assert_eq!(gen.scripted_tool_use(), None);
+    }
+
     #[test]
     fn test_deterministic_with_seed() {
         let mut gen1 = ContentGenerator::with_seed(42);
@@ -256,4 +683,21 @@ mod tests {
 
         assert_eq!(words1, words2);
     }
+
+    #[test]
+    fn test_completion_id_deterministic_with_seed() {
+        let mut gen1 = ContentGenerator::with_seed(7);
+        let mut gen2 = ContentGenerator::with_seed(7);
+
+        let id = gen1.completion_id();
+        assert!(id.starts_with("chatcmpl-"));
+        assert_eq!(id, gen2.completion_id());
+        assert_ne!(gen1.completion_id(), id);
+    }
+
+    #[test]
+    fn test_fixed_clock() {
+        let gen = ContentGenerator::new().with_clock(Arc::new(FixedClock(1_700_000_000)));
+        assert_eq!(gen.now_unix(), 1_700_000_000);
+    }
 }
@@ -0,0 +1,564 @@
+//! In-memory journal of received requests.
+//!
+//! Every provider request is recorded with its headers, parsed body, response
+//! status and a summary of what was returned, so tests can assert on what a
+//! client actually sent (retries, headers, prompts). The journal is bounded;
+//! the oldest entries are dropped first.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::errors::Provider;
+
+/// Credential headers whose values are never recorded.
+const REDACTED_HEADERS: [&str; 3] = ["authorization", "x-api-key", "x-goog-api-key"];
+
+/// Recorded in place of a credential.
+const REDACTED: &str = "[REDACTED]";
+
+/// Journal settings in the main config.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JournalConfig {
+    pub enabled: bool,
+    /// Maximum number of entries kept.
+    pub capacity: usize,
+}
+
+impl Default for JournalConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            capacity: 1000,
+        }
+    }
+}
+
+/// A received request and what it was answered with.
+#[derive(Debug, Clone, Serialize)]
+pub struct JournalEntry {
+    /// Sequence number, increasing from 1 and never reused after `clear`.
+    pub id: u64,
+    /// Unix time in milliseconds when the request arrived.
+    pub timestamp_ms: u64,
+    pub provider: Option<Provider>,
+    pub method: String,
+    /// Path and query.
+    pub path: String,
+    pub model: Option<String>,
+    pub headers: BTreeMap<String, String>,
+    /// Request body as JSON, or as a string when it isn't valid JSON.
+    pub body: Value,
+    pub status: u16,
+    pub response: ResponseSummary,
+}
+
+/// What a response contained, extracted from the provider's format.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ResponseSummary {
+    pub stream: bool,
+    /// False while a streamed response is still being sent.
+    pub complete: bool,
+    /// Concatenated text output.
+    pub text: String,
+    /// Names of the tools called.
+    pub tool_calls: Vec<String>,
+    /// `stop_reason`, `finish_reason`, `finishReason` or `status`.
+    pub stop_reason: Option<String>,
+    /// Error type or status for error responses.
+    pub error: Option<String>,
+}
+
+/// Criteria for querying the journal. Empty fields match everything.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct JournalFilter {
+    pub provider: Option<Provider>,
+    pub model: Option<String>,
+    /// Path prefix.
+    pub path: Option<String>,
+    pub status: Option<u16>,
+    /// Header that must be present, as `name` or `name:value`.
+    pub header: Option<String>,
+    /// Substring of the JSON-encoded request body.
+    pub contains: Option<String>,
+    /// Only entries with an id greater than this.
+    pub since_id: Option<u64>,
+    /// Return at most this many of the newest matching entries.
+    pub limit: Option<usize>,
+}
+
+/// Bounded, thread-safe request journal.
+#[derive(Debug, Default)]
+pub struct Journal {
+    entries: Mutex<VecDeque<JournalEntry>>,
+    next_id: AtomicU64,
+}
+
+impl Journal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an entry, assigning its id and evicting the oldest beyond `capacity`.
+    pub fn record(&self, mut entry: JournalEntry, capacity: usize) -> u64 {
+        entry.id = self.next_id.fetch_add(1, Ordering::SeqCst) + 1;
+        let id = entry.id;
+        let mut entries = self.entries.lock().unwrap();
+        entries.push_back(entry);
+        while entries.len() > capacity {
+            entries.pop_front();
+        }
+        id
+    }
+
+    /// Replace the response summary of an entry, e.g. once a stream finishes.
+    pub fn update(&self, id: u64, response: ResponseSummary) {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries.iter_mut().rev().find(|e| e.id == id) {
+            entry.response = response;
+        }
+    }
+
+    /// Matching entries, oldest first.
+    pub fn entries(&self, filter: &JournalFilter) -> Vec<JournalEntry> {
+        let entries = self.entries.lock().unwrap();
+        let mut matching: Vec<JournalEntry> = entries
+            .iter()
+            .filter(|entry| filter.matches(entry))
+            .cloned()
+            .collect();
+        if let Some(limit) = filter.limit {
+            let skip = matching.len().saturating_sub(limit);
+            matching.drain(..skip);
+        }
+        matching
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove all entries, returning how many there were.
+    pub fn clear(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let count = entries.len();
+        entries.clear();
+        count
+    }
+}
+
+impl JournalFilter {
+    fn matches(&self, entry: &JournalEntry) -> bool {
+        if self.provider.is_some_and(|p| entry.provider != Some(p)) {
+            return false;
+        }
+        if let Some(model) = &self.model {
+            if entry.model.as_deref() != Some(model.as_str()) {
+                return false;
+            }
+        }
+        if let Some(path) = &self.path {
+            if !entry.path.starts_with(path.as_str()) {
+                return false;
+            }
+        }
+        if self.status.is_some_and(|s| entry.status != s) {
+            return false;
+        }
+        if let Some(header) = &self.header {
+            let (name, value) = match header.split_once(':') {
+                Some((name, value)) => (name.trim(), Some(value.trim())),
+                None => (header.trim(), None),
+            };
+            let found = entry.headers.get(&name.to_ascii_lowercase());
+            if found.is_none() || value.is_some_and(|v| found.map(String::as_str) != Some(v)) {
+                return false;
+            }
+        }
+        if let Some(needle) = &self.contains {
+            let body = match &entry.body {
+                Value::String(text) => text.clone(),
+                other => other.to_string(),
+            };
+            if !body.contains(needle.as_str()) {
+                return false;
+            }
+        }
+        if self.since_id.is_some_and(|since| entry.id <= since) {
+            return false;
+        }
+        true
+    }
+}
+
+impl JournalEntry {
+    /// Entry for an incoming request; `id`, `status` and `response` are filled in later.
+    pub fn new(
+        provider: Option<Provider>,
+        method: &str,
+        path: &str,
+        headers: &axum::http::HeaderMap,
+        body: &[u8],
+    ) -> Self {
+        let body = if body.is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_slice(body)
+                .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(body).into()))
+        };
+        let model = match provider {
+            // Gemini puts the model in the path: /v1beta/models/{model}:{action}
+            Some(Provider::Gemini) => path
+                .strip_prefix("/v1beta/models/")
+                .and_then(|rest| rest.split([':', '?']).next())
+                .map(str::to_string),
+            _ => body["model"].as_str().map(str::to_string),
+        };
+        Self {
+            id: 0,
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX)),
+            provider,
+            method: method.to_string(),
+            path: redact_query(path),
+            model,
+            headers: headers
+                .iter()
+                .filter_map(|(name, value)| {
+                    let value = if REDACTED_HEADERS.contains(&name.as_str()) {
+                        REDACTED
+                    } else {
+                        value.to_str().ok()?
+                    };
+                    Some((name.as_str().to_string(), value.to_string()))
+                })
+                .collect(),
+            body,
+            status: 0,
+            response: ResponseSummary::default(),
+        }
+    }
+}
+
+/// Hide the Gemini `key=` query parameter of a request path.
+fn redact_query(path: &str) -> String {
+    let Some((path, query)) = path.split_once('?') else {
+        return path.to_string();
+    };
+    let query: Vec<String> = query
+        .split('&')
+        .map(|param| {
+            if param.starts_with("key=") {
+                format!("key={REDACTED}")
+            } else {
+                param.to_string()
+            }
+        })
+        .collect();
+    format!("{path}?{}", query.join("&"))
+}
+
+impl ResponseSummary {
+    /// Summarize a complete non-streaming response body.
+    pub fn from_body(provider: Option<Provider>, body: &[u8]) -> Self {
+        let mut summary = Self {
+            complete: true,
+            ..Self::default()
+        };
+        if let (Some(provider), Ok(value)) = (provider, serde_json::from_slice(body)) {
+            summary.absorb(provider, &value);
+        }
+        summary
+    }
+
+    /// Summary for a stream that is about to start.
+    pub fn streaming() -> Self {
+        Self {
+            stream: true,
+            ..Self::default()
+        }
+    }
+
+    /// Fold one SSE event's `data:` payloads into the summary.
+    pub fn absorb_event(&mut self, provider: Option<Provider>, event: &[u8]) {
+        let Some(provider) = provider else {
+            return;
+        };
+        for line in String::from_utf8_lossy(event).lines() {
+            if let Some(data) = line.strip_prefix("data:") {
+                if let Ok(value) = serde_json::from_str(data.trim()) {
+                    self.absorb(provider, &value);
+                }
+            }
+        }
+    }
+
+    /// Fold a response body or a stream event into the summary.
+    fn absorb(&mut self, provider: Provider, value: &Value) {
+        if let Some(error) = error_kind(value) {
+            self.error = Some(error);
+            return;
+        }
+        match provider {
+            Provider::Claude => {
+                match value["type"].as_str() {
+                    Some("content_block_start") => self.claude_block(&value["content_block"]),
+                    Some("content_block_delta") => {
+                        if let Some(text) = value["delta"]["text"].as_str() {
+                            self.text.push_str(text);
+                        }
+                    }
+                    Some("message_delta") => self.stop(&value["delta"]["stop_reason"]),
+                    _ => {}
+                }
+                if let Some(blocks) = value["content"].as_array() {
+                    for block in blocks {
+                        self.claude_block(block);
+                    }
+                }
+                self.stop(&value["stop_reason"]);
+            }
+            Provider::OpenAI => {
+                // Streams deliver each output item in its own `done` event.
+                if value["type"] == "response.output_item.done" {
+                    self.openai_item(&value["item"]);
+                } else if value["response"].is_object() {
+                    self.stop(&value["response"]["status"]);
+                } else if value["object"] == "response" {
+                    for item in value["output"].as_array().into_iter().flatten() {
+                        self.openai_item(item);
+                    }
+                    self.stop(&value["status"]);
+                }
+            }
+            Provider::Cerebras => {
+                for choice in value["choices"].as_array().into_iter().flatten() {
+                    let message = if choice["delta"].is_object() {
+                        &choice["delta"]
+                    } else {
+                        &choice["message"]
+                    };
+                    if let Some(text) = message["content"].as_str() {
+                        self.text.push_str(text);
+                    }
+                    for call in message["tool_calls"].as_array().into_iter().flatten() {
+                        self.tool(&call["function"]["name"]);
+                    }
+                    self.stop(&choice["finish_reason"]);
+                }
+            }
+            Provider::Gemini => {
+                // Streams are a JSON array when not using SSE.
+                if let Some(chunks) = value.as_array() {
+                    for chunk in chunks {
+                        self.absorb(provider, chunk);
+                    }
+                }
+                for candidate in value["candidates"].as_array().into_iter().flatten() {
+                    for part in candidate["content"]["parts"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                    {
+                        if let Some(text) = part["text"].as_str() {
+                            self.text.push_str(text);
+                        }
+                        self.tool(&part["functionCall"]["name"]);
+                    }
+                    self.stop(&candidate["finishReason"]);
+                }
+            }
+        }
+    }
+
+    fn claude_block(&mut self, block: &Value) {
+        match block["type"].as_str() {
+            Some("text") => {
+                if let Some(text) = block["text"].as_str() {
+                    self.text.push_str(text);
+                }
+            }
+            Some("tool_use") => self.tool(&block["name"]),
+            _ => {}
+        }
+    }
+
+    fn openai_item(&mut self, item: &Value) {
+        match item["type"].as_str() {
+            Some("function_call") => self.tool(&item["name"]),
+            Some("message") => {
+                for part in item["content"].as_array().into_iter().flatten() {
+                    if let Some(text) = part["text"].as_str() {
+                        self.text.push_str(text);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn tool(&mut self, name: &Value) {
+        if let Some(name) = name.as_str() {
+            self.tool_calls.push(name.to_string());
+        }
+    }
+
+    fn stop(&mut self, reason: &Value) {
+        if let Some(reason) = reason.as_str() {
+            self.stop_reason = Some(reason.to_string());
+        }
+    }
+}
+
+/// Error type of a provider error body, if it is one.
+fn error_kind(value: &Value) -> Option<String> {
+    let error = value.get("error")?;
+    ["type", "status", "code"]
+        .iter()
+        .find_map(|key| error[key].as_str())
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderMap;
+    use serde_json::json;
+
+    fn entry(provider: Provider, body: &Value) -> JournalEntry {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-attempt", "1".parse().unwrap());
+        JournalEntry::new(
+            Some(provider),
+            "POST",
+            "/v1/messages",
+            &headers,
+            body.to_string().as_bytes(),
+        )
+    }
+
+    #[test]
+    fn test_credentials_are_redacted() {
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", "Bearer sk-secret".parse().unwrap());
+        headers.insert("x-api-key", "sk-ant-secret".parse().unwrap());
+        headers.insert("x-goog-api-key", "AIza-secret".parse().unwrap());
+        headers.insert("x-attempt", "1".parse().unwrap());
+        let entry = JournalEntry::new(
+            Some(Provider::Gemini),
+            "POST",
+            "/v1beta/models/gemini-pro:streamGenerateContent?key=AIza-secret&alt=sse",
+            &headers,
+            b"",
+        );
+        assert_eq!(
+            entry.path,
+            "/v1beta/models/gemini-pro:streamGenerateContent?key=[REDACTED]&alt=sse"
+        );
+        assert_eq!(entry.model.as_deref(), Some("gemini-pro"));
+        for name in REDACTED_HEADERS {
+            assert_eq!(entry.headers[name], REDACTED);
+        }
+        assert_eq!(entry.headers["x-attempt"], "1");
+        assert_eq!(redact_query("/v1/messages"), "/v1/messages");
+    }
+
+    #[test]
+    fn test_journal_is_bounded() {
+        let journal = Journal::new();
+        for _ in 0..5 {
+            journal.record(entry(Provider::Claude, &json!({"model": "claude"})), 3);
+        }
+        let entries = journal.entries(&JournalFilter::default());
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].id, 3);
+        assert_eq!(journal.clear(), 3);
+        assert!(journal.is_empty());
+    }
+
+    #[test]
+    fn test_filters() {
+        let journal = Journal::new();
+        journal.record(
+            entry(
+                Provider::Claude,
+                &json!({"model": "a", "system": "Be brief"}),
+            ),
+            10,
+        );
+        journal.record(entry(Provider::OpenAI, &json!({"model": "b"})), 10);
+
+        let by_model = JournalFilter {
+            model: Some("a".to_string()),
+            ..JournalFilter::default()
+        };
+        assert_eq!(journal.entries(&by_model).len(), 1);
+
+        let by_body = JournalFilter {
+            contains: Some("Be brief".to_string()),
+            ..JournalFilter::default()
+        };
+        assert_eq!(journal.entries(&by_body)[0].id, 1);
+
+        let by_header = JournalFilter {
+            header: Some("X-Attempt: 1".to_string()),
+            provider: Some(Provider::OpenAI),
+            ..JournalFilter::default()
+        };
+        assert_eq!(journal.entries(&by_header)[0].id, 2);
+
+        let since = JournalFilter {
+            since_id: Some(1),
+            ..JournalFilter::default()
+        };
+        assert_eq!(journal.entries(&since).len(), 1);
+    }
+
+    #[test]
+    fn test_gemini_model_from_path() {
+        let entry = JournalEntry::new(
+            Some(Provider::Gemini),
+            "POST",
+            "/v1beta/models/gemini-2.0-flash:streamGenerateContent?alt=sse",
+            &HeaderMap::new(),
+            b"{}",
+        );
+        assert_eq!(entry.model.as_deref(), Some("gemini-2.0-flash"));
+    }
+
+    #[test]
+    fn test_summarize_claude_stream() {
+        let mut summary = ResponseSummary::streaming();
+        let events = [
+            "event: content_block_start\ndata: {\"type\":\"content_block_start\",\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n",
+            "event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"delta\":{\"type\":\"text_delta\",\"text\":\"Hi\"}}\n\n",
+            "event: content_block_start\ndata: {\"type\":\"content_block_start\",\"content_block\":{\"type\":\"tool_use\",\"name\":\"get_weather\"}}\n\n",
+            "event: message_delta\ndata: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"tool_use\"}}\n\n",
+        ];
+        for event in events {
+            summary.absorb_event(Some(Provider::Claude), event.as_bytes());
+        }
+        assert_eq!(summary.text, "Hi");
+        assert_eq!(summary.tool_calls, vec!["get_weather".to_string()]);
+        assert_eq!(summary.stop_reason.as_deref(), Some("tool_use"));
+    }
+
+    #[test]
+    fn test_summarize_error() {
+        let body = json!({"error": {"code": 429, "status": "RESOURCE_EXHAUSTED"}});
+        let summary =
+            ResponseSummary::from_body(Some(Provider::Gemini), body.to_string().as_bytes());
+        assert_eq!(summary.error.as_deref(), Some("RESOURCE_EXHAUSTED"));
+    }
+}
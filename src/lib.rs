@@ -26,35 +26,80 @@
 //! - **Gemini**: `/v1beta/models/{model}:generateContent` - Google Gemini API
 //! - **OpenAI**: `/v1/responses` - OpenAI Responses API
 
+pub mod admin;
 pub mod cerebras;
 pub mod claude;
 pub mod config;
 pub mod errors;
+pub mod fixtures;
 pub mod gemini;
 pub mod generator;
+pub mod journal;
 pub mod openai;
+pub mod proxy;
+pub mod ratelimit;
+pub mod schema;
+pub mod sse;
+pub mod timeout;
+pub mod timing;
+pub mod tools;
 
 pub use config::{Config, RuntimeState};
 pub use errors::Provider;
 pub use generator::ContentGenerator;
 
+use std::collections::HashMap;
+use std::future::Future;
 use std::sync::Arc;
 use std::time::Duration;
 
 use axum::{
-    extract::State,
-    http::{header::AUTHORIZATION, Request},
+    body::Bytes,
+    extract::{DefaultBodyLimit, Path, Query, State},
+    http::{
+        header::{AUTHORIZATION, CONTENT_TYPE},
+        HeaderMap, Request, StatusCode, Uri,
+    },
     middleware::{self, Next},
-    response::Response,
+    response::{IntoResponse, Response},
     routing::{get, post},
     Json, Router,
 };
+use config::KeyPolicy;
+use fixtures::{History, Reply, RequestInfo};
+use futures::StreamExt;
+use journal::{JournalEntry, ResponseSummary};
+use ratelimit::{Cost, LimitKey, RateLimitStatus};
+use serde::de::DeserializeOwned;
 use tokio::time::sleep;
+use tools::ToolChoice;
 use tower_http::cors::CorsLayer;
 
 type AppState = Arc<RuntimeState>;
 
-/// Creates the configured Axum router with all enabled providers.
+/// Request header that seeds the content generator for a single request.
+pub const SEED_HEADER: &str = "x-tokenipsum-seed";
+
+/// Request header asking for reply text of an exact number of output tokens,
+/// or `max` for the request's output token limit.
+pub const OUTPUT_TOKENS_HEADER: &str = "x-tokenipsum-output-tokens";
+
+/// Request header setting the number of parallel calls in a generated tool
+/// call reply.
+pub const TOOL_CALLS_HEADER: &str = "x-tokenipsum-tool-calls";
+
+/// Longest reply [`OUTPUT_TOKENS_HEADER`] asks for, whatever the header or the
+/// request's output limit say.
+const MAX_OUTPUT_TOKENS: u32 = 128_000;
+
+/// Largest request body accepted, axum's default `DefaultBodyLimit`. The
+/// journal buffers request bodies up to the same size.
+const BODY_LIMIT: usize = 2 * 1024 * 1024;
+
+/// Creates the Axum router for all providers plus the `/_admin` API.
+///
+/// Routes for every provider are registered; disabled providers answer 404
+/// until they are enabled in the live config.
 ///
 /// # Example
 ///
@@ -66,39 +111,241 @@ type AppState = Arc<RuntimeState>;
 /// let app = create_router(state);
 /// ```
 pub fn create_router(state: Arc<RuntimeState>) -> Router {
-    let config = &state.config;
-    let mut app = Router::new().route("/health", get(health));
+    Router::new()
+        .route("/health", get(health))
+        .route("/v1/chat/completions", post(cerebras_handler))
+        .route("/v1beta/models/{model_action}", post(gemini_handler))
+        .route("/v1/messages", post(claude_handler))
+        .route("/v1/responses", post(openai_handler))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            stream_fault_middleware,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            timeout_middleware,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            error_middleware,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            latency_middleware,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            provider_middleware,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            journal_middleware,
+        ))
+        .layer(DefaultBodyLimit::max(BODY_LIMIT))
+        .merge(admin::router())
+        .layer(CorsLayer::permissive())
+        .with_state(state)
+}
 
-    if config.providers.cerebras {
-        app = app.route("/v1/chat/completions", post(cerebras_handler));
+async fn health() -> &'static str {
+    "ok"
+}
+
+/// Record each request and a summary of its response in the journal.
+///
+/// The response body is passed through unbuffered and summarized once it has
+/// been sent, so streams keep their timing.
+async fn journal_middleware(
+    State(state): State<AppState>,
+    request: Request<axum::body::Body>,
+    next: Next,
+) -> Response {
+    let journal = state.config().journal.clone();
+    if !journal.enabled {
+        return next.run(request).await;
     }
 
-    if config.providers.gemini {
-        app = app.route("/v1beta/models/{model_action}", post(gemini_handler));
+    let (parts, body) = request.into_parts();
+    let provider = route_provider(parts.uri.path());
+    let Ok(bytes) = axum::body::to_bytes(body, BODY_LIMIT).await else {
+        return match provider {
+            Some(provider) => {
+                errors::error_response(config::ErrorType::RequestTooLarge, provider, None)
+            }
+            None => StatusCode::PAYLOAD_TOO_LARGE.into_response(),
+        };
+    };
+    let path = parts.uri.path_and_query().map_or("", |p| p.as_str());
+    let mut entry = JournalEntry::new(
+        provider,
+        parts.method.as_str(),
+        path,
+        &parts.headers,
+        &bytes,
+    );
+
+    let response = next
+        .run(Request::from_parts(parts, axum::body::Body::from(bytes)))
+        .await;
+    let stream = is_event_stream(&response);
+    entry.status = response.status().as_u16();
+    entry.response = ResponseSummary {
+        stream,
+        ..ResponseSummary::default()
+    };
+    let id = state.journal.record(entry, journal.capacity);
+
+    let (parts, body) = response.into_parts();
+    let tee = JournalTee {
+        body: body.into_data_stream(),
+        state,
+        id,
+        provider,
+        buffer: Vec::new(),
+        summary: ResponseSummary::streaming(),
+        stream,
+    };
+    let body = futures::stream::unfold(Some(tee), |tee| async move {
+        let mut tee = tee?;
+        match tee.body.next().await {
+            Some(Ok(chunk)) => {
+                tee.push(&chunk);
+                Some((Ok(chunk), Some(tee)))
+            }
+            Some(Err(e)) => Some((Err(e), None)),
+            None => {
+                tee.finish();
+                None
+            }
+        }
+    });
+    Response::from_parts(parts, axum::body::Body::from_stream(body))
+}
+
+/// Copies a response body into the journal summary as it is sent.
+struct JournalTee {
+    body: axum::body::BodyDataStream,
+    state: AppState,
+    id: u64,
+    provider: Option<Provider>,
+    buffer: Vec<u8>,
+    summary: ResponseSummary,
+    stream: bool,
+}
+
+impl JournalTee {
+    fn push(&mut self, chunk: &[u8]) {
+        self.buffer.extend_from_slice(chunk);
+        if self.stream {
+            while let Some(end) = proxy::event_end(&self.buffer) {
+                let event: Vec<u8> = self.buffer.drain(..end).collect();
+                self.summary.absorb_event(self.provider, &event);
+            }
+        }
     }
 
-    if config.providers.claude {
-        app = app.route("/v1/messages", post(claude_handler));
+    fn finish(mut self) {
+        let summary = if self.stream {
+            self.summary.absorb_event(self.provider, &self.buffer);
+            self.summary.complete = true;
+            self.summary
+        } else {
+            ResponseSummary::from_body(self.provider, &self.buffer)
+        };
+        self.state.journal.update(self.id, summary);
     }
+}
+
+/// Whether a response is a server-sent event stream.
+fn is_event_stream(response: &Response) -> bool {
+    response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|ct| ct.to_str().ok())
+        .is_some_and(|ct| ct.starts_with("text/event-stream"))
+}
+
+/// Inject a mid-stream fault into streamed responses, from the
+/// [`sse::STREAM_FAULT_HEADER`] header or the live config.
+async fn stream_fault_middleware(
+    State(state): State<AppState>,
+    request: Request<axum::body::Body>,
+    next: Next,
+) -> Response {
+    let provider = route_provider(request.uri().path());
+    let requested = request
+        .headers()
+        .get(sse::STREAM_FAULT_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| {
+            let default_after = state.config().stream_faults.after_events;
+            sse::StreamFault::parse(value, default_after)
+                .map_err(|e| {
+                    tracing::warn!(
+                        "Ignoring invalid {} header: {}",
+                        sse::STREAM_FAULT_HEADER,
+                        e
+                    );
+                })
+                .ok()
+        });
 
-    if config.providers.openai {
-        app = app.route("/v1/responses", post(openai_handler));
+    let response = next.run(request).await;
+    let Some(provider) = provider else {
+        return response;
+    };
+    if !is_event_stream(&response) {
+        return response;
     }
+    match requested.or_else(|| state.stream_fault()) {
+        Some(fault) => sse::inject(response, provider, fault),
+        None => response,
+    }
+}
+
+/// Hold the connection open instead of responding normally, from the
+/// [`timeout::TIMEOUT_HEADER`] header or the live config.
+async fn timeout_middleware(
+    State(state): State<AppState>,
+    request: Request<axum::body::Body>,
+    next: Next,
+) -> Response {
+    let Some(provider) = route_provider(request.uri().path()) else {
+        return next.run(request).await;
+    };
+    let requested = request
+        .headers()
+        .get(timeout::TIMEOUT_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| {
+            let default_interval =
+                Duration::from_millis(state.config().timeouts.rule(provider).drip_interval_ms);
+            timeout::Timeout::parse(value, default_interval)
+                .map_err(|e| {
+                    tracing::warn!("Ignoring invalid {} header: {}", timeout::TIMEOUT_HEADER, e);
+                })
+                .ok()
+        });
+    let Some(timeout) = requested.or_else(|| state.timeout(provider)) else {
+        return next.run(request).await;
+    };
 
-    app.layer(middleware::from_fn_with_state(
-        state.clone(),
-        error_middleware,
-    ))
-    .layer(middleware::from_fn_with_state(
-        state.clone(),
-        latency_middleware,
-    ))
-    .layer(CorsLayer::permissive())
-    .with_state(state)
+    timeout.before_response().await;
+    timeout.apply(next.run(request).await)
 }
 
-async fn health() -> &'static str {
-    "ok"
+/// Answer 404 for routes of providers disabled in the live config.
+async fn provider_middleware(
+    State(state): State<AppState>,
+    request: Request<axum::body::Body>,
+    next: Next,
+) -> Response {
+    if let Some(provider) = route_provider(request.uri().path()) {
+        if !state.config().providers.enabled(provider) {
+            return StatusCode::NOT_FOUND.into_response();
+        }
+    }
+    next.run(request).await
 }
 
 async fn latency_middleware(
@@ -120,27 +367,44 @@ async fn error_middleware(
 ) -> Response {
     state.increment_requests();
 
-    if state.config.auth.require_auth {
-        let auth = request
-            .headers()
-            .get(AUTHORIZATION)
-            .and_then(|h| h.to_str().ok())
-            .map(|s| s.trim_start_matches("Bearer ").trim());
+    let require_auth = state.config().auth.require_auth;
+    if require_auth {
+        let provider = provider_from_path(request.uri().path());
+        if let Some(response) = authenticate(&state, provider, request.headers(), request.uri()) {
+            return response;
+        }
+    }
 
-        if !state.is_valid_key(auth) {
-            let provider = provider_from_path(request.uri().path());
-            return errors::error_response(config::ErrorType::Unauthorized, provider);
+    let key_policy = route_provider(request.uri().path()).and_then(|provider| {
+        let key = api_key(provider, request.headers(), request.uri())?;
+        let policy = state.key_policy(&key)?;
+        Some((provider, key, policy))
+    });
+    if let Some((provider, key, policy)) = key_policy {
+        if let Some(response) = apply_key_policy(provider, key, &policy).await {
+            return response;
         }
     }
 
     if let Some(error) = state.should_error() {
         let provider = provider_from_path(request.uri().path());
-        return errors::error_response(error, provider);
+        return errors::error_response(error, provider, None);
     }
 
     next.run(request).await
 }
 
+/// Provider serving `path`, or `None` for routes that aren't provider endpoints.
+fn route_provider(path: &str) -> Option<Provider> {
+    match path {
+        "/v1/chat/completions" => Some(Provider::Cerebras),
+        "/v1/messages" => Some(Provider::Claude),
+        "/v1/responses" => Some(Provider::OpenAI),
+        _ if path.starts_with("/v1beta/models/") => Some(Provider::Gemini),
+        _ => None,
+    }
+}
+
 fn provider_from_path(path: &str) -> Provider {
     if path.contains("/v1beta/models") {
         Provider::Gemini
@@ -153,33 +417,367 @@ fn provider_from_path(path: &str) -> Provider {
     }
 }
 
+/// Read the per-request seed from [`SEED_HEADER`], if present and valid.
+fn seed_override(headers: &HeaderMap) -> Option<u64> {
+    let value = headers.get(SEED_HEADER)?.to_str().ok()?;
+    match value.trim().parse() {
+        Ok(seed) => Some(seed),
+        Err(_) => {
+            tracing::warn!("Ignoring invalid {} header: {:?}", SEED_HEADER, value);
+            None
+        }
+    }
+}
+
+/// Read the requested reply length from [`OUTPUT_TOKENS_HEADER`], if present
+/// and valid, resolving `max` to `max_output_tokens`. The length is capped at
+/// `max_output_tokens` and [`MAX_OUTPUT_TOKENS`].
+fn output_tokens_override(headers: &HeaderMap, max_output_tokens: Option<u32>) -> Option<u32> {
+    let value = headers.get(OUTPUT_TOKENS_HEADER)?.to_str().ok()?.trim();
+    let tokens = if value.eq_ignore_ascii_case("max") {
+        max_output_tokens
+    } else {
+        value.parse().ok()
+    };
+    if tokens.is_none() {
+        tracing::warn!(
+            "Ignoring invalid {} header: {:?}",
+            OUTPUT_TOKENS_HEADER,
+            value
+        );
+    }
+    tokens.map(|tokens| {
+        tokens
+            .min(max_output_tokens.unwrap_or(u32::MAX))
+            .min(MAX_OUTPUT_TOKENS)
+    })
+}
+
+/// Read the number of parallel tool calls from [`TOOL_CALLS_HEADER`], if
+/// present and a positive number.
+fn tool_calls_override(headers: &HeaderMap) -> Option<usize> {
+    let value = headers.get(TOOL_CALLS_HEADER)?.to_str().ok()?;
+    match value.trim().parse() {
+        Ok(calls) if calls > 0 => Some(calls),
+        _ => {
+            tracing::warn!("Ignoring invalid {} header: {:?}", TOOL_CALLS_HEADER, value);
+            None
+        }
+    }
+}
+
+/// Script the generator from the first matching fixture.
+/// Returns the response to send instead when the fixture is an error.
+fn apply_fixture(
+    state: &RuntimeState,
+    info: &RequestInfo,
+    gen: &mut ContentGenerator,
+) -> Option<Response> {
+    let reply = state.fixtures().find(info).cloned();
+    match reply {
+        Some(Reply::Error(error)) => Some(errors::error_response(
+            error,
+            info.provider,
+            Some(info.model),
+        )),
+        reply => {
+            gen.script = reply;
+            None
+        }
+    }
+}
+
+/// API key sent with a request, from the header or query parameter the
+/// provider's API reads it from: `Authorization: Bearer` for OpenAI and
+/// Cerebras, `x-api-key` for Anthropic, and `x-goog-api-key` or `?key=` for Gemini.
+fn api_key(provider: Provider, headers: &HeaderMap, uri: &Uri) -> Option<String> {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+    let key = match provider {
+        Provider::Cerebras | Provider::OpenAI => header(AUTHORIZATION.as_str())
+            .and_then(|auth| auth.strip_prefix("Bearer "))
+            .map(str::to_string),
+        Provider::Claude => header("x-api-key").map(str::to_string),
+        Provider::Gemini => header("x-goog-api-key").map(str::to_string).or_else(|| {
+            let Query(mut query) = Query::<HashMap<String, String>>::try_from_uri(uri).ok()?;
+            query.remove("key")
+        }),
+    };
+    key.map(|key| key.trim().to_string())
+        .filter(|key| !key.is_empty())
+}
+
+/// Check the request's credentials the way `provider`'s API does, returning
+/// its error response if they are missing or invalid.
+fn authenticate(
+    state: &RuntimeState,
+    provider: Provider,
+    headers: &HeaderMap,
+    uri: &Uri,
+) -> Option<Response> {
+    let failure = match api_key(provider, headers, uri) {
+        None => errors::AuthFailure::MissingKey,
+        Some(key) if !state.is_valid_key(Some(&key)) => errors::AuthFailure::InvalidKey(key),
+        Some(_) => {
+            if provider == Provider::Claude && !headers.contains_key("anthropic-version") {
+                return Some(errors::missing_anthropic_version());
+            }
+            return None;
+        }
+    };
+    Some(errors::auth_error(provider, &failure))
+}
+
+/// Enforce the parts of a key's policy that don't depend on the request body,
+/// returning the response to send instead if the request is rejected.
+async fn apply_key_policy(provider: Provider, key: String, policy: &KeyPolicy) -> Option<Response> {
+    if !policy.allows_provider(provider) {
+        return Some(errors::auth_error(
+            provider,
+            &errors::AuthFailure::InvalidKey(key),
+        ));
+    }
+    if policy.latency_ms > 0 {
+        sleep(Duration::from_millis(policy.latency_ms)).await;
+    }
+    if let Some(error) = policy.force_error.error_type() {
+        return Some(errors::error_response(error, provider, None));
+    }
+    if policy.out_of_credits {
+        return Some(errors::insufficient_quota(provider));
+    }
+    None
+}
+
+/// Check the key may use `model` and count the request against the rate
+/// limiter. Returns the error to send if the request is rejected, or the
+/// limiter state for the response headers if it was admitted.
+fn admit(
+    state: &RuntimeState,
+    provider: Provider,
+    model: &str,
+    api_key: Option<String>,
+    input_tokens: u32,
+    output_tokens: u32,
+) -> Result<Option<RateLimitStatus>, Box<Response>> {
+    if let Some(policy) = api_key.as_deref().and_then(|key| state.key_policy(key)) {
+        if !policy.allows_model(model) {
+            return Err(Box::new(errors::model_not_found(provider, model)));
+        }
+    }
+    let key = LimitKey {
+        api_key: api_key.unwrap_or_default(),
+        provider,
+        model: model.to_string(),
+    };
+    let cost = Cost {
+        input_tokens: u64::from(input_tokens),
+        output_tokens: u64::from(output_tokens),
+    };
+    match state.check_rate_limit(key, cost) {
+        Some(status) if status.exceeded.is_some() => {
+            Err(Box::new(errors::rate_limit(provider, Some(&status))))
+        }
+        status => Ok(status),
+    }
+}
+
+/// Send the limiter's remaining capacity with an admitted request's response,
+/// as the real APIs do, so clients can throttle before hitting a 429.
+fn with_rate_limit_headers(
+    mut response: Response,
+    provider: Provider,
+    status: Option<&RateLimitStatus>,
+) -> Response {
+    if let Some(status) = status {
+        status.apply_headers(provider, response.headers_mut());
+    }
+    response
+}
+
+/// Record or replay through the proxy when it is enabled in the live config.
+async fn proxy(
+    state: &RuntimeState,
+    provider: Provider,
+    uri: &Uri,
+    headers: &HeaderMap,
+    body: &Bytes,
+) -> Option<Response> {
+    let config = {
+        let config = state.config();
+        if config.proxy.mode == proxy::ProxyMode::Off {
+            return None;
+        }
+        config.proxy.clone()
+    };
+    state
+        .proxy
+        .handle(&config, provider, uri, headers, body)
+        .await
+}
+
+/// What the shared handler pipeline reads from a parsed provider request.
+pub trait ProviderRequest: DeserializeOwned {
+    /// Estimated prompt tokens, as reported in the response usage.
+    fn input_tokens(&self) -> u32;
+
+    /// Requested output token limit.
+    fn max_output_tokens(&self) -> Option<u32>;
+
+    /// Tool calling constraints.
+    fn tool_choice(&self) -> ToolChoice;
+
+    /// Names of the declared tools.
+    fn tool_names(&self) -> Vec<&str>;
+
+    /// Text of the most recent user message.
+    fn last_user_text(&self) -> Option<&str>;
+
+    /// Turn number and pending tool results.
+    fn history(&self) -> History;
+}
+
+/// Pipeline shared by the provider handlers: proxying, parsing, admission,
+/// tool choice validation, generator setup and fixtures. `model` names the
+/// requested model and `respond` generates the reply.
+async fn handle<R, F>(
+    state: &RuntimeState,
+    provider: Provider,
+    uri: &Uri,
+    headers: &HeaderMap,
+    body: &Bytes,
+    model: impl FnOnce(&R) -> String,
+    respond: impl FnOnce(ContentGenerator, R) -> F,
+) -> Response
+where
+    R: ProviderRequest,
+    F: Future<Output = Response>,
+{
+    if let Some(response) = proxy(state, provider, uri, headers, body).await {
+        return response;
+    }
+
+    let mut gen = state.generator(seed_override(headers), body);
+    let Json(req): Json<R> = match Json::from_bytes(body) {
+        Ok(req) => req,
+        Err(rejection) => return rejection.into_response(),
+    };
+
+    let model = model(&req);
+    let info = RequestInfo {
+        provider,
+        model: &model,
+        path: uri.path(),
+        headers,
+        last_user_text: req.last_user_text(),
+        history: req.history(),
+    };
+    let target_tokens = output_tokens_override(headers, req.max_output_tokens());
+    // Reserve the output limit, or the reply's length when there is none, so
+    // output token limits apply to every request.
+    let output_tokens = req
+        .max_output_tokens()
+        .or(target_tokens)
+        .unwrap_or(ContentGenerator::PARAGRAPH_TOKENS);
+    let limit = match admit(
+        state,
+        provider,
+        &model,
+        api_key(provider, headers, uri),
+        req.input_tokens(),
+        output_tokens,
+    ) {
+        Ok(limit) => limit,
+        Err(response) => return *response,
+    };
+    if let Err(error) = req.tool_choice().check(&req.tool_names()) {
+        return errors::invalid_tool_choice(provider, &error);
+    }
+    gen.timing = state.timing(provider, &model);
+    gen.target_tokens = target_tokens;
+    gen.tool_calls = tool_calls_override(headers).unwrap_or(gen.tool_calls);
+    if let Some(response) = apply_fixture(state, &info, &mut gen) {
+        return response;
+    }
+
+    let response = respond(gen, req).await;
+    with_rate_limit_headers(response, provider, limit.as_ref())
+}
+
 async fn cerebras_handler(
-    State(_state): State<AppState>,
-    body: Json<cerebras::ChatCompletionRequest>,
+    State(state): State<AppState>,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
 ) -> Response {
-    cerebras::chat_completions(body).await
+    handle(
+        &state,
+        Provider::Cerebras,
+        &uri,
+        &headers,
+        &body,
+        |req: &cerebras::ChatCompletionRequest| req.model.clone(),
+        |gen, req| cerebras::chat_completions(gen, Json(req)),
+    )
+    .await
 }
 
 async fn gemini_handler(
-    State(_state): State<AppState>,
-    path: axum::extract::Path<String>,
-    body: Json<gemini::GenerateContentRequest>,
+    State(state): State<AppState>,
+    Path(model_action): Path<String>,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
 ) -> Response {
-    gemini::handle_model_action(path, body).await
+    let model = model_action
+        .rsplit_once(':')
+        .map_or(model_action.as_str(), |(model, _)| model)
+        .to_string();
+    handle(
+        &state,
+        Provider::Gemini,
+        &uri,
+        &headers,
+        &body,
+        |_: &gemini::GenerateContentRequest| model,
+        |gen, req| gemini::handle_model_action(gen, Path(model_action), Json(req)),
+    )
+    .await
 }
 
 async fn claude_handler(
-    State(_state): State<AppState>,
-    body: Json<claude::MessagesRequest>,
+    State(state): State<AppState>,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
 ) -> Response {
-    claude::messages(body).await
+    handle(
+        &state,
+        Provider::Claude,
+        &uri,
+        &headers,
+        &body,
+        |req: &claude::MessagesRequest| req.model.clone(),
+        |gen, req| claude::messages(gen, Json(req)),
+    )
+    .await
 }
 
 async fn openai_handler(
-    State(_state): State<AppState>,
-    body: Json<openai::ResponsesRequest>,
+    State(state): State<AppState>,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
 ) -> Response {
-    openai::responses(body).await
+    handle(
+        &state,
+        Provider::OpenAI,
+        &uri,
+        &headers,
+        &body,
+        |req: &openai::ResponsesRequest| req.model.clone(),
+        |gen, req| openai::responses(gen, Json(req)),
+    )
+    .await
 }
 
 #[cfg(test)]
@@ -323,6 +921,866 @@ mod tests {
         assert_eq!(response.status(), StatusCode::OK);
     }
 
+    #[tokio::test]
+    async fn test_deterministic_responses() {
+        let mut config = Config::default();
+        config.content.deterministic = true;
+        let state = RuntimeState::new(config);
+
+        let requests = [
+            (
+                "/v1/chat/completions",
+                serde_json::json!({
+                    "model": "llama-3.3-70b",
+                    "messages": [{"role": "user", "content": "Hello"}],
+                    "stream": true
+                }),
+            ),
+            (
+                "/v1/messages",
+                serde_json::json!({
+                    "model": "claude-3-haiku",
+                    "max_tokens": 100,
+                    "thinking": {"type": "enabled", "budget_tokens": 1024},
+                    "messages": [{"role": "user", "content": "Hello"}]
+                }),
+            ),
+            (
+                "/v1beta/models/gemini-pro:generateContent",
+                serde_json::json!({
+                    "contents": [{"role": "user", "parts": [{"text": "Hello"}]}]
+                }),
+            ),
+            (
+                "/v1/responses",
+                serde_json::json!({"model": "gpt-4o", "input": "Hello"}),
+            ),
+        ];
+
+        for (path, body) in requests {
+            let mut bodies = Vec::new();
+            for _ in 0..2 {
+                let response = create_router(state.clone())
+                    .oneshot(
+                        Request::post(path)
+                            .header("content-type", "application/json")
+                            .body(Body::from(body.to_string()))
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                bodies.push(response.into_body().collect().await.unwrap().to_bytes());
+            }
+            assert_eq!(
+                bodies[0], bodies[1],
+                "non-deterministic response for {path}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_seed_header() {
+        let state = RuntimeState::new(Config::default());
+        let body = serde_json::json!({
+            "model": "claude-3-haiku",
+            "max_tokens": 100,
+            "messages": [{"role": "user", "content": "Hello"}]
+        });
+
+        let mut bodies = Vec::new();
+        for seed in ["7", "7", "8"] {
+            let response = create_router(state.clone())
+                .oneshot(
+                    Request::post("/v1/messages")
+                        .header("content-type", "application/json")
+                        .header(SEED_HEADER, seed)
+                        .body(Body::from(body.to_string()))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            bodies.push(response.into_body().collect().await.unwrap().to_bytes());
+        }
+
+        assert_eq!(bodies[0], bodies[1]);
+        assert_ne!(bodies[0], bodies[2]);
+    }
+
+    #[test]
+    fn test_seed_override_parsing() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(seed_override(&headers), None);
+        headers.insert(SEED_HEADER, "42".parse().unwrap());
+        assert_eq!(seed_override(&headers), Some(42));
+        headers.insert(SEED_HEADER, "not-a-number".parse().unwrap());
+        assert_eq!(seed_override(&headers), None);
+    }
+
+    #[test]
+    fn test_output_tokens_override_parsing() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(output_tokens_override(&headers, Some(100)), None);
+        headers.insert(OUTPUT_TOKENS_HEADER, "40".parse().unwrap());
+        assert_eq!(output_tokens_override(&headers, Some(100)), Some(40));
+        // Capped at the request's limit, and at MAX_OUTPUT_TOKENS without one.
+        headers.insert(OUTPUT_TOKENS_HEADER, "4000".parse().unwrap());
+        assert_eq!(output_tokens_override(&headers, Some(100)), Some(100));
+        headers.insert(OUTPUT_TOKENS_HEADER, "4000000000".parse().unwrap());
+        assert_eq!(
+            output_tokens_override(&headers, None),
+            Some(MAX_OUTPUT_TOKENS)
+        );
+        assert_eq!(
+            output_tokens_override(&headers, Some(u32::MAX)),
+            Some(MAX_OUTPUT_TOKENS)
+        );
+        headers.insert(OUTPUT_TOKENS_HEADER, "max".parse().unwrap());
+        assert_eq!(output_tokens_override(&headers, Some(100)), Some(100));
+        assert_eq!(output_tokens_override(&headers, None), None);
+        headers.insert(OUTPUT_TOKENS_HEADER, "lots".parse().unwrap());
+        assert_eq!(output_tokens_override(&headers, Some(100)), None);
+    }
+
+    #[test]
+    fn test_tool_calls_override_parsing() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(tool_calls_override(&headers), None);
+        headers.insert(TOOL_CALLS_HEADER, "3".parse().unwrap());
+        assert_eq!(tool_calls_override(&headers), Some(3));
+        headers.insert(TOOL_CALLS_HEADER, "0".parse().unwrap());
+        assert_eq!(tool_calls_override(&headers), None);
+    }
+
+    #[tokio::test]
+    async fn test_parallel_tool_calls() {
+        let state = RuntimeState::new(Config::default());
+        let json = |body: &str| serde_json::from_str::<serde_json::Value>(body).unwrap();
+        let three = [(TOOL_CALLS_HEADER, "3")];
+        let expected = ["get_weather", "get_time", "get_weather"];
+
+        let claude = |stream| {
+            serde_json::json!({
+                "model": "claude-haiku",
+                "max_tokens": 200,
+                "stream": stream,
+                "messages": [{"role": "user", "content": "Weather and time in Tokyo?"}],
+                "tools": [{"name": "get_weather"}, {"name": "get_time"}]
+            })
+        };
+        let (_, body) = post_json(&state, "/v1/messages", &claude(false), &three).await;
+        let body = json(&body);
+        let names: Vec<_> = body["content"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|b| &b["name"])
+            .collect();
+        assert_eq!(names, expected);
+        let (_, body) = post_json(&state, "/v1/messages", &claude(true), &three).await;
+        assert!(
+            body.contains(r#""index":2,"type":"content_block_start""#),
+            "{body}"
+        );
+
+        let cerebras = |stream, parallel: bool| {
+            serde_json::json!({
+                "model": "llama3.1-8b",
+                "stream": stream,
+                "parallel_tool_calls": parallel,
+                "messages": [{"role": "user", "content": "Weather and time in Tokyo?"}],
+                "tools": [
+                    {"type": "function", "function": {"name": "get_weather"}},
+                    {"type": "function", "function": {"name": "get_time"}}
+                ]
+            })
+        };
+        let path = "/v1/chat/completions";
+        let (_, body) = post_json(&state, path, &cerebras(false, true), &three).await;
+        let body = json(&body);
+        let calls = body["choices"][0]["message"]["tool_calls"]
+            .as_array()
+            .unwrap();
+        let names: Vec<_> = calls.iter().map(|c| &c["function"]["name"]).collect();
+        assert_eq!(names, expected);
+        let (_, body) = post_json(&state, path, &cerebras(true, true), &three).await;
+        assert!(body.contains(r#""tool_calls":[{"function":{"arguments""#));
+        assert!(body.contains(r#""index":2,"type":"function""#), "{body}");
+        let (_, body) = post_json(&state, path, &cerebras(false, false), &three).await;
+        let body = json(&body);
+        assert_eq!(
+            body["choices"][0]["message"]["tool_calls"]
+                .as_array()
+                .unwrap()
+                .len(),
+            1
+        );
+
+        let gemini = serde_json::json!({
+            "contents": [{"role": "user", "parts": [{"text": "Weather and time in Tokyo?"}]}],
+            "tools": [{"functionDeclarations": [{"name": "get_weather"}, {"name": "get_time"}]}]
+        });
+        let path = "/v1beta/models/gemini-pro:generateContent";
+        let (_, body) = post_json(&state, path, &gemini, &three).await;
+        let body = json(&body);
+        let parts = body["candidates"][0]["content"]["parts"]
+            .as_array()
+            .unwrap();
+        let names: Vec<_> = parts.iter().map(|p| &p["functionCall"]["name"]).collect();
+        assert_eq!(names, expected);
+
+        let openai = |stream| {
+            serde_json::json!({
+                "model": "gpt-4o",
+                "stream": stream,
+                "input": "Weather and time in Tokyo?",
+                "tools": [
+                    {"type": "function", "name": "get_weather"},
+                    {"type": "function", "name": "get_time"}
+                ]
+            })
+        };
+        let (_, body) = post_json(&state, "/v1/responses", &openai(false), &three).await;
+        let body = json(&body);
+        let names: Vec<_> = body["output"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|i| &i["name"])
+            .collect();
+        assert_eq!(names, expected);
+        let (_, body) = post_json(&state, "/v1/responses", &openai(true), &three).await;
+        assert!(body.contains(r#""output_index":2"#), "{body}");
+    }
+
+    #[tokio::test]
+    async fn test_tool_choice() {
+        let state = RuntimeState::new(Config::default());
+        let json = |body: &str| serde_json::from_str::<serde_json::Value>(body).unwrap();
+        let three = [(TOOL_CALLS_HEADER, "3")];
+
+        let claude = |prompt: &str, tool_choice: serde_json::Value| {
+            serde_json::json!({
+                "model": "claude-haiku",
+                "max_tokens": 200,
+                "messages": [{"role": "user", "content": prompt}],
+                "tools": [{"name": "get_weather"}, {"name": "get_time"}],
+                "tool_choice": tool_choice
+            })
+        };
+        let none = claude("Weather in Tokyo?", serde_json::json!({"type": "none"}));
+        let (_, body) = post_json(&state, "/v1/messages", &none, &[]).await;
+        assert_eq!(json(&body)["stop_reason"], "end_turn");
+        let any = claude("Hello", serde_json::json!({"type": "any"}));
+        let (_, body) = post_json(&state, "/v1/messages", &any, &[]).await;
+        assert_eq!(json(&body)["stop_reason"], "tool_use");
+        let tool = claude(
+            "Hello",
+            serde_json::json!({"type": "tool", "name": "get_time", "disable_parallel_tool_use": true}),
+        );
+        let (_, body) = post_json(&state, "/v1/messages", &tool, &three).await;
+        let body = json(&body);
+        assert_eq!(body["content"].as_array().unwrap().len(), 1);
+        assert_eq!(body["content"][0]["name"], "get_time");
+
+        let cerebras = |prompt: &str, tool_choice: serde_json::Value| {
+            serde_json::json!({
+                "model": "llama3.1-8b",
+                "messages": [{"role": "user", "content": prompt}],
+                "tools": [
+                    {"type": "function", "function": {"name": "get_weather"}},
+                    {"type": "function", "function": {"name": "get_time"}}
+                ],
+                "tool_choice": tool_choice
+            })
+        };
+        let path = "/v1/chat/completions";
+        let none = cerebras("Weather in Tokyo?", serde_json::json!("none"));
+        let (_, body) = post_json(&state, path, &none, &[]).await;
+        assert_eq!(json(&body)["choices"][0]["finish_reason"], "stop");
+        let function = cerebras(
+            "Hello",
+            serde_json::json!({"type": "function", "function": {"name": "get_time"}}),
+        );
+        let (_, body) = post_json(&state, path, &function, &[]).await;
+        let body = json(&body);
+        assert_eq!(body["choices"][0]["finish_reason"], "tool_calls");
+        assert_eq!(
+            body["choices"][0]["message"]["tool_calls"][0]["function"]["name"],
+            "get_time"
+        );
+
+        let gemini = |mode: &str| {
+            serde_json::json!({
+                "contents": [{"role": "user", "parts": [{"text": "Hello"}]}],
+                "tools": [{"functionDeclarations": [{"name": "get_weather"}, {"name": "get_time"}]}],
+                "toolConfig": {"functionCallingConfig": {
+                    "mode": mode, "allowedFunctionNames": ["get_time"]
+                }}
+            })
+        };
+        let path = "/v1beta/models/gemini-pro:generateContent";
+        let (_, body) = post_json(&state, path, &gemini("ANY"), &three).await;
+        let body = json(&body);
+        let parts = body["candidates"][0]["content"]["parts"]
+            .as_array()
+            .unwrap();
+        assert_eq!(parts.len(), 3);
+        assert!(parts
+            .iter()
+            .all(|p| p["functionCall"]["name"] == "get_time"));
+        let (_, body) = post_json(&state, path, &gemini("AUTO"), &[]).await;
+        assert!(json(&body)["candidates"][0]["content"]["parts"][0]["text"].is_string());
+
+        let openai = |tool_choice: serde_json::Value| {
+            serde_json::json!({
+                "model": "gpt-4o",
+                "input": "Hello",
+                "tools": [
+                    {"type": "function", "name": "get_weather"},
+                    {"type": "function", "name": "get_time"}
+                ],
+                "tool_choice": tool_choice
+            })
+        };
+        let required = openai(serde_json::json!("required"));
+        let (_, body) = post_json(&state, "/v1/responses", &required, &[]).await;
+        let body = json(&body);
+        assert_eq!(body["output"][0]["name"], "get_weather");
+        assert_eq!(body["tool_choice"], "required");
+        let allowed = openai(serde_json::json!({
+            "type": "allowed_tools",
+            "mode": "required",
+            "tools": [{"type": "function", "name": "get_time"}]
+        }));
+        let (_, body) = post_json(&state, "/v1/responses", &allowed, &[]).await;
+        assert_eq!(json(&body)["output"][0]["name"], "get_time");
+    }
+
+    #[tokio::test]
+    async fn test_invalid_tool_choice() {
+        let state = RuntimeState::new(Config::default());
+        let json = |body: &str| serde_json::from_str::<serde_json::Value>(body).unwrap();
+
+        let claude = serde_json::json!({
+            "model": "claude-haiku",
+            "max_tokens": 200,
+            "messages": [{"role": "user", "content": "Hello"}],
+            "tools": [{"name": "get_weather"}],
+            "tool_choice": {"type": "tool", "name": "get_time"}
+        });
+        let (status, body) = post_json(&state, "/v1/messages", &claude, &[]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let body = json(&body);
+        assert_eq!(body["error"]["type"], "invalid_request_error");
+        assert!(body["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("get_time"));
+        let mut unnamed = claude.clone();
+        unnamed["tool_choice"] = serde_json::json!({"type": "tool"});
+        let (status, body) = post_json(&state, "/v1/messages", &unnamed, &[]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(json(&body)["error"]["type"], "invalid_request_error");
+
+        let cerebras = serde_json::json!({
+            "model": "llama3.1-8b",
+            "messages": [{"role": "user", "content": "Hello"}],
+            "tool_choice": "required"
+        });
+        let (status, body) = post_json(&state, "/v1/chat/completions", &cerebras, &[]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(json(&body)["error"]["param"], "tool_choice");
+
+        let gemini = serde_json::json!({
+            "contents": [{"role": "user", "parts": [{"text": "Hello"}]}],
+            "tools": [{"functionDeclarations": [{"name": "get_weather"}]}],
+            "toolConfig": {"functionCallingConfig": {
+                "mode": "ANY", "allowedFunctionNames": ["get_time"]
+            }}
+        });
+        let path = "/v1beta/models/gemini-pro:generateContent";
+        let (status, body) = post_json(&state, path, &gemini, &[]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(json(&body)["error"]["status"], "INVALID_ARGUMENT");
+
+        let openai = serde_json::json!({
+            "model": "gpt-4o",
+            "input": "What is the weather in Tokyo?",
+            "tools": [{"type": "function", "name": "get_weather"}],
+            "tool_choice": {"type": "function", "name": "get_time"}
+        });
+        let (status, _) = post_json(&state, "/v1/responses", &openai, &[]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        // Tools without function declarations are never called.
+        let search = serde_json::json!({
+            "contents": [{"role": "user", "parts": [{"text": "Search for Tokyo"}]}],
+            "tools": [{"googleSearch": {}}]
+        });
+        let (status, body) = post_json(&state, path, &search, &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(json(&body)["candidates"][0]["content"]["parts"][0]["text"].is_string());
+    }
+
+    #[tokio::test]
+    async fn test_tool_trigger_config() {
+        let state = RuntimeState::new(Config {
+            tool_trigger: toml::from_str(
+                r#"
+                keywords = ["wetter"]
+                match_tools = true
+                "#,
+            )
+            .unwrap(),
+            ..Config::default()
+        });
+        let body = |prompt: &str| {
+            serde_json::json!({
+                "model": "llama3.1-8b",
+                "messages": [{"role": "user", "content": prompt}],
+                "tools": [{"type": "function", "function": {
+                    "name": "uhrzeit_abfragen",
+                    "description": "Liefert die aktuelle Uhrzeit"
+                }}]
+            })
+        };
+        let finish_reason = |body: &str| {
+            serde_json::from_str::<serde_json::Value>(body).unwrap()["choices"][0]["finish_reason"]
+                .as_str()
+                .unwrap()
+                .to_string()
+        };
+        let path = "/v1/chat/completions";
+
+        for prompt in ["Wie ist das Wetter in Berlin?", "Welche Uhrzeit haben wir?"] {
+            let (_, text) = post_json(&state, path, &body(prompt), &[]).await;
+            assert_eq!(finish_reason(&text), "tool_calls", "{prompt}");
+        }
+        let (_, text) = post_json(&state, path, &body("What is the weather?"), &[]).await;
+        assert_eq!(finish_reason(&text), "stop");
+    }
+
+    #[tokio::test]
+    async fn test_argument_streaming() {
+        let state = RuntimeState::new(Config {
+            fixtures: toml::from_str(
+                r#"
+                [[rules]]
+                response = { tool_call = { name = "note", arguments = { text = "Grüße \"😀\"\n" } } }
+                "#,
+            )
+            .unwrap(),
+            argument_streaming: toml::from_str(
+                r#"
+                split = "adversarial"
+                max_chars = 4
+                escape_unicode = true
+                "#,
+            )
+            .unwrap(),
+            ..Config::default()
+        });
+        let expected = serde_json::json!({ "text": "Grüße \"😀\"\n" });
+        let cases = [
+            (
+                "/v1/messages",
+                serde_json::json!({
+                    "model": "claude-haiku",
+                    "max_tokens": 200,
+                    "stream": true,
+                    "messages": [{"role": "user", "content": "Hello"}]
+                }),
+                "/delta/partial_json",
+            ),
+            (
+                "/v1/chat/completions",
+                serde_json::json!({
+                    "model": "llama3.1-8b",
+                    "stream": true,
+                    "messages": [{"role": "user", "content": "Hello"}]
+                }),
+                "/choices/0/delta/tool_calls/0/function/arguments",
+            ),
+            (
+                "/v1/responses",
+                serde_json::json!({"model": "gpt-4o", "input": "Hello", "stream": true}),
+                "/delta",
+            ),
+        ];
+
+        for (path, body, pointer) in cases {
+            let (_, text) = post_json(&state, path, &body, &[]).await;
+            let fragments: Vec<String> = text
+                .lines()
+                .filter_map(|line| line.strip_prefix("data: "))
+                .filter_map(|data| serde_json::from_str::<serde_json::Value>(data).ok())
+                .filter_map(|event| event.pointer(pointer)?.as_str().map(str::to_string))
+                .collect();
+            assert!(fragments.len() > 5, "{path}: {fragments:?}");
+            assert!(
+                fragments.iter().any(|f| f.ends_with('\\')),
+                "{path}: {fragments:?}"
+            );
+            let json = fragments.concat();
+            assert!(json.contains(r"\ud83d\ude00"), "{path}: {json}");
+            let arguments: serde_json::Value = serde_json::from_str(&json).unwrap();
+            assert_eq!(arguments, expected, "{path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_output_tokens_header() {
+        let state = RuntimeState::new(Config::default());
+        let json = |body: &str| serde_json::from_str::<serde_json::Value>(body).unwrap();
+        let exact = [(OUTPUT_TOKENS_HEADER, "37")];
+        let max = [(OUTPUT_TOKENS_HEADER, "max")];
+
+        let claude = |stream| {
+            serde_json::json!({
+                "model": "claude-haiku",
+                "max_tokens": 200,
+                "stream": stream,
+                "messages": [{"role": "user", "content": "Hello"}]
+            })
+        };
+        let (_, body) = post_json(&state, "/v1/messages", &claude(false), &exact).await;
+        let body = json(&body);
+        assert_eq!(body["usage"]["output_tokens"], 37);
+        assert_eq!(body["stop_reason"], "end_turn");
+        let (_, body) = post_json(&state, "/v1/messages", &claude(true), &max).await;
+        assert!(body.contains(r#""output_tokens":200"#));
+        assert!(body.contains(r#""stop_reason":"end_turn""#));
+
+        let cerebras = |stream| {
+            serde_json::json!({
+                "model": "llama3.1-8b",
+                "stream": stream,
+                "stream_options": {"include_usage": true},
+                "messages": [{"role": "user", "content": "Hello"}]
+            })
+        };
+        let path = "/v1/chat/completions";
+        let (_, body) = post_json(&state, path, &cerebras(false), &exact).await;
+        assert_eq!(json(&body)["usage"]["completion_tokens"], 37);
+        let (_, body) = post_json(&state, path, &cerebras(true), &exact).await;
+        assert!(body.contains(r#""completion_tokens":37"#));
+
+        let gemini = serde_json::json!({
+            "contents": [{"role": "user", "parts": [{"text": "Hello"}]}]
+        });
+        let path = "/v1beta/models/gemini-pro:generateContent";
+        let (_, body) = post_json(&state, path, &gemini, &exact).await;
+        assert_eq!(json(&body)["usageMetadata"]["candidatesTokenCount"], 37);
+        let path = "/v1beta/models/gemini-pro:streamGenerateContent";
+        let (_, body) = post_json(&state, path, &gemini, &exact).await;
+        assert!(body.contains(r#""candidatesTokenCount":37"#));
+
+        let openai = |stream| {
+            serde_json::json!({
+                "model": "gpt-4o",
+                "input": "Hello",
+                "max_output_tokens": 4000,
+                "stream": stream
+            })
+        };
+        let (_, body) = post_json(&state, "/v1/responses", &openai(false), &max).await;
+        let body = json(&body);
+        assert_eq!(body["usage"]["output_tokens"], 4000);
+        assert_eq!(body["status"], "completed");
+        let (_, body) = post_json(&state, "/v1/responses", &openai(true), &exact).await;
+        assert!(body.contains(r#""output_tokens":37"#));
+    }
+
+    fn fixture_config() -> Config {
+        Config {
+            fixtures: toml::from_str(
+                r#"
+            [[rules]]
+            match = { contains = "Compare" }
+            response = { tool_calls = [
+              { name = "get_weather", arguments = { city = "Oslo" } },
+              { name = "get_weather", arguments = { city = "Lima" } },
+            ] }
+
+            [[rules]]
+            match = { contains = "weather" }
+            response = { tool_call = { name = "get_weather", arguments = { city = "Oslo" } } }
+
+            [[rules]]
+            match = { provider = "gemini", regex = "(?i)^hello" }
+            response = { text = "Scripted Gemini greeting." }
+
+            [[rules]]
+            match = { contains = "Hello" }
+            response = { text = "Scripted greeting." }
+
+            [[rules]]
+            match = { headers = { "x-test-case" = "overloaded" } }
+            response = { error = "server_error" }
+            "#,
+            )
+            .unwrap(),
+            ..Config::default()
+        }
+    }
+
+    async fn post_json(
+        state: &Arc<RuntimeState>,
+        path: &str,
+        body: &serde_json::Value,
+        headers: &[(&str, &str)],
+    ) -> (StatusCode, String) {
+        let mut request = Request::post(path).header("content-type", "application/json");
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let response = create_router(state.clone())
+            .oneshot(request.body(Body::from(body.to_string())).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_fixture_text_reply() {
+        let state = RuntimeState::new(fixture_config());
+
+        let cases = [
+            (
+                "/v1/chat/completions",
+                serde_json::json!({
+                    "model": "llama",
+                    "messages": [{"role": "user", "content": "Hello"}]
+                }),
+                "Scripted greeting.",
+            ),
+            (
+                "/v1/messages",
+                serde_json::json!({
+                    "model": "claude",
+                    "max_tokens": 100,
+                    "stream": true,
+                    "messages": [{"role": "user", "content": "Hello"}]
+                }),
+                "Scripted",
+            ),
+            (
+                "/v1beta/models/gemini-pro:generateContent",
+                serde_json::json!({
+                    "contents": [{"role": "user", "parts": [{"text": "Hello"}]}]
+                }),
+                "Scripted Gemini greeting.",
+            ),
+            (
+                "/v1/responses",
+                serde_json::json!({"model": "gpt-4o", "input": "Hello", "stream": true}),
+                "\"text\":\"Scripted greeting.\"",
+            ),
+        ];
+
+        for (path, body, expected) in cases {
+            let (status, text) = post_json(&state, path, &body, &[]).await;
+            assert_eq!(status, StatusCode::OK);
+            assert!(text.contains(expected), "{path}: {text}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fixture_tool_call_reply() {
+        let state = RuntimeState::new(fixture_config());
+        let body = serde_json::json!({
+            "model": "claude",
+            "max_tokens": 100,
+            "messages": [{"role": "user", "content": "How is the weather?"}]
+        });
+
+        let (status, text) = post_json(&state, "/v1/messages", &body, &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        let json: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(json["stop_reason"], "tool_use");
+        assert_eq!(json["content"][0]["name"], "get_weather");
+        assert_eq!(json["content"][0]["input"]["city"], "Oslo");
+    }
+
+    #[tokio::test]
+    async fn test_fixture_parallel_tool_calls_reply() {
+        let state = RuntimeState::new(fixture_config());
+        let body = serde_json::json!({
+            "model": "llama3.1-8b",
+            "messages": [{"role": "user", "content": "Compare Oslo and Lima"}]
+        });
+
+        let (_, text) = post_json(&state, "/v1/chat/completions", &body, &[]).await;
+        let json: serde_json::Value = serde_json::from_str(&text).unwrap();
+        let calls = &json["choices"][0]["message"]["tool_calls"];
+        assert_eq!(calls.as_array().unwrap().len(), 2);
+        assert_eq!(calls[0]["function"]["arguments"], r#"{"city":"Oslo"}"#);
+        assert_eq!(calls[1]["function"]["arguments"], r#"{"city":"Lima"}"#);
+    }
+
+    #[tokio::test]
+    async fn test_fixture_error_reply() {
+        let state = RuntimeState::new(fixture_config());
+        let body = serde_json::json!({"model": "gpt-4o", "input": "Anything"});
+
+        let (status, text) = post_json(
+            &state,
+            "/v1/responses",
+            &body,
+            &[("x-test-case", "overloaded")],
+        )
+        .await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(text.contains("server_error"));
+    }
+
+    #[tokio::test]
+    async fn test_fixture_multi_turn_script() {
+        let config = Config {
+            fixtures: toml::from_str(
+                r#"
+            [[rules]]
+            match = { provider = "claude", contains = "weather" }
+            turns = [
+                { tool_call = { name = "get_weather", arguments = { city = "Lima" } } },
+                { text = "It is sunny in Lima." },
+            ]
+            "#,
+            )
+            .unwrap(),
+            ..Config::default()
+        };
+        let state = RuntimeState::new(config);
+
+        let first = serde_json::json!({
+            "model": "claude-sonnet-4-20250514",
+            "max_tokens": 100,
+            "messages": [{"role": "user", "content": "What is the weather in Lima?"}]
+        });
+        let (status, text) = post_json(&state, "/v1/messages", &first, &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(text.contains("get_weather"));
+
+        let second = serde_json::json!({
+            "model": "claude-sonnet-4-20250514",
+            "max_tokens": 100,
+            "messages": [
+                {"role": "user", "content": "What is the weather in Lima?"},
+                {"role": "assistant", "content": [
+                    {"type": "tool_use", "id": "toolu_1", "name": "get_weather",
+                     "input": {"city": "Lima"}}
+                ]},
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "toolu_1", "content": "Sunny"}
+                ]}
+            ]
+        });
+        let (status, text) = post_json(&state, "/v1/messages", &second, &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(text.contains("It is sunny in Lima."));
+    }
+
+    /// Serve a deterministic tokenipsum on an ephemeral port as a stand-in upstream.
+    async fn spawn_upstream() -> String {
+        let mut config = Config::default();
+        config.content.deterministic = true;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, create_router(RuntimeState::new(config)))
+                .await
+                .unwrap();
+        });
+        format!("http://{addr}")
+    }
+
+    fn proxy_config(mode: proxy::ProxyMode, dir: &std::path::Path, upstream: &str) -> Config {
+        let mut config = Config::default();
+        config.proxy.mode = mode;
+        config.proxy.cassette_dir = dir.display().to_string();
+        config.proxy.upstreams.claude = upstream.to_string();
+        config.proxy.upstreams.openai = upstream.to_string();
+        config
+    }
+
+    #[tokio::test]
+    async fn test_proxy_record_and_replay() {
+        let dir = std::env::temp_dir().join(format!("tokenipsum-{}", uuid::Uuid::new_v4()));
+        let upstream = spawn_upstream().await;
+        let body = serde_json::json!({
+            "model": "claude-sonnet-4-20250514",
+            "max_tokens": 100,
+            "messages": [{"role": "user", "content": "Hello"}]
+        });
+
+        let record_state =
+            RuntimeState::new(proxy_config(proxy::ProxyMode::Record, &dir, &upstream));
+        let (status, recorded) = post_json(
+            &record_state,
+            "/v1/messages",
+            &body,
+            &[("x-api-key", "secret")],
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        // Replay never contacts the upstream.
+        let replay_state = RuntimeState::new(proxy_config(
+            proxy::ProxyMode::Replay,
+            &dir,
+            "http://127.0.0.1:9",
+        ));
+        let (status, replayed) = post_json(&replay_state, "/v1/messages", &body, &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&replayed).unwrap(),
+            serde_json::from_str::<serde_json::Value>(&recorded).unwrap()
+        );
+
+        let other = serde_json::json!({"model": "claude-sonnet-4-20250514", "max_tokens": 5,
+            "messages": [{"role": "user", "content": "Hello"}]});
+        let (status, text) = post_json(&replay_state, "/v1/messages", &other, &[]).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(text.contains("cassette_not_found"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_proxy_records_stream_events() {
+        let dir = std::env::temp_dir().join(format!("tokenipsum-{}", uuid::Uuid::new_v4()));
+        let upstream = spawn_upstream().await;
+        let body = serde_json::json!({"model": "gpt-4o", "input": "Hello", "stream": true});
+
+        let record_state =
+            RuntimeState::new(proxy_config(proxy::ProxyMode::Record, &dir, &upstream));
+        let (status, recorded) = post_json(&record_state, "/v1/responses", &body, &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(recorded.contains("response.completed"));
+
+        let entry = std::fs::read_dir(&dir).unwrap().next().unwrap().unwrap();
+        let cassette: proxy::Cassette =
+            serde_json::from_str(&std::fs::read_to_string(entry.path()).unwrap()).unwrap();
+        assert!(cassette.response.events.len() > 2);
+        assert!(cassette.response.events[0]
+            .data
+            .starts_with("event: response.created"));
+
+        let mut config = proxy_config(proxy::ProxyMode::Replay, &dir, "http://127.0.0.1:9");
+        config.proxy.replay_timing = false;
+        let (status, replayed) =
+            post_json(&RuntimeState::new(config), "/v1/responses", &body, &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(replayed, recorded);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
     #[tokio::test]
     async fn test_auth_required_no_key() {
         let mut config = Config::default();
@@ -381,6 +1839,149 @@ mod tests {
         assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
     }
 
+    #[tokio::test]
+    async fn test_auth_claude_headers() {
+        let mut config = Config::default();
+        config.auth.require_auth = true;
+        config.auth.valid_keys = vec!["test-key".to_string()];
+        let state = RuntimeState::new(config);
+        let body = serde_json::json!({
+            "model": "claude-haiku",
+            "max_tokens": 50,
+            "messages": [{"role": "user", "content": "Hello"}]
+        });
+
+        // Bearer auth is how OpenAI authenticates, not Anthropic.
+        let (status, text) = post_json(
+            &state,
+            "/v1/messages",
+            &body,
+            &[("authorization", "Bearer test-key")],
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(text.contains("x-api-key header is required"));
+
+        let (status, text) =
+            post_json(&state, "/v1/messages", &body, &[("x-api-key", "wrong")]).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(text.contains("invalid x-api-key"));
+
+        let (status, text) =
+            post_json(&state, "/v1/messages", &body, &[("x-api-key", "test-key")]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(text.contains("anthropic-version"));
+
+        let headers = [
+            ("x-api-key", "test-key"),
+            ("anthropic-version", "2023-06-01"),
+        ];
+        let (status, _) = post_json(&state, "/v1/messages", &body, &headers).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_auth_gemini_key() {
+        let mut config = Config::default();
+        config.auth.require_auth = true;
+        config.auth.valid_keys = vec!["test-key".to_string(), "test+key/2".to_string()];
+        let state = RuntimeState::new(config);
+        let body = serde_json::json!({"contents": [{"parts": [{"text": "Hello"}]}]});
+        let path = "/v1beta/models/gemini-2.0-flash:generateContent";
+
+        let (status, text) = post_json(&state, path, &body, &[]).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(text.contains("PERMISSION_DENIED"));
+
+        let (status, text) = post_json(&state, &format!("{path}?key=wrong"), &body, &[]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(text.contains("API_KEY_INVALID"));
+
+        let (status, _) = post_json(&state, &format!("{path}?key=test-key"), &body, &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = post_json(&state, path, &body, &[("x-goog-api-key", "test-key")]).await;
+        assert_eq!(status, StatusCode::OK);
+        // Query parameters are percent-decoded.
+        let escaped = format!("{path}?alt=json&key=test%2Bkey%2F2");
+        let (status, _) = post_json(&state, &escaped, &body, &[]).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_key_policies() {
+        let mut config = Config::default();
+        config.auth.require_auth = true;
+        let keys = &mut config.auth.keys;
+        keys.insert("healthy".to_string(), KeyPolicy::default());
+        keys.insert(
+            "throttled".to_string(),
+            KeyPolicy {
+                requests_per_minute: Some(1),
+                ..KeyPolicy::default()
+            },
+        );
+        keys.insert(
+            "revoked".to_string(),
+            KeyPolicy {
+                force_error: config::ForceError::Unauthorized,
+                ..KeyPolicy::default()
+            },
+        );
+        keys.insert(
+            "broke".to_string(),
+            KeyPolicy {
+                out_of_credits: true,
+                ..KeyPolicy::default()
+            },
+        );
+        keys.insert(
+            "mini-only".to_string(),
+            KeyPolicy {
+                providers: vec![Provider::Claude],
+                models: vec!["claude-haiku".to_string()],
+                ..KeyPolicy::default()
+            },
+        );
+        let state = RuntimeState::new(config);
+        let body = serde_json::json!({
+            "model": "claude-sonnet",
+            "max_tokens": 50,
+            "messages": [{"role": "user", "content": "Hello"}]
+        });
+        let send = |key: &'static str| {
+            let state = state.clone();
+            let body = body.clone();
+            async move {
+                let headers = [("x-api-key", key), ("anthropic-version", "2023-06-01")];
+                post_json(&state, "/v1/messages", &body, &headers).await
+            }
+        };
+
+        for _ in 0..2 {
+            assert_eq!(send("healthy").await.0, StatusCode::OK);
+        }
+        assert_eq!(send("throttled").await.0, StatusCode::OK);
+        assert_eq!(send("throttled").await.0, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(send("revoked").await.0, StatusCode::UNAUTHORIZED);
+        let (status, text) = send("broke").await;
+        assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+        assert!(text.contains("billing_error"));
+        let (status, text) = send("mini-only").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(text.contains("model: claude-sonnet"));
+
+        let openai = serde_json::json!({"model": "gpt-4o", "input": "Hello"});
+        let (status, text) = post_json(
+            &state,
+            "/v1/responses",
+            &openai,
+            &[("authorization", "Bearer mini-only")],
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(text.contains("invalid_api_key"));
+    }
+
     #[tokio::test]
     async fn test_rate_limit_after_requests() {
         let mut config = Config::default();
@@ -429,6 +2030,855 @@ mod tests {
         assert_eq!(response.status(), StatusCode::NOT_FOUND);
     }
 
+    async fn admin_request(
+        state: &Arc<RuntimeState>,
+        method: &str,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> (StatusCode, serde_json::Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(path)
+            .header("content-type", "application/json")
+            .body(body.map_or_else(Body::empty, |b| Body::from(b.to_string())))
+            .unwrap();
+        let response = create_router(state.clone()).oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    #[tokio::test]
+    async fn test_admin_toggle_provider() {
+        let state = RuntimeState::new(Config::default());
+        let body = serde_json::json!({"model": "gpt-4o", "input": "Hello"});
+
+        let (status, providers) = admin_request(
+            &state,
+            "PUT",
+            "/_admin/providers/openai",
+            Some(serde_json::json!({"enabled": false})),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(providers["openai"], false);
+        let (status, _) = post_json(&state, "/v1/responses", &body, &[]).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        state.update_config(|config| config.providers.openai = true);
+        let (status, _) = post_json(&state, "/v1/responses", &body, &[]).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_admin_patch_config() {
+        let state = RuntimeState::new(Config::default());
+
+        let (status, config) = admin_request(
+            &state,
+            "PATCH",
+            "/_admin/config",
+            Some(serde_json::json!({"errors": {"force_error": "rate_limit"}})),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(config["errors"]["force_error"], "rate_limit");
+        assert_eq!(config["server"]["port"], 8787);
+
+        let body = serde_json::json!({"model": "gpt-4o", "input": "Hello"});
+        let (status, _) = post_json(&state, "/v1/responses", &body, &[]).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+
+        // Admin routes are exempt from forced errors.
+        let (status, config) = admin_request(&state, "GET", "/_admin/config", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(config["errors"]["force_error"], "rate_limit");
+
+        let (status, _) = admin_request(
+            &state,
+            "PATCH",
+            "/_admin/config",
+            Some(serde_json::json!({"errors": {"force_error": "bogus"}})),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            state.config().errors.force_error,
+            config::ForceError::RateLimit
+        );
+    }
+
+    #[tokio::test]
+    async fn test_admin_put_config_and_reset() {
+        let mut config = Config::default();
+        config.rate_limit.fail_after_requests = 1;
+        let state = RuntimeState::new(config);
+        let body = serde_json::json!({"model": "gpt-4o", "input": "Hello"});
+
+        let (status, _) = post_json(&state, "/v1/responses", &body, &[]).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+
+        let (status, reset) = admin_request(&state, "POST", "/_admin/reset", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(reset["previous_request_count"], 1);
+
+        let (status, config) = admin_request(
+            &state,
+            "PUT",
+            "/_admin/config",
+            Some(serde_json::json!({"server": {"latency_ms": 0}})),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(config["rate_limit"]["fail_after_requests"], 0);
+        let (status, _) = post_json(&state, "/v1/responses", &body, &[]).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_journal_records_requests() {
+        let mut config = Config::default();
+        config.rate_limit.fail_after_requests = 2;
+        let state = RuntimeState::new(config);
+        let body = serde_json::json!({
+            "model": "claude-sonnet-4-20250514",
+            "max_tokens": 100,
+            "system": "You are a pirate.",
+            "messages": [{"role": "user", "content": "Hello"}]
+        });
+
+        for attempt in ["1", "2", "3"] {
+            post_json(
+                &state,
+                "/v1/messages",
+                &body,
+                &[("x-retry-attempt", attempt), ("x-api-key", "sk-ant-test")],
+            )
+            .await;
+        }
+
+        let entries = state.requests(&journal::JournalFilter {
+            contains: Some("You are a pirate.".to_string()),
+            ..journal::JournalFilter::default()
+        });
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].provider, Some(Provider::Claude));
+        assert_eq!(
+            entries[0].model.as_deref(),
+            Some("claude-sonnet-4-20250514")
+        );
+        assert_eq!(entries[0].headers["x-retry-attempt"], "1");
+        assert_eq!(entries[0].headers["x-api-key"], "[REDACTED]");
+        assert_eq!(entries[0].status, 200);
+        assert!(entries[0].response.complete);
+        assert!(!entries[0].response.text.is_empty());
+        assert_eq!(entries[2].status, 429);
+        assert_eq!(
+            entries[2].response.error.as_deref(),
+            Some("rate_limit_error")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_journal_body_limit() {
+        let state = RuntimeState::new(Config::default());
+        let body = serde_json::json!({
+            "model": "claude-sonnet-4-20250514",
+            "max_tokens": 100,
+            "messages": [{"role": "user", "content": "a".repeat(BODY_LIMIT)}]
+        });
+        let (status, text) = post_json(&state, "/v1/messages", &body, &[]).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(text.contains("request_too_large"));
+        assert!(state
+            .requests(&journal::JournalFilter::default())
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_journal_summarizes_streams() {
+        let state = RuntimeState::new(Config::default());
+        let body = serde_json::json!({
+            "model": "gpt-4o",
+            "input": "What is the weather in Paris?",
+            "stream": true,
+            "tools": [{"type": "function", "name": "get_weather"}]
+        });
+        post_json(&state, "/v1/responses", &body, &[]).await;
+
+        let entry = &state.requests(&journal::JournalFilter::default())[0];
+        assert!(entry.response.stream);
+        assert!(entry.response.complete);
+        assert_eq!(entry.response.tool_calls, vec!["get_weather".to_string()]);
+        assert_eq!(entry.response.stop_reason.as_deref(), Some("completed"));
+    }
+
+    #[tokio::test]
+    async fn test_admin_requests_query_and_clear() {
+        let state = RuntimeState::new(Config::default());
+        let claude = serde_json::json!({
+            "model": "claude-haiku",
+            "max_tokens": 10,
+            "messages": [{"role": "user", "content": "Hi"}]
+        });
+        let openai = serde_json::json!({"model": "gpt-4o", "input": "Hi"});
+        post_json(&state, "/v1/messages", &claude, &[]).await;
+        post_json(&state, "/v1/responses", &openai, &[]).await;
+
+        let (status, entries) =
+            admin_request(&state, "GET", "/_admin/requests?provider=openai", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(entries.as_array().unwrap().len(), 1);
+        assert_eq!(entries[0]["model"], "gpt-4o");
+
+        let (_, cleared) = admin_request(&state, "DELETE", "/_admin/requests", None).await;
+        assert_eq!(cleared["cleared"], 2);
+        let (_, entries) = admin_request(&state, "GET", "/_admin/requests", None).await;
+        assert!(entries.as_array().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_per_key_and_model() {
+        let mut config = Config::default();
+        config.rate_limit.enabled = true;
+        config.rate_limit.requests_per_minute = 2;
+        let state = RuntimeState::new(config);
+        let body = serde_json::json!({"model": "gpt-4o", "input": "Hello"});
+        let key_a = [("authorization", "Bearer key-a")];
+
+        for _ in 0..2 {
+            let (status, _) = post_json(&state, "/v1/responses", &body, &key_a).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+
+        let request = Request::post("/v1/responses")
+            .header("content-type", "application/json")
+            .header("authorization", "Bearer key-a")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = create_router(state.clone()).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["x-ratelimit-limit-requests"], "2");
+        assert_eq!(response.headers()["x-ratelimit-remaining-requests"], "0");
+        let retry_after: u64 = response.headers()["retry-after"]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((1..=60).contains(&retry_after));
+        let body_bytes = response.into_body().collect().await.unwrap().to_bytes();
+        let error: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+        assert_eq!(error["error"]["code"], "rate_limit_exceeded");
+        assert!(error["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("gpt-4o"));
+
+        // Another key and another model each have their own window.
+        let (status, _) = post_json(
+            &state,
+            "/v1/responses",
+            &body,
+            &[("authorization", "Bearer key-b")],
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let other_model = serde_json::json!({"model": "gpt-4o-mini", "input": "Hello"});
+        let (status, _) = post_json(&state, "/v1/responses", &other_model, &key_a).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_tokens_per_minute() {
+        let mut config = Config::default();
+        config.rate_limit.enabled = true;
+        config.rate_limit.tokens_per_minute = 150;
+        let state = RuntimeState::new(config);
+        let body = serde_json::json!({
+            "model": "claude-haiku",
+            "max_tokens": 100,
+            "messages": [{"role": "user", "content": "Hello"}]
+        });
+
+        let (status, _) = post_json(&state, "/v1/messages", &body, &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, text) = post_json(&state, "/v1/messages", &body, &[]).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert!(text.contains("150 tokens per minute"));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_output_tokens_claude() {
+        let mut config = Config::default();
+        config.rate_limit.enabled = true;
+        config.rate_limit.output_tokens_per_minute = 300;
+        let state = RuntimeState::new(config);
+        let body = serde_json::json!({
+            "model": "claude-haiku",
+            "max_tokens": 200,
+            "messages": [{"role": "user", "content": "Hello"}]
+        });
+        post_json(&state, "/v1/messages", &body, &[]).await;
+
+        let request = Request::post("/v1/messages")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = create_router(state).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(
+            response.headers()["anthropic-ratelimit-output-tokens-limit"],
+            "300"
+        );
+        assert_eq!(
+            response.headers()["anthropic-ratelimit-output-tokens-remaining"],
+            "100"
+        );
+        let text = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(String::from_utf8_lossy(&text).contains("300 output tokens per minute"));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_output_tokens_without_max_tokens() {
+        let mut config = Config::default();
+        config.rate_limit.enabled = true;
+        config.rate_limit.output_tokens_per_minute = 100;
+        let state = RuntimeState::new(config);
+        let body = serde_json::json!({"model": "gpt-4o", "input": "Hello"});
+
+        // Each request reserves a default reply's 36 tokens.
+        for _ in 0..2 {
+            let (status, _) = post_json(&state, "/v1/responses", &body, &[]).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let (status, text) = post_json(&state, "/v1/responses", &body, &[]).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert!(text.contains("Limit 100, Used 72, Requested 36"), "{text}");
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_input_tokens_gemini() {
+        let mut config = Config::default();
+        config.rate_limit.enabled = true;
+        config.rate_limit.input_tokens_per_minute = 5;
+        let state = RuntimeState::new(config);
+        let body = serde_json::json!({
+            "contents": [{"role": "user", "parts": [{"text": "Tell me a very long story about dragons and castles"}]}]
+        });
+
+        let (status, text) = post_json(
+            &state,
+            "/v1beta/models/gemini-2.0-flash:generateContent",
+            &body,
+            &[],
+        )
+        .await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        let error: serde_json::Value = serde_json::from_str(&text).unwrap();
+        let violation = &error["error"]["details"][0]["violations"][0];
+        assert_eq!(
+            violation["quotaMetric"],
+            "generativelanguage.googleapis.com/generate_content_input_token_count"
+        );
+        assert_eq!(violation["quotaDimensions"]["model"], "gemini-2.0-flash");
+        assert_eq!(violation["quotaValue"], "5");
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_headers_on_success() {
+        let mut config = Config::default();
+        config.rate_limit.enabled = true;
+        config.rate_limit.requests_per_minute = 5;
+        config.rate_limit.tokens_per_minute = 10_000;
+        let state = RuntimeState::new(config);
+        let app = create_router(state);
+
+        let body = serde_json::json!({"model": "gpt-4o", "input": "Hello", "stream": true});
+        let request = Request::post("/v1/responses")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers();
+        assert_eq!(headers["x-ratelimit-limit-requests"], "5");
+        assert_eq!(headers["x-ratelimit-remaining-requests"], "4");
+        assert_eq!(headers["x-ratelimit-limit-tokens"], "10000");
+        let reset = headers["x-ratelimit-reset-requests"].to_str().unwrap();
+        assert!(reset.ends_with('s'), "{reset}");
+
+        let body = serde_json::json!({
+            "model": "claude-haiku",
+            "max_tokens": 100,
+            "messages": [{"role": "user", "content": "Hello"}]
+        });
+        let request = Request::post("/v1/messages")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers();
+        assert_eq!(headers["anthropic-ratelimit-requests-remaining"], "4");
+        let reset = headers["anthropic-ratelimit-requests-reset"]
+            .to_str()
+            .unwrap();
+        assert!(reset.ends_with('Z') && reset.contains('T'), "{reset}");
+        assert!(!headers.contains_key("retry-after"));
+    }
+
+    #[tokio::test]
+    async fn test_stream_faults() {
+        let state = RuntimeState::new(Config::default());
+        let body = serde_json::json!({
+            "model": "claude-haiku",
+            "max_tokens": 50,
+            "stream": true,
+            "messages": [{"role": "user", "content": "Hello"}]
+        });
+        let (status, text) = post_json(
+            &state,
+            "/v1/messages",
+            &body,
+            &[(sse::STREAM_FAULT_HEADER, "error:2")],
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(text.matches("\n\n").count(), 3);
+        assert!(text.contains("event: error"));
+        assert!(text.contains("overloaded_error"));
+        assert!(!text.contains("message_stop"));
+
+        // Configured faults apply to every stream.
+        state.update_config(|config| {
+            config.stream_faults.fault = sse::StreamFaultKind::Disconnect;
+        });
+        let request =
+            Request::post("/v1beta/models/gemini-2.0-flash:streamGenerateContent?alt=sse")
+                .header("content-type", "application/json")
+                .body(Body::from(
+                    serde_json::json!({"contents": [{"parts": [{"text": "Hello"}]}]}).to_string(),
+                ))
+                .unwrap();
+        let response = create_router(state.clone()).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.into_body().collect().await.is_err());
+
+        // Non-streaming responses are untouched.
+        let body = serde_json::json!({"model": "gpt-4o", "input": "Hello"});
+        let (status, _) = post_json(&state, "/v1/responses", &body, &[]).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_timeout_modes() {
+        let mut config = Config::default();
+        config.timeouts.providers.insert(
+            Provider::Claude,
+            timeout::TimeoutRule {
+                mode: timeout::TimeoutMode::Hang,
+                ..timeout::TimeoutRule::default()
+            },
+        );
+        let state = RuntimeState::new(config);
+        let claude = serde_json::json!({
+            "model": "claude-haiku",
+            "max_tokens": 50,
+            "messages": [{"role": "user", "content": "Hello"}]
+        });
+        let wait = Duration::from_millis(100);
+
+        let hung = tokio::time::timeout(wait, post_json(&state, "/v1/messages", &claude, &[]));
+        assert!(hung.await.is_err());
+
+        // Other providers are unaffected unless the header asks for a timeout.
+        let body = serde_json::json!({"model": "gpt-4o", "input": "Hello"});
+        let (status, _) = post_json(&state, "/v1/responses", &body, &[]).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let request = Request::post("/v1/responses")
+            .header("content-type", "application/json")
+            .header(timeout::TIMEOUT_HEADER, "stall")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = create_router(state).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let mut body = response.into_body();
+        assert!(tokio::time::timeout(wait, body.frame()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_streaming_timing() {
+        let mut config = Config::default();
+        config.timing.default = timing::TimingModel {
+            time_to_first_token_ms: 0,
+            tokens_per_second: 0.0,
+            ..timing::TimingModel::default()
+        };
+        config.timing.models.insert(
+            "claude-haiku".to_string(),
+            timing::TimingModel {
+                time_to_first_token_ms: 300,
+                tokens_per_second: 0.0,
+                ..timing::TimingModel::default()
+            },
+        );
+        let state = RuntimeState::new(config);
+        let ttft = Duration::from_millis(300);
+
+        let claude = serde_json::json!({
+            "model": "claude-haiku",
+            "max_tokens": 50,
+            "stream": true,
+            "messages": [{"role": "user", "content": "Hello"}]
+        });
+        let start = std::time::Instant::now();
+        let (status, body) = post_json(&state, "/v1/messages", &claude, &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("message_stop"));
+        assert!(start.elapsed() >= ttft);
+
+        let openai = serde_json::json!({"model": "gpt-4o", "input": "Hello", "stream": true});
+        let start = std::time::Instant::now();
+        let (status, body) = post_json(&state, "/v1/responses", &openai, &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("response.completed"));
+        assert!(start.elapsed() < ttft);
+    }
+
+    #[tokio::test]
+    async fn test_max_tokens_truncation() {
+        let config = Config {
+            fixtures: toml::from_str(
+                r#"
+            [[rules]]
+            match = { contains = "Hello" }
+            response = { text = "This scripted reply is longer than three tokens." }
+            "#,
+            )
+            .unwrap(),
+            ..Config::default()
+        };
+        let state = RuntimeState::new(config);
+        let json = |body: &str| serde_json::from_str::<serde_json::Value>(body).unwrap();
+
+        let claude = |max_tokens, stream| {
+            serde_json::json!({
+                "model": "claude-haiku",
+                "max_tokens": max_tokens,
+                "stream": stream,
+                "messages": [{"role": "user", "content": "Hello"}]
+            })
+        };
+        let (_, body) = post_json(&state, "/v1/messages", &claude(3, false), &[]).await;
+        let body = json(&body);
+        assert_eq!(body["stop_reason"], "max_tokens");
+        assert_eq!(body["usage"]["output_tokens"], 3);
+        let (_, body) = post_json(&state, "/v1/messages", &claude(4096, false), &[]).await;
+        assert_eq!(json(&body)["stop_reason"], "end_turn");
+        let (_, body) = post_json(&state, "/v1/messages", &claude(3, true), &[]).await;
+        assert!(body.contains(r#""stop_reason":"max_tokens""#));
+
+        let cerebras = |max_tokens, stream| {
+            serde_json::json!({
+                "model": "llama3.1-8b",
+                "max_completion_tokens": max_tokens,
+                "stream": stream,
+                "messages": [{"role": "user", "content": "Hello"}]
+            })
+        };
+        let path = "/v1/chat/completions";
+        let (_, body) = post_json(&state, path, &cerebras(3, false), &[]).await;
+        let body = json(&body);
+        assert_eq!(body["choices"][0]["finish_reason"], "length");
+        assert_eq!(body["usage"]["completion_tokens"], 3);
+        let (_, body) = post_json(&state, path, &cerebras(4096, false), &[]).await;
+        assert_eq!(json(&body)["choices"][0]["finish_reason"], "stop");
+        let (_, body) = post_json(&state, path, &cerebras(3, true), &[]).await;
+        assert!(body.contains(r#""finish_reason":"length""#));
+
+        let gemini = serde_json::json!({
+            "contents": [{"role": "user", "parts": [{"text": "Hello"}]}],
+            "generationConfig": {"maxOutputTokens": 3}
+        });
+        let path = "/v1beta/models/gemini-pro:generateContent";
+        let (_, body) = post_json(&state, path, &gemini, &[]).await;
+        let body = json(&body);
+        assert_eq!(body["candidates"][0]["finishReason"], "MAX_TOKENS");
+        assert_eq!(body["usageMetadata"]["candidatesTokenCount"], 3);
+        let path = "/v1beta/models/gemini-pro:streamGenerateContent";
+        let (_, body) = post_json(&state, path, &gemini, &[]).await;
+        assert!(body.contains(r#""finishReason":"MAX_TOKENS""#));
+
+        let openai = |stream| {
+            serde_json::json!({
+                "model": "gpt-4o",
+                "input": "Hello",
+                "max_output_tokens": 3,
+                "stream": stream
+            })
+        };
+        let (_, body) = post_json(&state, "/v1/responses", &openai(false), &[]).await;
+        let body = json(&body);
+        assert_eq!(body["status"], "incomplete");
+        assert_eq!(body["incomplete_details"]["reason"], "max_output_tokens");
+        assert_eq!(body["usage"]["output_tokens"], 3);
+        let (_, body) = post_json(&state, "/v1/responses", &openai(true), &[]).await;
+        assert!(body.contains("event: response.incomplete"));
+        assert!(!body.contains("response.completed"));
+    }
+
+    #[tokio::test]
+    async fn test_streaming_within_limit() {
+        let state = RuntimeState::new(Config::default());
+        let cases = [
+            (
+                "/v1/messages",
+                serde_json::json!({
+                    "model": "claude-haiku",
+                    "max_tokens": 1024,
+                    "stream": true,
+                    "messages": [{"role": "user", "content": "Hello"}]
+                }),
+                r#""stop_reason":"end_turn""#,
+            ),
+            (
+                "/v1/chat/completions",
+                serde_json::json!({
+                    "model": "llama3.1-8b",
+                    "max_tokens": 1024,
+                    "stream": true,
+                    "messages": [{"role": "user", "content": "Hello"}]
+                }),
+                r#""finish_reason":"stop""#,
+            ),
+            (
+                "/v1beta/models/gemini-pro:streamGenerateContent",
+                serde_json::json!({
+                    "contents": [{"role": "user", "parts": [{"text": "Hello"}]}],
+                    "generationConfig": {"maxOutputTokens": 1024}
+                }),
+                r#""finishReason":"STOP""#,
+            ),
+            (
+                "/v1/responses",
+                serde_json::json!({
+                    "model": "gpt-4o",
+                    "input": "Hello",
+                    "max_output_tokens": 1024,
+                    "stream": true
+                }),
+                "event: response.completed",
+            ),
+        ];
+        for seed in ["1", "2", "3"] {
+            for (path, body, finished) in &cases {
+                let (_, text) = post_json(&state, path, body, &[(SEED_HEADER, seed)]).await;
+                assert!(text.contains(finished), "{path}: {text}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_max_tokens_claude_tools_and_thinking() {
+        let state = RuntimeState::new(Config::default());
+        let json = |body: &str| serde_json::from_str::<serde_json::Value>(body).unwrap();
+        let three = [(TOOL_CALLS_HEADER, "3")];
+
+        // Each call's input, {"location":"Tokyo"}, is 5 tokens.
+        let tools = |max_tokens, stream| {
+            serde_json::json!({
+                "model": "claude-haiku",
+                "max_tokens": max_tokens,
+                "stream": stream,
+                "messages": [{"role": "user", "content": "What is the weather in Tokyo"}],
+                "tools": [{"name": "get_weather"}]
+            })
+        };
+        let (_, body) = post_json(&state, "/v1/messages", &tools(1024, false), &three).await;
+        let body = json(&body);
+        assert_eq!(body["stop_reason"], "tool_use");
+        assert_eq!(body["usage"]["output_tokens"], 15);
+        let (_, body) = post_json(&state, "/v1/messages", &tools(12, false), &three).await;
+        let body = json(&body);
+        assert_eq!(body["stop_reason"], "max_tokens");
+        assert_eq!(body["usage"]["output_tokens"], 12);
+        assert_eq!(body["content"][2]["input"], serde_json::json!({}));
+        let (_, body) = post_json(&state, "/v1/messages", &tools(12, true), &three).await;
+        assert!(body.contains(r#""stop_reason":"max_tokens""#));
+        assert!(body.contains(r#""output_tokens":12"#));
+
+        let thinking = |max_tokens, stream| {
+            serde_json::json!({
+                "model": "claude-haiku",
+                "max_tokens": max_tokens,
+                "stream": stream,
+                "messages": [{"role": "user", "content": "Hello"}],
+                "thinking": {"type": "enabled", "budget_tokens": 1024}
+            })
+        };
+        let (_, body) = post_json(&state, "/v1/messages", &thinking(4096, false), &[]).await;
+        assert!(json(&body)["content"][0]["signature"].is_string());
+        let (_, body) = post_json(&state, "/v1/messages", &thinking(3, false), &[]).await;
+        let body = json(&body);
+        assert_eq!(body["stop_reason"], "max_tokens");
+        assert!(body["content"][0].get("signature").is_none());
+        let (_, body) = post_json(&state, "/v1/messages", &thinking(4096, true), &[]).await;
+        assert!(body.contains("signature_delta"));
+        let (_, body) = post_json(&state, "/v1/messages", &thinking(3, true), &[]).await;
+        assert!(!body.contains("signature_delta"));
+    }
+
+    #[tokio::test]
+    async fn test_max_tokens_tool_calls() {
+        let state = RuntimeState::new(Config::default());
+        let json = |body: &str| serde_json::from_str::<serde_json::Value>(body).unwrap();
+        let three = [(TOOL_CALLS_HEADER, "3")];
+
+        // Each call's arguments, {"location":"Tokyo"}, are 5 tokens.
+        let cerebras = |max_tokens, stream| {
+            serde_json::json!({
+                "model": "llama3.1-8b",
+                "max_tokens": max_tokens,
+                "stream": stream,
+                "stream_options": {"include_usage": true},
+                "messages": [{"role": "user", "content": "What is the weather in Tokyo"}],
+                "tools": [{"type": "function", "function": {"name": "get_weather"}}]
+            })
+        };
+        let path = "/v1/chat/completions";
+        let (_, body) = post_json(&state, path, &cerebras(1024, false), &three).await;
+        let body = json(&body);
+        assert_eq!(body["choices"][0]["finish_reason"], "tool_calls");
+        assert_eq!(body["usage"]["completion_tokens"], 15);
+        let (_, body) = post_json(&state, path, &cerebras(12, false), &three).await;
+        let body = json(&body);
+        assert_eq!(body["choices"][0]["finish_reason"], "length");
+        assert_eq!(body["usage"]["completion_tokens"], 12);
+        let (_, body) = post_json(&state, path, &cerebras(12, true), &three).await;
+        assert!(body.contains(r#""finish_reason":"length""#));
+        assert!(body.contains(r#""completion_tokens":12"#));
+
+        let gemini = |max_tokens| {
+            serde_json::json!({
+                "contents": [{"role": "user", "parts": [{"text": "What is the weather in Tokyo"}]}],
+                "tools": [{"functionDeclarations": [{"name": "get_weather"}]}],
+                "generationConfig": {"maxOutputTokens": max_tokens}
+            })
+        };
+        let path = "/v1beta/models/gemini-pro:generateContent";
+        let (_, body) = post_json(&state, path, &gemini(1024), &three).await;
+        let body = json(&body);
+        assert_eq!(body["candidates"][0]["finishReason"], "STOP");
+        assert_eq!(body["usageMetadata"]["candidatesTokenCount"], 15);
+        let (_, body) = post_json(&state, path, &gemini(12), &three).await;
+        let body = json(&body);
+        assert_eq!(body["candidates"][0]["finishReason"], "MAX_TOKENS");
+        assert_eq!(body["usageMetadata"]["candidatesTokenCount"], 12);
+        let parts = &body["candidates"][0]["content"]["parts"];
+        assert_eq!(parts[2]["functionCall"]["args"], serde_json::json!({}));
+        let path = "/v1beta/models/gemini-pro:streamGenerateContent";
+        let (_, body) = post_json(&state, path, &gemini(12), &three).await;
+        assert!(body.contains(r#""finishReason":"MAX_TOKENS""#));
+
+        let openai = |max_tokens, stream| {
+            serde_json::json!({
+                "model": "gpt-4o",
+                "max_output_tokens": max_tokens,
+                "stream": stream,
+                "input": "What is the weather in Tokyo",
+                "tools": [{"type": "function", "name": "get_weather"}]
+            })
+        };
+        let path = "/v1/responses";
+        let (_, body) = post_json(&state, path, &openai(1024, false), &three).await;
+        let body = json(&body);
+        assert_eq!(body["status"], "completed");
+        assert_eq!(body["usage"]["output_tokens"], 15);
+        let (_, body) = post_json(&state, path, &openai(12, false), &three).await;
+        let body = json(&body);
+        assert_eq!(body["status"], "incomplete");
+        assert_eq!(body["incomplete_details"]["reason"], "max_output_tokens");
+        assert_eq!(body["usage"]["output_tokens"], 12);
+        assert_eq!(body["output"][2]["status"], "incomplete");
+        let (_, body) = post_json(&state, path, &openai(12, true), &three).await;
+        assert!(body.contains("event: response.incomplete"));
+        assert!(body.contains(r#""output_tokens":12"#));
+    }
+
+    #[tokio::test]
+    async fn test_stop_sequences() {
+        let config = Config {
+            fixtures: toml::from_str(
+                r#"
+            [[rules]]
+            match = { contains = "Script" }
+            response = { text = "Answer: 42\nHuman: more" }
+            "#,
+            )
+            .unwrap(),
+            content: config::ContentConfig {
+                stop_sequence_rate: 1.0,
+                ..config::ContentConfig::default()
+            },
+            ..Config::default()
+        };
+        let state = RuntimeState::new(config);
+        let json = |body: &str| serde_json::from_str::<serde_json::Value>(body).unwrap();
+
+        let claude = |content: &str, stream| {
+            serde_json::json!({
+                "model": "claude-haiku",
+                "max_tokens": 1024,
+                "stop_sequences": ["\nHuman:"],
+                "stream": stream,
+                "messages": [{"role": "user", "content": content}]
+            })
+        };
+        let (_, body) = post_json(&state, "/v1/messages", &claude("Script", false), &[]).await;
+        let body = json(&body);
+        assert_eq!(body["stop_reason"], "stop_sequence");
+        assert_eq!(body["stop_sequence"], "\nHuman:");
+        assert_eq!(body["content"][0]["text"], "Answer: 42");
+        let (_, body) = post_json(&state, "/v1/messages", &claude("Hello", true), &[]).await;
+        assert!(body.contains(r#""stop_sequence":"\nHuman:""#));
+        // Without a rate, generated text only stops where it contains a sequence.
+        let default_state = RuntimeState::new(Config::default());
+        let (_, body) =
+            post_json(&default_state, "/v1/messages", &claude("Hello", false), &[]).await;
+        assert_eq!(json(&body)["stop_reason"], "end_turn");
+
+        let cerebras = serde_json::json!({
+            "model": "llama3.1-8b",
+            "stop": "\nHuman:",
+            "messages": [{"role": "user", "content": "Script"}]
+        });
+        let (_, body) = post_json(&state, "/v1/chat/completions", &cerebras, &[]).await;
+        let body = json(&body);
+        assert_eq!(body["choices"][0]["message"]["content"], "Answer: 42");
+        assert_eq!(body["choices"][0]["finish_reason"], "stop");
+
+        let gemini = serde_json::json!({
+            "contents": [{"role": "user", "parts": [{"text": "Script"}]}],
+            "generationConfig": {"stopSequences": ["\nHuman:"]}
+        });
+        let path = "/v1beta/models/gemini-pro:generateContent";
+        let (_, body) = post_json(&state, path, &gemini, &[]).await;
+        let body = json(&body);
+        assert_eq!(
+            body["candidates"][0]["content"]["parts"][0]["text"],
+            "Answer: 42"
+        );
+        assert_eq!(body["candidates"][0]["finishReason"], "STOP");
+
+        let openai =
+            serde_json::json!({"model": "gpt-4o", "input": "Script", "stop": ["\nHuman:"]});
+        let (_, body) = post_json(&state, "/v1/responses", &openai, &[]).await;
+        let body = json(&body);
+        assert_eq!(body["output"][0]["content"][0]["text"], "Answer: 42");
+        assert_eq!(body["status"], "completed");
+    }
+
     #[tokio::test]
     async fn test_forced_error() {
         let mut config = Config::default();
@@ -8,6 +8,7 @@
 
 use std::net::SocketAddr;
 
+use tokenipsum::proxy::ProxyMode;
 use tokenipsum::{create_router, Config, RuntimeState};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
@@ -45,7 +46,7 @@ async fn main() {
         tracing::info!("OpenAI endpoint: POST /v1/responses");
     }
 
-    let app = create_router(state);
+    let app = create_router(state.clone());
 
     let addr = SocketAddr::from(([0, 0, 0, 0], port));
     tracing::info!("TokenIpsum listening on http://{}", addr);
@@ -56,6 +57,20 @@ async fn main() {
             config.rate_limit.fail_after_requests
         );
     }
+    if !state.fixtures().is_empty() {
+        tracing::info!("Loaded {} fixture rules", state.fixtures().len());
+    }
+    match config.proxy.mode {
+        ProxyMode::Off => {}
+        ProxyMode::Record => tracing::info!(
+            "Proxy: recording upstream responses to {}",
+            config.proxy.cassette_dir
+        ),
+        ProxyMode::Replay => tracing::info!(
+            "Proxy: replaying cassettes from {}",
+            config.proxy.cassette_dir
+        ),
+    }
     if config.errors.error_rate > 0.0 {
         tracing::info!(
             "Random errors enabled: {:.0}% chance",
@@ -6,18 +6,19 @@
 //! Endpoints:
 //! - POST /v1/responses - Non-streaming and streaming
 
-use crate::generator::ContentGenerator;
+use crate::fixtures::History;
+use crate::generator::{ContentGenerator, StopSequences};
+use crate::schema;
+use crate::timing::Events;
+use crate::tools::{ToolChoice, ToolMode};
+use crate::ProviderRequest;
 use axum::{
-    body::Body,
-    http::{header, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
-use futures::stream::{self, StreamExt};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use tokio::time::sleep;
+use std::collections::HashMap;
 
 /// Request body for responses endpoint.
 #[derive(Debug, Deserialize)]
@@ -31,6 +32,8 @@ pub struct ResponsesRequest {
     #[serde(default)]
     pub max_output_tokens: Option<u32>,
     #[serde(default)]
+    pub stop: Option<StopSequences>,
+    #[serde(default)]
     pub temperature: Option<f32>,
     #[serde(default)]
     pub top_p: Option<f32>,
@@ -39,6 +42,8 @@ pub struct ResponsesRequest {
     #[serde(default)]
     pub tool_choice: Option<Value>,
     #[serde(default)]
+    pub parallel_tool_calls: Option<bool>,
+    #[serde(default)]
     pub store: Option<bool>,
     #[serde(default)]
     pub reasoning: Option<ReasoningConfig>,
@@ -50,7 +55,16 @@ pub struct ResponsesRequest {
 #[serde(untagged)]
 pub enum InputType {
     Text(String),
-    Messages(Vec<InputMessage>),
+    Messages(Vec<InputItem>),
+}
+
+/// An input list item: a message, or one half of a function call round-trip.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum InputItem {
+    Message(InputMessage),
+    FunctionCall(FunctionCallItem),
+    FunctionCallOutput(FunctionCallOutputItem),
 }
 
 #[derive(Debug, Deserialize)]
@@ -59,6 +73,21 @@ pub struct InputMessage {
     pub content: MessageContent,
 }
 
+/// A `function_call` item echoed back from a previous response.
+#[derive(Debug, Deserialize)]
+pub struct FunctionCallItem {
+    pub call_id: String,
+    pub name: String,
+    pub arguments: String,
+}
+
+/// A `function_call_output` item carrying a tool result.
+#[derive(Debug, Deserialize)]
+pub struct FunctionCallOutputItem {
+    pub call_id: String,
+    pub output: String,
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(untagged)]
 pub enum MessageContent {
@@ -125,7 +154,7 @@ pub struct ResponsesResponse {
     pub store: bool,
     pub temperature: f32,
     pub text: TextOutput,
-    pub tool_choice: &'static str,
+    pub tool_choice: Value,
     pub tools: Vec<Value>,
     pub top_p: f32,
     pub truncation: &'static str,
@@ -205,9 +234,13 @@ pub struct TextFormatOutput {
 }
 
 /// Main handler for POST /v1/responses
-pub async fn responses(Json(req): Json<ResponsesRequest>) -> Response {
-    let gen = ContentGenerator::new();
-    let wants_tools = req.tools.is_some() && should_call_tool(&req);
+pub async fn responses(mut gen: ContentGenerator, Json(req): Json<ResponsesRequest>) -> Response {
+    let wants_tools = gen.scripted_tool_use().unwrap_or_else(|| {
+        !req.tool_names().is_empty()
+            && req
+                .tool_choice()
+                .should_call(|| should_call_tool(&req, &mut gen))
+    });
 
     if req.stream {
         stream_response(req, gen, wants_tools).await
@@ -216,30 +249,174 @@ pub async fn responses(Json(req): Json<ResponsesRequest>) -> Response {
     }
 }
 
-/// Decide if we should generate a tool call response.
-fn should_call_tool(req: &ResponsesRequest) -> bool {
-    let text = extract_input_text(&req.input);
-    if let Some(text) = text {
-        let lower = text.to_lowercase();
-        return lower.contains("weather")
-            || lower.contains("search")
-            || lower.contains("calculate")
-            || lower.contains("what is")
-            || lower.contains("find");
+impl ResponsesRequest {
+    /// Custom sequences that end generation.
+    pub fn stop_sequences(&self) -> &[String] {
+        self.stop.as_ref().map_or(&[], StopSequences::as_slice)
     }
-    false
+}
+
+impl ProviderRequest for ResponsesRequest {
+    /// Estimated prompt tokens, as reported in `usage.input_tokens`.
+    fn input_tokens(&self) -> u32 {
+        count_input_tokens(&self.input)
+    }
+
+    /// Requested output token limit.
+    fn max_output_tokens(&self) -> Option<u32> {
+        self.max_output_tokens
+    }
+
+    /// Tool calling constraints from `tool_choice` and `parallel_tool_calls`.
+    fn tool_choice(&self) -> ToolChoice {
+        let mode = |mode: &str| match mode {
+            "none" => ToolMode::None,
+            "required" => ToolMode::Required,
+            _ => ToolMode::Auto,
+        };
+        let mut choice = match &self.tool_choice {
+            Some(Value::String(m)) => ToolChoice::new(mode(m)),
+            Some(choice) => match choice["type"].as_str() {
+                Some("function") => match choice["name"].as_str() {
+                    Some(name) => ToolChoice::function(name),
+                    None => ToolChoice::default(),
+                },
+                Some("allowed_tools") => {
+                    let names = choice["tools"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|tool| tool["name"].as_str())
+                        .map(str::to_string)
+                        .collect();
+                    ToolChoice::new(mode(choice["mode"].as_str().unwrap_or_default()))
+                        .allowing(names)
+                }
+                _ => ToolChoice::default(),
+            },
+            None => ToolChoice::default(),
+        };
+        choice.parallel = self.parallel_tool_calls.unwrap_or(true);
+        choice
+    }
+
+    /// Names of the declared tools.
+    fn tool_names(&self) -> Vec<&str> {
+        self.tools
+            .iter()
+            .flatten()
+            .map(|t| t.name.as_str())
+            .collect()
+    }
+
+    /// Text of the most recent user message.
+    fn last_user_text(&self) -> Option<&str> {
+        match &self.input {
+            InputType::Text(t) => Some(t.as_str()),
+            InputType::Messages(items) => items
+                .iter()
+                .rev()
+                .filter_map(|item| match item {
+                    InputItem::Message(m) if m.role == "user" => Some(m),
+                    _ => None,
+                })
+                .find_map(|m| message_text(&m.content)),
+        }
+    }
+
+    /// Turn number and pending tool results, from `function_call` / `function_call_output` items.
+    fn history(&self) -> History {
+        let InputType::Messages(items) = &self.input else {
+            return History::from_entries(Vec::new());
+        };
+        let tool_names: HashMap<&str, &str> = items
+            .iter()
+            .filter_map(|item| match item {
+                InputItem::FunctionCall(call) => Some((call.call_id.as_str(), call.name.as_str())),
+                _ => None,
+            })
+            .collect();
+
+        History::from_entries(items.iter().map(|item| match item {
+            InputItem::Message(m) => (m.role == "assistant", None),
+            InputItem::FunctionCall(_) => (true, None),
+            InputItem::FunctionCallOutput(output) => {
+                let name = tool_names.get(output.call_id.as_str()).unwrap_or(&"");
+                (false, Some((*name).to_string()))
+            }
+        }))
+    }
+}
+
+fn message_text(content: &MessageContent) -> Option<&str> {
+    match content {
+        MessageContent::Text(t) => Some(t.as_str()),
+        MessageContent::Parts(parts) => parts.iter().find_map(|p| p.text.as_deref()),
+    }
+}
+
+/// Decide if we should generate a tool call response, from the latest input.
+fn should_call_tool(req: &ResponsesRequest, gen: &mut ContentGenerator) -> bool {
+    let tool_texts = req
+        .tools
+        .iter()
+        .flatten()
+        .flat_map(|t| [Some(t.name.as_str()), t.description.as_deref()])
+        .flatten();
+    gen.triggers_tool_call(extract_input_text(&req.input), tool_texts)
 }
 
 fn extract_input_text(input: &InputType) -> Option<&str> {
     match input {
         InputType::Text(t) => Some(t.as_str()),
-        InputType::Messages(msgs) => msgs.last().and_then(|m| match &m.content {
-            MessageContent::Text(t) => Some(t.as_str()),
-            MessageContent::Parts(parts) => parts.iter().find_map(|p| p.text.as_deref()),
+        InputType::Messages(items) => items.last().and_then(|item| match item {
+            InputItem::Message(m) => message_text(&m.content),
+            _ => None,
         }),
     }
 }
 
+/// Pick the tool names and JSON arguments for a function call reply,
+/// preferring scripted calls. Generated calls go to the tools `tool_choice`
+/// allows in order, wrapping around when there are more calls than tools.
+fn tool_calls(req: &ResponsesRequest, gen: &mut ContentGenerator) -> Vec<(String, String)> {
+    if let Some(calls) = gen.take_scripted_tool_calls() {
+        return calls
+            .into_iter()
+            .map(|call| (call.name, gen.encode_arguments(&call.arguments)))
+            .collect();
+    }
+    let arg_value = extract_input_text(&req.input).map_or_else(
+        || "unknown".to_string(),
+        |t| {
+            t.split_whitespace()
+                .rfind(|w| w.len() > 2)
+                .unwrap_or("unknown")
+                .to_string()
+        },
+    );
+    let choice = req.tool_choice();
+    let tools: Vec<&Tool> = req
+        .tools
+        .iter()
+        .flatten()
+        .filter(|tool| choice.allows(&tool.name))
+        .collect();
+    let count = gen.tool_call_count(choice.parallel);
+    tools
+        .iter()
+        .cycle()
+        .take(count)
+        .map(|tool| {
+            let arguments = match &tool.parameters {
+                Some(parameters) => schema::arguments(parameters, &arg_value, gen),
+                None => json!({ "location": arg_value }),
+            };
+            (tool.name.clone(), gen.encode_arguments(&arguments))
+        })
+        .collect()
+}
+
 fn generate_response_id(gen: &mut ContentGenerator) -> String {
     format!("resp_{}", gen.tool_call_id())
 }
@@ -252,25 +429,24 @@ fn generate_call_id(gen: &mut ContentGenerator) -> String {
     format!("call_{}", gen.tool_call_id())
 }
 
-fn now_unix() -> u64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs()
-}
-
 fn count_input_tokens(input: &InputType) -> u32 {
     match input {
         InputType::Text(t) => ContentGenerator::estimate_tokens(t),
-        InputType::Messages(msgs) => msgs
+        InputType::Messages(items) => items
             .iter()
-            .map(|m| match &m.content {
-                MessageContent::Text(t) => ContentGenerator::estimate_tokens(t),
-                MessageContent::Parts(parts) => parts
-                    .iter()
-                    .filter_map(|p| p.text.as_ref())
-                    .map(|t| ContentGenerator::estimate_tokens(t))
-                    .sum(),
+            .map(|item| match item {
+                InputItem::Message(m) => match &m.content {
+                    MessageContent::Text(t) => ContentGenerator::estimate_tokens(t),
+                    MessageContent::Parts(parts) => parts
+                        .iter()
+                        .filter_map(|p| p.text.as_ref())
+                        .map(|t| ContentGenerator::estimate_tokens(t))
+                        .sum(),
+                },
+                InputItem::FunctionCall(call) => ContentGenerator::estimate_tokens(&call.arguments),
+                InputItem::FunctionCallOutput(output) => {
+                    ContentGenerator::estimate_tokens(&output.output)
+                }
             })
             .sum(),
     }
@@ -283,39 +459,42 @@ fn non_stream_response(
     wants_tools: bool,
 ) -> Response {
     let id = generate_response_id(&mut gen);
-    let created_at = now_unix();
+    let created_at = gen.now_unix();
     let input_tokens = count_input_tokens(&req.input);
 
-    let (output, output_tokens) = if wants_tools {
-        let tool = req.tools.as_ref().and_then(|t| t.first());
-        let tool_name = tool.map_or_else(|| "unknown".to_string(), |t| t.name.clone());
-        let arg_value = extract_input_text(&req.input).map_or_else(
-            || "unknown".to_string(),
-            |t| {
-                t.split_whitespace()
-                    .rfind(|w| w.len() > 2)
-                    .unwrap_or("unknown")
-                    .to_string()
-            },
-        );
-
-        (
-            vec![OutputItem::FunctionCall {
+    let (output, output_tokens, truncated) = if wants_tools {
+        let (names, mut arguments): (Vec<String>, Vec<String>) =
+            tool_calls(&req, &mut gen).into_iter().unzip();
+        let (tokens, truncated) =
+            ContentGenerator::limit_tool_arguments(&mut arguments, req.max_output_tokens);
+        let count = arguments.len();
+        let output: Vec<OutputItem> = names
+            .into_iter()
+            .zip(arguments)
+            .enumerate()
+            .map(|(i, (tool_name, arguments))| OutputItem::FunctionCall {
                 id: format!("fc_{}", gen.tool_call_id()),
-                status: "completed",
+                status: if truncated && i + 1 == count {
+                    "incomplete"
+                } else {
+                    "completed"
+                },
                 name: tool_name,
-                arguments: json!({"location": arg_value}).to_string(),
+                arguments,
                 call_id: generate_call_id(&mut gen),
-            }],
-            15u32,
-        )
+            })
+            .collect();
+        (output, tokens, truncated)
     } else {
-        let content = gen.paragraph();
+        let (mut content, _) = gen.reply_text(ContentGenerator::paragraph, req.stop_sequences());
+        let truncated = req
+            .max_output_tokens
+            .is_some_and(|max| ContentGenerator::truncate_to_tokens(&mut content, max));
         let tokens = ContentGenerator::estimate_tokens(&content);
         (
             vec![OutputItem::Message {
                 id: generate_message_id(&mut gen),
-                status: "completed",
+                status: if truncated { "incomplete" } else { "completed" },
                 content: vec![OutputContent {
                     content_type: "output_text",
                     annotations: vec![],
@@ -325,6 +504,7 @@ fn non_stream_response(
                 role: "assistant",
             }],
             tokens,
+            truncated,
         )
     };
 
@@ -332,7 +512,7 @@ fn non_stream_response(
         id,
         object: "response",
         created_at,
-        status: "completed",
+        status: if truncated { "incomplete" } else { "completed" },
         background: false,
         model: req.model,
         output,
@@ -346,13 +526,13 @@ fn non_stream_response(
             total_tokens: input_tokens + output_tokens,
         },
         billing: Some(Billing { payer: "openai" }),
-        completed_at: Some(now_unix()),
+        completed_at: (!truncated).then(|| gen.now_unix()),
         error: None,
-        incomplete_details: None,
+        incomplete_details: truncated.then(|| json!({ "reason": "max_output_tokens" })),
         instructions: req.instructions,
         max_output_tokens: req.max_output_tokens,
         max_tool_calls: None,
-        parallel_tool_calls: true,
+        parallel_tool_calls: req.parallel_tool_calls.unwrap_or(true),
         previous_response_id: None,
         reasoning: ReasoningOutput {
             effort: req.reasoning.as_ref().and_then(|r| r.effort.clone()),
@@ -367,7 +547,7 @@ fn non_stream_response(
             },
             verbosity: "medium",
         },
-        tool_choice: "auto",
+        tool_choice: req.tool_choice.unwrap_or_else(|| json!("auto")),
         tools: vec![],
         top_p: req.top_p.unwrap_or(1.0),
         truncation: "disabled",
@@ -386,10 +566,10 @@ async fn stream_response(
 ) -> Response {
     let id = generate_response_id(&mut gen);
     let model = req.model.clone();
-    let created_at = now_unix();
+    let created_at = gen.now_unix();
     let input_tokens = count_input_tokens(&req.input);
 
-    let mut events: Vec<String> = Vec::new();
+    let mut events = Events::new();
     let mut seq = 0u32;
 
     // Helper to create event
@@ -433,97 +613,101 @@ async fn stream_response(
     seq += 1;
 
     let output_tokens;
+    let truncated;
 
     if wants_tools {
-        let tool = req.tools.as_ref().and_then(|t| t.first());
-        let tool_name = tool.map_or_else(|| "unknown".to_string(), |t| t.name.clone());
-        let arg_value = extract_input_text(&req.input).map_or_else(
-            || "unknown".to_string(),
-            |t| {
-                t.split_whitespace()
-                    .rfind(|w| w.len() > 2)
-                    .unwrap_or("unknown")
-                    .to_string()
-            },
-        );
-        let fc_id = format!("fc_{}", gen.tool_call_id());
-        let call_id = generate_call_id(&mut gen);
-
-        output_tokens = 15u32;
+        let (names, mut arguments): (Vec<String>, Vec<String>) =
+            tool_calls(&req, &mut gen).into_iter().unzip();
+        (output_tokens, truncated) =
+            ContentGenerator::limit_tool_arguments(&mut arguments, req.max_output_tokens);
+        let count = arguments.len();
+
+        for (output_index, (tool_name, args)) in names.into_iter().zip(arguments).enumerate() {
+            let status = if truncated && output_index + 1 == count {
+                "incomplete"
+            } else {
+                "completed"
+            };
+            let fc_id = format!("fc_{}", gen.tool_call_id());
+            let call_id = generate_call_id(&mut gen);
 
-        // output_item.added for function_call
-        events.push(event(
-            "response.output_item.added",
-            json!({
-                "type": "response.output_item.added",
-                "sequence_number": seq,
-                "output_index": 0,
-                "item": {
-                    "id": &fc_id,
-                    "type": "function_call",
-                    "status": "in_progress",
-                    "name": &tool_name,
-                    "arguments": "",
-                    "call_id": &call_id
-                }
-            }),
-        ));
-        seq += 1;
+            // output_item.added for function_call
+            events.push(event(
+                "response.output_item.added",
+                json!({
+                    "type": "response.output_item.added",
+                    "sequence_number": seq,
+                    "output_index": output_index,
+                    "item": {
+                        "id": &fc_id,
+                        "type": "function_call",
+                        "status": "in_progress",
+                        "name": &tool_name,
+                        "arguments": "",
+                        "call_id": &call_id
+                    }
+                }),
+            ));
+            seq += 1;
 
-        // function_call_arguments.delta
-        let args = json!({"location": arg_value}).to_string();
-        events.push(event(
-            "response.function_call_arguments.delta",
-            json!({
-                "type": "response.function_call_arguments.delta",
-                "sequence_number": seq,
-                "item_id": &fc_id,
-                "output_index": 0,
-                "delta": &args
-            }),
-        ));
-        seq += 1;
+            // function_call_arguments.delta for each fragment of the arguments
+            for delta in gen.argument_deltas(&args) {
+                let tokens = ContentGenerator::estimate_tokens(&delta);
+                events.push_tokens(
+                    event(
+                        "response.function_call_arguments.delta",
+                        json!({
+                            "type": "response.function_call_arguments.delta",
+                            "sequence_number": seq,
+                            "item_id": &fc_id,
+                            "output_index": output_index,
+                            "delta": delta
+                        }),
+                    ),
+                    tokens,
+                );
+                seq += 1;
+            }
 
-        // function_call_arguments.done
-        events.push(event(
-            "response.function_call_arguments.done",
-            json!({
-                "type": "response.function_call_arguments.done",
-                "sequence_number": seq,
-                "item_id": &fc_id,
-                "output_index": 0,
-                "arguments": &args
-            }),
-        ));
-        seq += 1;
+            // function_call_arguments.done
+            events.push(event(
+                "response.function_call_arguments.done",
+                json!({
+                    "type": "response.function_call_arguments.done",
+                    "sequence_number": seq,
+                    "item_id": &fc_id,
+                    "output_index": output_index,
+                    "arguments": &args
+                }),
+            ));
+            seq += 1;
 
-        // output_item.done
-        events.push(event(
-            "response.output_item.done",
-            json!({
-                "type": "response.output_item.done",
-                "sequence_number": seq,
-                "output_index": 0,
-                "item": {
-                    "id": &fc_id,
-                    "type": "function_call",
-                    "status": "completed",
-                    "name": &tool_name,
-                    "arguments": &args,
-                    "call_id": &call_id
-                }
-            }),
-        ));
-        seq += 1;
+            // output_item.done
+            events.push(event(
+                "response.output_item.done",
+                json!({
+                    "type": "response.output_item.done",
+                    "sequence_number": seq,
+                    "output_index": output_index,
+                    "item": {
+                        "id": &fc_id,
+                        "type": "function_call",
+                        "status": status,
+                        "name": &tool_name,
+                        "arguments": &args,
+                        "call_id": &call_id
+                    }
+                }),
+            ));
+            seq += 1;
+        }
     } else {
         let msg_id = generate_message_id(&mut gen);
-        let content_parts = gen.stream_chunks(req.max_output_tokens.unwrap_or(50) as usize);
+        let (text, _) = gen.reply_text(ContentGenerator::paragraph, req.stop_sequences());
+        let mut deltas = gen.chunk_text(&text);
+        truncated = ContentGenerator::limit_chunks(&mut deltas, req.max_output_tokens);
 
-        output_tokens = content_parts
-            .iter()
-            .map(|c| ContentGenerator::estimate_tokens(c))
-            .sum::<u32>()
-            .max(1);
+        output_tokens = ContentGenerator::estimate_tokens(&deltas.concat()).max(1);
 
         // output_item.added
         events.push(event(
@@ -564,26 +748,24 @@ async fn stream_response(
 
         // output_text.delta for each chunk
         let mut full_text = String::new();
-        for (i, chunk) in content_parts.iter().enumerate() {
-            let delta = if i > 0 {
-                format!(" {chunk}")
-            } else {
-                chunk.clone()
-            };
-            full_text.push_str(&delta);
-
-            events.push(event(
-                "response.output_text.delta",
-                json!({
-                    "type": "response.output_text.delta",
-                    "sequence_number": seq,
-                    "item_id": &msg_id,
-                    "output_index": 0,
-                    "content_index": 0,
-                    "delta": &delta,
-                    "logprobs": []
-                }),
-            ));
+        for delta in &deltas {
+            full_text.push_str(delta);
+
+            events.push_tokens(
+                event(
+                    "response.output_text.delta",
+                    json!({
+                        "type": "response.output_text.delta",
+                        "sequence_number": seq,
+                        "item_id": &msg_id,
+                        "output_index": 0,
+                        "content_index": 0,
+                        "delta": delta,
+                        "logprobs": []
+                    }),
+                ),
+                ContentGenerator::estimate_tokens(delta),
+            );
             seq += 1;
         }
 
@@ -631,7 +813,7 @@ async fn stream_response(
                 "item": {
                     "id": &msg_id,
                     "type": "message",
-                    "status": "completed",
+                    "status": if truncated { "incomplete" } else { "completed" },
                     "content": [{
                         "type": "output_text",
                         "annotations": [],
@@ -645,18 +827,34 @@ async fn stream_response(
         seq += 1;
     }
 
-    // response.completed
+    // response.completed, or response.incomplete when cut at max_output_tokens
+    let (name, status, completed_at, incomplete_details) = if truncated {
+        (
+            "response.incomplete",
+            "incomplete",
+            Value::Null,
+            json!({ "reason": "max_output_tokens" }),
+        )
+    } else {
+        (
+            "response.completed",
+            "completed",
+            json!(gen.now_unix()),
+            Value::Null,
+        )
+    };
     events.push(event(
-        "response.completed",
+        name,
         json!({
-            "type": "response.completed",
+            "type": name,
             "sequence_number": seq,
             "response": {
                 "id": &id,
                 "object": "response",
                 "created_at": created_at,
-                "status": "completed",
-                "completed_at": now_unix(),
+                "status": status,
+                "completed_at": completed_at,
+                "incomplete_details": incomplete_details,
                 "model": &model,
                 "output": [],
                 "usage": {
@@ -670,28 +868,13 @@ async fn stream_response(
         }),
     ));
 
-    // Build the stream
-    let stream = stream::iter(events)
-        .then(|event| async move {
-            sleep(Duration::from_millis(10)).await;
-            event
-        })
-        .map(Ok::<_, std::convert::Infallible>);
-
-    let body = Body::from_stream(stream);
-
-    Response::builder()
-        .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, "text/event-stream")
-        .header(header::CACHE_CONTROL, "no-cache")
-        .header(header::CONNECTION, "keep-alive")
-        .body(body)
-        .unwrap()
+    gen.paced_response(events)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use axum::http::StatusCode;
 
     #[test]
     fn test_deserialize_simple_request() {
@@ -726,7 +909,7 @@ mod tests {
         }"#;
         let req: ResponsesRequest = serde_json::from_str(json).unwrap();
         assert!(req.tools.is_some());
-        assert!(should_call_tool(&req));
+        assert!(should_call_tool(&req, &mut ContentGenerator::new()));
     }
 
     #[test]
@@ -780,7 +963,7 @@ mod tests {
                 },
                 verbosity: "medium",
             },
-            tool_choice: "auto",
+            tool_choice: json!("auto"),
             tools: vec![],
             top_p: 1.0,
             truncation: "disabled",
@@ -800,17 +983,38 @@ mod tests {
             input: InputType::Text("Hello there!".to_string()),
             stream: false,
             max_output_tokens: None,
+            stop: None,
             temperature: None,
             top_p: None,
             tools: Some(vec![]),
             tool_choice: None,
+            parallel_tool_calls: None,
             reasoning: None,
             text: None,
             instructions: None,
             store: None,
         };
 
-        assert!(!should_call_tool(&req));
+        assert!(!should_call_tool(&req, &mut ContentGenerator::new()));
+    }
+
+    #[test]
+    fn test_history_after_function_call_output() {
+        let json = r#"{
+            "model": "gpt-4o",
+            "input": [
+                {"role": "user", "content": "What is the weather in Lima?"},
+                {"type": "function_call", "call_id": "call_1", "name": "get_weather",
+                 "arguments": "{}"},
+                {"type": "function_call_output", "call_id": "call_1", "output": "Sunny"}
+            ]
+        }"#;
+        let req: ResponsesRequest = serde_json::from_str(json).unwrap();
+
+        let history = req.history();
+        assert_eq!(history.turn, 2);
+        assert_eq!(history.tool_names, vec!["get_weather".to_string()]);
+        assert_eq!(req.last_user_text(), Some("What is the weather in Lima?"));
     }
 
     #[test]
@@ -822,10 +1026,10 @@ mod tests {
 
     #[test]
     fn test_extract_input_text_messages() {
-        let input = InputType::Messages(vec![InputMessage {
+        let input = InputType::Messages(vec![InputItem::Message(InputMessage {
             role: "user".to_string(),
             content: MessageContent::Text("Hello from messages".to_string()),
-        }]);
+        })]);
         let text = extract_input_text(&input);
         assert_eq!(text, Some("Hello from messages"));
     }
@@ -837,17 +1041,19 @@ mod tests {
             input: InputType::Text("Hello".to_string()),
             stream: false,
             max_output_tokens: Some(50),
+            stop: None,
             temperature: None,
             top_p: None,
             tools: None,
             tool_choice: None,
+            parallel_tool_calls: None,
             reasoning: None,
             text: None,
             instructions: None,
             store: None,
         };
 
-        let response = responses(Json(req)).await;
+        let response = responses(ContentGenerator::new(), Json(req)).await;
         assert_eq!(response.status(), StatusCode::OK);
     }
 
@@ -858,17 +1064,19 @@ mod tests {
             input: InputType::Text("Hello".to_string()),
             stream: true,
             max_output_tokens: Some(50),
+            stop: None,
             temperature: None,
             top_p: None,
             tools: None,
             tool_choice: None,
+            parallel_tool_calls: None,
             reasoning: None,
             text: None,
             instructions: None,
             store: None,
         };
 
-        let response = responses(Json(req)).await;
+        let response = responses(ContentGenerator::new(), Json(req)).await;
         assert_eq!(response.status(), StatusCode::OK);
         assert_eq!(
             response.headers().get("content-type").unwrap(),
@@ -883,6 +1091,7 @@ mod tests {
             input: InputType::Text("What is the weather in NYC?".to_string()),
             stream: false,
             max_output_tokens: Some(50),
+            stop: None,
             temperature: None,
             top_p: None,
             tools: Some(vec![Tool {
@@ -892,13 +1101,14 @@ mod tests {
                 parameters: None,
             }]),
             tool_choice: None,
+            parallel_tool_calls: None,
             reasoning: None,
             text: None,
             instructions: None,
             store: None,
         };
 
-        let response = responses(Json(req)).await;
+        let response = responses(ContentGenerator::new(), Json(req)).await;
         assert_eq!(response.status(), StatusCode::OK);
     }
 
@@ -906,23 +1116,25 @@ mod tests {
     async fn test_responses_with_messages_input() {
         let req = ResponsesRequest {
             model: "gpt-4o".to_string(),
-            input: InputType::Messages(vec![InputMessage {
+            input: InputType::Messages(vec![InputItem::Message(InputMessage {
                 role: "user".to_string(),
                 content: MessageContent::Text("Hello".to_string()),
-            }]),
+            })]),
             stream: false,
             max_output_tokens: Some(50),
+            stop: None,
             temperature: None,
             top_p: None,
             tools: None,
             tool_choice: None,
+            parallel_tool_calls: None,
             reasoning: None,
             text: None,
             instructions: None,
             store: None,
         };
 
-        let response = responses(Json(req)).await;
+        let response = responses(ContentGenerator::new(), Json(req)).await;
         assert_eq!(response.status(), StatusCode::OK);
     }
 }
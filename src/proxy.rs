@@ -0,0 +1,612 @@
+//! Record-and-replay proxy against real provider APIs.
+//!
+//! In `record` mode requests are forwarded to the configured upstream for
+//! their provider and every request/response pair is written to a cassette
+//! file, including the full SSE event sequence with inter-event timing.
+//! In `replay` mode cassettes are served offline: requests are matched by
+//! provider, path and normalized JSON body, so key order and credentials
+//! don't affect matching.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use axum::{
+    body::{Body, Bytes},
+    http::{header::CONTENT_TYPE, HeaderMap, HeaderName, HeaderValue, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures::stream::{self, BoxStream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::time::sleep;
+
+use crate::config::derive_seed;
+use crate::errors::Provider;
+
+/// Proxy settings in the main config.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProxyConfig {
+    pub mode: ProxyMode,
+    /// Directory cassettes are written to and read from.
+    pub cassette_dir: String,
+    /// Sleep for the recorded latency and event gaps when replaying.
+    pub replay_timing: bool,
+    pub upstreams: Upstreams,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProxyMode {
+    /// Generate responses locally.
+    #[default]
+    Off,
+    /// Forward to the upstream and save cassettes.
+    Record,
+    /// Serve saved cassettes without network access.
+    Replay,
+}
+
+/// Upstream base URLs, one per provider.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Upstreams {
+    pub cerebras: String,
+    pub gemini: String,
+    pub claude: String,
+    pub openai: String,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        Self {
+            mode: ProxyMode::Off,
+            cassette_dir: "cassettes".to_string(),
+            replay_timing: true,
+            upstreams: Upstreams::default(),
+        }
+    }
+}
+
+impl Default for Upstreams {
+    fn default() -> Self {
+        Self {
+            cerebras: "https://api.cerebras.ai".to_string(),
+            gemini: "https://generativelanguage.googleapis.com".to_string(),
+            claude: "https://api.anthropic.com".to_string(),
+            openai: "https://api.openai.com".to_string(),
+        }
+    }
+}
+
+impl Upstreams {
+    fn base_url(&self, provider: Provider) -> &str {
+        match provider {
+            Provider::Cerebras => &self.cerebras,
+            Provider::Gemini => &self.gemini,
+            Provider::Claude => &self.claude,
+            Provider::OpenAI => &self.openai,
+        }
+    }
+}
+
+/// A recorded request/response pair.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Cassette {
+    pub request: RecordedRequest,
+    pub response: RecordedResponse,
+}
+
+/// The normalized request a cassette answers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    pub provider: Provider,
+    /// Path and query, without credentials.
+    pub path: String,
+    pub body: Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    pub status: u16,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Time from sending the request to receiving response headers.
+    #[serde(default)]
+    pub latency_ms: u64,
+    /// Non-streaming body (JSON, or a string for anything else).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<Value>,
+    /// SSE events of a streaming response, in order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<RecordedEvent>,
+}
+
+/// One SSE event, raw and including its trailing blank line.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedEvent {
+    /// Time since the previous event (or since the response headers).
+    pub delay_ms: u64,
+    pub data: String,
+}
+
+/// Records and replays cassettes, sharing one HTTP client across requests.
+#[derive(Debug, Default)]
+pub struct Proxy {
+    client: reqwest::Client,
+}
+
+/// Request headers that are never forwarded upstream.
+const SKIPPED_REQUEST_HEADERS: &[&str] = &[
+    "host",
+    "content-length",
+    "connection",
+    "accept-encoding",
+    "transfer-encoding",
+];
+
+/// Response headers that are neither recorded nor passed through.
+const SKIPPED_RESPONSE_HEADERS: &[&str] = &[
+    "content-length",
+    "connection",
+    "transfer-encoding",
+    "content-encoding",
+    "date",
+    "set-cookie",
+];
+
+impl Proxy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record or replay the request, or `None` when proxying is off.
+    pub async fn handle(
+        &self,
+        config: &ProxyConfig,
+        provider: Provider,
+        uri: &Uri,
+        headers: &HeaderMap,
+        body: &Bytes,
+    ) -> Option<Response> {
+        let request = RecordedRequest::new(provider, uri, body);
+        match config.mode {
+            ProxyMode::Off => None,
+            ProxyMode::Record => Some(
+                self.record(config, request, uri, headers, body.clone())
+                    .await,
+            ),
+            ProxyMode::Replay => Some(replay(config, &request).await),
+        }
+    }
+
+    async fn record(
+        &self,
+        config: &ProxyConfig,
+        request: RecordedRequest,
+        uri: &Uri,
+        headers: &HeaderMap,
+        body: Bytes,
+    ) -> Response {
+        let base = config.upstreams.base_url(request.provider);
+        let path_and_query = uri.path_and_query().map_or(uri.path(), |p| p.as_str());
+        let url = format!("{}{}", base.trim_end_matches('/'), path_and_query);
+
+        let mut upstream = self.client.post(&url).body(body);
+        for (name, value) in headers {
+            let name_str = name.as_str();
+            if !SKIPPED_REQUEST_HEADERS.contains(&name_str)
+                && !name_str.starts_with("x-tokenipsum-")
+            {
+                upstream = upstream.header(name, value);
+            }
+        }
+
+        let started = Instant::now();
+        let upstream = match upstream.send().await {
+            Ok(response) => response,
+            Err(e) => {
+                tracing::warn!("Upstream request to {} failed: {}", url, e);
+                return proxy_error(
+                    StatusCode::BAD_GATEWAY,
+                    "upstream_error",
+                    &format!("Upstream request to {url} failed: {e}"),
+                );
+            }
+        };
+
+        let mut response = RecordedResponse {
+            status: upstream.status().as_u16(),
+            headers: upstream
+                .headers()
+                .iter()
+                .filter(|(name, _)| !SKIPPED_RESPONSE_HEADERS.contains(&name.as_str()))
+                .filter_map(|(name, value)| {
+                    Some((name.as_str().to_string(), value.to_str().ok()?.to_string()))
+                })
+                .collect(),
+            latency_ms: elapsed_ms(started),
+            body: None,
+            events: Vec::new(),
+        };
+        let path = cassette_path(config, &request);
+
+        if !response.is_stream() {
+            let bytes = match upstream.bytes().await {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    return proxy_error(
+                        StatusCode::BAD_GATEWAY,
+                        "upstream_error",
+                        &format!("Failed to read upstream response: {e}"),
+                    )
+                }
+            };
+            response.body = Some(
+                serde_json::from_slice(&bytes)
+                    .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into())),
+            );
+            let head = response.head();
+            save(&path, &Cassette { request, response });
+            return head.body(Body::from(bytes)).unwrap();
+        }
+
+        // Pass chunks through as they arrive, splitting them into events on the side.
+        let head = response.head();
+        let recorder = Recorder {
+            upstream: upstream.bytes_stream().boxed(),
+            buffer: Vec::new(),
+            last_event: Instant::now(),
+            cassette: Cassette { request, response },
+            path,
+        };
+        let stream = stream::unfold(Some(recorder), |recorder| async move {
+            let mut recorder = recorder?;
+            match recorder.upstream.next().await {
+                Some(Ok(chunk)) => {
+                    recorder.push(&chunk);
+                    Some((Ok(chunk), Some(recorder)))
+                }
+                Some(Err(e)) => {
+                    tracing::warn!("Upstream stream failed, cassette not saved: {}", e);
+                    Some((Err(e), None))
+                }
+                None => {
+                    recorder.finish();
+                    None
+                }
+            }
+        });
+        head.body(Body::from_stream(stream)).unwrap()
+    }
+}
+
+async fn replay(config: &ProxyConfig, request: &RecordedRequest) -> Response {
+    let path = cassette_path(config, request);
+    let Some(cassette) = load(&path) else {
+        return proxy_error(
+            StatusCode::NOT_FOUND,
+            "cassette_not_found",
+            &format!(
+                "No cassette for {} request to {} (expected {})",
+                provider_name(request.provider),
+                request.path,
+                path.display()
+            ),
+        );
+    };
+
+    let timing = config.replay_timing;
+    let recorded = cassette.response;
+    if timing && recorded.latency_ms > 0 {
+        sleep(Duration::from_millis(recorded.latency_ms)).await;
+    }
+
+    let head = recorded.head();
+    if !recorded.is_stream() {
+        let body = match recorded.body {
+            Some(Value::String(text)) => text,
+            Some(value) => value.to_string(),
+            None => String::new(),
+        };
+        return head.body(Body::from(body)).unwrap();
+    }
+
+    let events = stream::iter(recorded.events).then(move |event| async move {
+        if timing && event.delay_ms > 0 {
+            sleep(Duration::from_millis(event.delay_ms)).await;
+        }
+        Ok::<_, std::convert::Infallible>(event.data)
+    });
+    head.body(Body::from_stream(events)).unwrap()
+}
+
+fn cassette_path(config: &ProxyConfig, request: &RecordedRequest) -> PathBuf {
+    Path::new(&config.cassette_dir).join(request.file_name())
+}
+
+impl RecordedRequest {
+    fn new(provider: Provider, uri: &Uri, body: &[u8]) -> Self {
+        let body = serde_json::from_slice(body)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(body).into()));
+        Self {
+            provider,
+            path: normalize_path(uri),
+            body,
+        }
+    }
+
+    /// Stable matching key: provider, path and canonical body.
+    fn key(&self) -> u64 {
+        let key = format!(
+            "{} {}\n{}",
+            provider_name(self.provider),
+            self.path,
+            canonical(&self.body)
+        );
+        derive_seed(0, key.as_bytes())
+    }
+
+    fn file_name(&self) -> String {
+        format!("{}-{:016x}.json", provider_name(self.provider), self.key())
+    }
+}
+
+impl RecordedResponse {
+    fn is_stream(&self) -> bool {
+        self.headers
+            .get(CONTENT_TYPE.as_str())
+            .is_some_and(|ct| ct.starts_with("text/event-stream"))
+    }
+
+    /// Response builder with the recorded status and headers.
+    fn head(&self) -> axum::http::response::Builder {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::BAD_GATEWAY);
+        let mut builder = Response::builder().status(status);
+        for (name, value) in &self.headers {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::try_from(name.as_str()),
+                HeaderValue::try_from(value.as_str()),
+            ) {
+                builder = builder.header(name, value);
+            }
+        }
+        builder
+    }
+}
+
+/// State of a streaming response being recorded.
+struct Recorder {
+    upstream: BoxStream<'static, reqwest::Result<Bytes>>,
+    buffer: Vec<u8>,
+    last_event: Instant,
+    cassette: Cassette,
+    path: PathBuf,
+}
+
+impl Recorder {
+    fn push(&mut self, chunk: &[u8]) {
+        self.buffer.extend_from_slice(chunk);
+        while let Some(end) = event_end(&self.buffer) {
+            let data: Vec<u8> = self.buffer.drain(..end).collect();
+            self.record_event(&data);
+        }
+    }
+
+    fn record_event(&mut self, data: &[u8]) {
+        let delay_ms = elapsed_ms(self.last_event);
+        self.last_event = Instant::now();
+        self.cassette.response.events.push(RecordedEvent {
+            delay_ms,
+            data: String::from_utf8_lossy(data).into_owned(),
+        });
+    }
+
+    fn finish(mut self) {
+        if !self.buffer.is_empty() {
+            let data = std::mem::take(&mut self.buffer);
+            self.record_event(&data);
+        }
+        save(&self.path, &self.cassette);
+    }
+}
+
+/// End of the first complete SSE event in `buffer`, including its blank line.
+pub(crate) fn event_end(buffer: &[u8]) -> Option<usize> {
+    [&b"\r\n\r\n"[..], &b"\n\n"[..]]
+        .iter()
+        .filter_map(|sep| {
+            buffer
+                .windows(sep.len())
+                .position(|w| w == *sep)
+                .map(|i| i + sep.len())
+        })
+        .min()
+}
+
+/// Path and query with API keys removed.
+fn normalize_path(uri: &Uri) -> String {
+    let Some(query) = uri.query() else {
+        return uri.path().to_string();
+    };
+    let params: Vec<&str> = query
+        .split('&')
+        .filter(|param| !param.is_empty() && !param.starts_with("key="))
+        .collect();
+    if params.is_empty() {
+        uri.path().to_string()
+    } else {
+        format!("{}?{}", uri.path(), params.join("&"))
+    }
+}
+
+/// JSON with object keys sorted, so key order doesn't affect matching.
+fn canonical(value: &Value) -> String {
+    match value {
+        Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            let fields: Vec<String> = entries
+                .into_iter()
+                .map(|(k, v)| format!("{}:{}", Value::String(k.clone()), canonical(v)))
+                .collect();
+            format!("{{{}}}", fields.join(","))
+        }
+        Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(canonical).collect();
+            format!("[{}]", items.join(","))
+        }
+        other => other.to_string(),
+    }
+}
+
+fn provider_name(provider: Provider) -> &'static str {
+    match provider {
+        Provider::Cerebras => "cerebras",
+        Provider::Gemini => "gemini",
+        Provider::Claude => "claude",
+        Provider::OpenAI => "openai",
+    }
+}
+
+fn elapsed_ms(since: Instant) -> u64 {
+    u64::try_from(since.elapsed().as_millis()).unwrap_or(u64::MAX)
+}
+
+fn load(path: &Path) -> Option<Cassette> {
+    let content = std::fs::read_to_string(path).ok()?;
+    serde_json::from_str(&content)
+        .map_err(|e| tracing::warn!("Failed to parse cassette {}: {}", path.display(), e))
+        .ok()
+}
+
+fn save(path: &Path, cassette: &Cassette) {
+    let result = path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|()| {
+            let json = serde_json::to_string_pretty(cassette).map_err(std::io::Error::other)?;
+            std::fs::write(path, json)
+        });
+    match result {
+        Ok(()) => tracing::info!("Recorded cassette {}", path.display()),
+        Err(e) => tracing::warn!("Failed to write cassette {}: {}", path.display(), e),
+    }
+}
+
+fn proxy_error(status: StatusCode, kind: &str, message: &str) -> Response {
+    (
+        status,
+        Json(json!({
+            "error": {
+                "type": kind,
+                "message": message
+            }
+        })),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_proxy_config() {
+        let config: ProxyConfig = toml::from_str(
+            r#"
+            mode = "replay"
+            cassette_dir = "tests/cassettes"
+            upstreams = { claude = "http://localhost:9000" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.mode, ProxyMode::Replay);
+        assert_eq!(config.cassette_dir, "tests/cassettes");
+        assert!(config.replay_timing);
+        assert_eq!(config.upstreams.claude, "http://localhost:9000");
+        assert_eq!(config.upstreams.openai, "https://api.openai.com");
+    }
+
+    #[test]
+    fn test_normalize_path_strips_key() {
+        let uri: Uri = "/v1beta/models/gemini-pro:streamGenerateContent?alt=sse&key=secret"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            normalize_path(&uri),
+            "/v1beta/models/gemini-pro:streamGenerateContent?alt=sse"
+        );
+
+        let uri: Uri = "/v1beta/models/gemini-pro:generateContent?key=secret"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            normalize_path(&uri),
+            "/v1beta/models/gemini-pro:generateContent"
+        );
+    }
+
+    #[test]
+    fn test_key_ignores_json_formatting() {
+        let uri: Uri = "/v1/messages".parse().unwrap();
+        let a = RecordedRequest::new(
+            Provider::Claude,
+            &uri,
+            br#"{"model":"claude","max_tokens":10}"#,
+        );
+        let b = RecordedRequest::new(
+            Provider::Claude,
+            &uri,
+            br#"{ "max_tokens": 10, "model": "claude" }"#,
+        );
+        let c = RecordedRequest::new(
+            Provider::Claude,
+            &uri,
+            br#"{"model":"claude","max_tokens":11}"#,
+        );
+        assert_eq!(a.file_name(), b.file_name());
+        assert_ne!(a.file_name(), c.file_name());
+        assert!(a.file_name().starts_with("claude-"));
+    }
+
+    #[test]
+    fn test_event_end() {
+        assert_eq!(event_end(b"data: a\n\ndata: b"), Some(9));
+        assert_eq!(event_end(b"data: a\r\n\r\n"), Some(11));
+        assert_eq!(event_end(b"data: partial"), None);
+    }
+
+    #[test]
+    fn test_cassette_roundtrip() {
+        let cassette = Cassette {
+            request: RecordedRequest {
+                provider: Provider::OpenAI,
+                path: "/v1/responses".to_string(),
+                body: json!({"model": "gpt-4o", "input": "Hi", "stream": true}),
+            },
+            response: RecordedResponse {
+                status: 200,
+                headers: BTreeMap::from([(
+                    "content-type".to_string(),
+                    "text/event-stream".to_string(),
+                )]),
+                latency_ms: 120,
+                body: None,
+                events: vec![RecordedEvent {
+                    delay_ms: 5,
+                    data: "event: response.created\ndata: {}\n\n".to_string(),
+                }],
+            },
+        };
+
+        let json = serde_json::to_string(&cassette).unwrap();
+        assert!(!json.contains("\"body\":null"));
+        let parsed: Cassette = serde_json::from_str(&json).unwrap();
+        assert!(parsed.response.is_stream());
+        assert_eq!(parsed.response.events.len(), 1);
+        assert_eq!(parsed.request.provider, Provider::OpenAI);
+    }
+}
@@ -0,0 +1,555 @@
+//! Sliding-window rate limiting.
+//!
+//! Each API key, provider and model combination gets its own one-minute
+//! window holding the time and token cost of every admitted request. A
+//! request is rejected when admitting it would exceed the configured
+//! requests, total tokens, input tokens or output tokens per minute; the
+//! returned [`RateLimitStatus`] carries the remaining capacity and reset
+//! times used for provider headers.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+
+use crate::errors::Provider;
+
+/// Length of the sliding window.
+pub const WINDOW: Duration = Duration::from_mins(1);
+
+/// Limits applied to each window. Zero means unlimited.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+    pub requests_per_minute: u64,
+    pub tokens_per_minute: u64,
+    pub input_tokens_per_minute: u64,
+    pub output_tokens_per_minute: u64,
+}
+
+/// What a window is keyed by.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LimitKey {
+    pub api_key: String,
+    pub provider: Provider,
+    pub model: String,
+}
+
+/// Token cost of a request: estimated prompt tokens and the output
+/// tokens it may generate (its `max_tokens`, or a default reply's length).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Cost {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+}
+
+/// A limit a request can run into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitKind {
+    Requests,
+    Tokens,
+    InputTokens,
+    OutputTokens,
+}
+
+/// Capacity of one limit after a check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    pub limit: u64,
+    pub remaining: u64,
+    /// Time until the window is fully replenished.
+    pub reset: Duration,
+}
+
+/// Outcome of a rate limit check.
+#[derive(Debug, Clone)]
+pub struct RateLimitStatus {
+    pub model: String,
+    pub requests: Option<Quota>,
+    pub tokens: Option<Quota>,
+    pub input_tokens: Option<Quota>,
+    pub output_tokens: Option<Quota>,
+    /// Set when the request was rejected.
+    pub exceeded: Option<LimitKind>,
+    /// Time until the rejected request would fit.
+    pub retry_after: Duration,
+    /// Cost of the checked request.
+    pub requested: Cost,
+}
+
+#[derive(Debug, Default)]
+struct Window {
+    /// Admitted requests: arrival time and cost, oldest first.
+    entries: VecDeque<(Instant, Cost)>,
+}
+
+/// Per-key sliding-window limiter.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    windows: Mutex<HashMap<LimitKey, Window>>,
+}
+
+impl LimitKind {
+    const ALL: [Self; 4] = [
+        Self::Requests,
+        Self::Tokens,
+        Self::InputTokens,
+        Self::OutputTokens,
+    ];
+
+    fn limit(self, limits: &Limits) -> u64 {
+        match self {
+            Self::Requests => limits.requests_per_minute,
+            Self::Tokens => limits.tokens_per_minute,
+            Self::InputTokens => limits.input_tokens_per_minute,
+            Self::OutputTokens => limits.output_tokens_per_minute,
+        }
+    }
+
+    /// How much of this limit a request uses.
+    fn amount(self, cost: &Cost) -> u64 {
+        match self {
+            Self::Requests => 1,
+            Self::Tokens => cost.input_tokens + cost.output_tokens,
+            Self::InputTokens => cost.input_tokens,
+            Self::OutputTokens => cost.output_tokens,
+        }
+    }
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check a request and admit it if it fits within every limit.
+    pub fn check(
+        &self,
+        key: LimitKey,
+        cost: Cost,
+        limits: Limits,
+        now: Instant,
+    ) -> RateLimitStatus {
+        let model = key.model.clone();
+        let mut windows = self.windows.lock().unwrap();
+        // Drop expired requests everywhere, and the windows left empty, so
+        // keys that stop sending don't keep an entry.
+        windows.retain(|_, window| {
+            window.prune(now);
+            !window.entries.is_empty()
+        });
+        let window = windows.entry(key).or_default();
+
+        let exceeded = LimitKind::ALL.into_iter().find(|kind| {
+            let limit = kind.limit(&limits);
+            limit > 0 && window.used(*kind) + kind.amount(&cost) > limit
+        });
+        let retry_after = match exceeded {
+            Some(kind) => window.time_until_free(kind, kind.limit(&limits), &cost, now),
+            None => {
+                window.entries.push_back((now, cost));
+                Duration::ZERO
+            }
+        };
+
+        let reset = window.full_reset(now);
+        let quota = |kind: LimitKind| {
+            let limit = kind.limit(&limits);
+            (limit > 0).then(|| Quota {
+                limit,
+                remaining: limit.saturating_sub(window.used(kind)),
+                reset,
+            })
+        };
+        RateLimitStatus {
+            model,
+            requests: quota(LimitKind::Requests),
+            tokens: quota(LimitKind::Tokens),
+            input_tokens: quota(LimitKind::InputTokens),
+            output_tokens: quota(LimitKind::OutputTokens),
+            exceeded,
+            retry_after,
+            requested: cost,
+        }
+    }
+
+    /// Forget all windows.
+    pub fn reset(&self) {
+        self.windows.lock().unwrap().clear();
+    }
+}
+
+impl Window {
+    fn prune(&mut self, now: Instant) {
+        while let Some((at, _)) = self.entries.front() {
+            if now.saturating_duration_since(*at) >= WINDOW {
+                self.entries.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn used(&self, kind: LimitKind) -> u64 {
+        self.entries.iter().map(|(_, cost)| kind.amount(cost)).sum()
+    }
+
+    fn expiry(at: Instant, now: Instant) -> Duration {
+        (at + WINDOW).saturating_duration_since(now)
+    }
+
+    fn full_reset(&self, now: Instant) -> Duration {
+        self.entries
+            .back()
+            .map_or(Duration::ZERO, |(at, _)| Self::expiry(*at, now))
+    }
+
+    /// Time until enough of `kind` expires for a request costing `cost`.
+    fn time_until_free(&self, kind: LimitKind, limit: u64, cost: &Cost, now: Instant) -> Duration {
+        let needed = kind.amount(cost);
+        if needed > limit {
+            return WINDOW;
+        }
+        let mut used = self.used(kind);
+        for (at, entry) in &self.entries {
+            used -= kind.amount(entry);
+            if used + needed <= limit {
+                return Self::expiry(*at, now);
+            }
+        }
+        Duration::ZERO
+    }
+}
+
+impl RateLimitStatus {
+    /// Whole seconds to put in `retry-after`, at least one.
+    pub fn retry_after_secs(&self) -> u64 {
+        let secs = self.retry_after.as_secs();
+        let rounded = if self.retry_after.subsec_nanos() > 0 {
+            secs + 1
+        } else {
+            secs
+        };
+        rounded.max(1)
+    }
+
+    /// Quota of the given limit, if it is configured.
+    pub fn quota(&self, kind: LimitKind) -> Option<Quota> {
+        match kind {
+            LimitKind::Requests => self.requests,
+            LimitKind::Tokens => self.tokens,
+            LimitKind::InputTokens => self.input_tokens,
+            LimitKind::OutputTokens => self.output_tokens,
+        }
+    }
+
+    /// The quota that was exceeded, if any.
+    pub fn exceeded_quota(&self) -> Option<Quota> {
+        self.quota(self.exceeded?)
+    }
+
+    /// How much of the exceeded limit the rejected request asked for.
+    pub fn requested_amount(&self) -> u64 {
+        self.exceeded.map_or(0, |kind| kind.amount(&self.requested))
+    }
+
+    /// Provider-specific rate limit headers describing the current window.
+    ///
+    /// OpenAI and Cerebras only report request and total token limits;
+    /// Anthropic also reports input and output tokens. Gemini sends no
+    /// rate limit headers.
+    pub fn headers(&self, provider: Provider) -> Vec<(String, String)> {
+        let mut headers = Vec::new();
+        match provider {
+            Provider::OpenAI | Provider::Cerebras => {
+                for (name, quota) in [("requests", self.requests), ("tokens", self.tokens)] {
+                    let Some(quota) = quota else { continue };
+                    headers.push((format!("x-ratelimit-limit-{name}"), quota.limit.to_string()));
+                    headers.push((
+                        format!("x-ratelimit-remaining-{name}"),
+                        quota.remaining.to_string(),
+                    ));
+                    headers.push((
+                        format!("x-ratelimit-reset-{name}"),
+                        format_duration(quota.reset),
+                    ));
+                }
+            }
+            Provider::Claude => {
+                let limits = [
+                    ("requests", self.requests),
+                    ("tokens", self.tokens),
+                    ("input-tokens", self.input_tokens),
+                    ("output-tokens", self.output_tokens),
+                ];
+                for (name, quota) in limits {
+                    let Some(quota) = quota else { continue };
+                    headers.push((
+                        format!("anthropic-ratelimit-{name}-limit"),
+                        quota.limit.to_string(),
+                    ));
+                    headers.push((
+                        format!("anthropic-ratelimit-{name}-remaining"),
+                        quota.remaining.to_string(),
+                    ));
+                    headers.push((
+                        format!("anthropic-ratelimit-{name}-reset"),
+                        rfc3339(SystemTime::now() + quota.reset),
+                    ));
+                }
+            }
+            Provider::Gemini => {}
+        }
+        headers
+    }
+
+    /// Add the provider's rate limit headers to a response's headers.
+    pub fn apply_headers(&self, provider: Provider, headers: &mut HeaderMap) {
+        for (name, value) in self.headers(provider) {
+            if let (Ok(name), Ok(value)) =
+                (HeaderName::try_from(name), HeaderValue::try_from(value))
+            {
+                headers.insert(name, value);
+            }
+        }
+    }
+}
+
+/// Format a duration the way OpenAI does in reset headers: `20ms`, `1.5s`, `6m0s`.
+pub fn format_duration(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    if millis < 1000 {
+        return format!("{millis}ms");
+    }
+    let hours = millis / 3_600_000;
+    let minutes = (millis / 60_000) % 60;
+    let secs = (millis % 60_000) as f64 / 1000.0;
+    let secs = format!("{secs:.3}")
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string();
+    match (hours, minutes) {
+        (0, 0) => format!("{secs}s"),
+        (0, m) => format!("{m}m{secs}s"),
+        (h, m) => format!("{h}h{m}m{secs}s"),
+    }
+}
+
+/// Format a time as an RFC 3339 UTC timestamp with second precision.
+pub fn rfc3339(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let days = i64::try_from(secs / 86_400).unwrap_or(0);
+    let rem = secs % 86_400;
+
+    // Civil date from days since the epoch (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        rem / 3600,
+        (rem / 60) % 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(input_tokens: u64) -> Cost {
+        Cost {
+            input_tokens,
+            output_tokens: 0,
+        }
+    }
+
+    fn key(model: &str) -> LimitKey {
+        LimitKey {
+            api_key: "sk-test".to_string(),
+            provider: Provider::OpenAI,
+            model: model.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_empty_windows_are_removed() {
+        let limiter = RateLimiter::new();
+        let limits = Limits {
+            requests_per_minute: 10,
+            ..Limits::default()
+        };
+        let start = Instant::now();
+        for model in ["a", "b", "c"] {
+            limiter.check(key(model), tokens(1), limits, start);
+        }
+        assert_eq!(limiter.windows.lock().unwrap().len(), 3);
+
+        limiter.check(key("d"), tokens(1), limits, start + WINDOW);
+        let windows = limiter.windows.lock().unwrap();
+        assert_eq!(windows.len(), 1);
+        assert!(windows.contains_key(&key("d")));
+    }
+
+    #[test]
+    fn test_request_limit_slides() {
+        let limiter = RateLimiter::new();
+        let limits = Limits {
+            requests_per_minute: 2,
+            ..Limits::default()
+        };
+        let start = Instant::now();
+
+        assert!(limiter
+            .check(key("a"), tokens(1), limits, start)
+            .exceeded
+            .is_none());
+        let second = limiter.check(key("a"), tokens(1), limits, start + Duration::from_secs(10));
+        assert!(second.exceeded.is_none());
+        assert_eq!(second.requests.unwrap().remaining, 0);
+        assert_eq!(second.requests.unwrap().reset, WINDOW);
+
+        let rejected = limiter.check(key("a"), tokens(1), limits, start + Duration::from_secs(20));
+        assert_eq!(rejected.exceeded, Some(LimitKind::Requests));
+        assert_eq!(rejected.retry_after, Duration::from_secs(40));
+        assert_eq!(rejected.retry_after_secs(), 40);
+
+        // Other models have their own window.
+        assert!(limiter
+            .check(key("b"), tokens(1), limits, start)
+            .exceeded
+            .is_none());
+
+        // The first request leaves the window after a minute.
+        let later = limiter.check(key("a"), tokens(1), limits, start + WINDOW);
+        assert!(later.exceeded.is_none());
+    }
+
+    #[test]
+    fn test_token_limit() {
+        let limiter = RateLimiter::new();
+        let limits = Limits {
+            requests_per_minute: 100,
+            tokens_per_minute: 1000,
+            ..Limits::default()
+        };
+        let start = Instant::now();
+
+        limiter.check(key("a"), tokens(600), limits, start);
+        limiter.check(
+            key("a"),
+            tokens(300),
+            limits,
+            start + Duration::from_secs(30),
+        );
+        let rejected = limiter.check(
+            key("a"),
+            tokens(500),
+            limits,
+            start + Duration::from_secs(45),
+        );
+        assert_eq!(rejected.exceeded, Some(LimitKind::Tokens));
+        assert_eq!(rejected.tokens.unwrap().remaining, 100);
+        // Freeing the first 600 tokens is enough.
+        assert_eq!(rejected.retry_after, Duration::from_secs(15));
+
+        let too_big = limiter.check(
+            key("a"),
+            tokens(5000),
+            limits,
+            start + Duration::from_secs(45),
+        );
+        assert_eq!(too_big.retry_after, WINDOW);
+    }
+
+    #[test]
+    fn test_output_token_limit() {
+        let limiter = RateLimiter::new();
+        let limits = Limits {
+            input_tokens_per_minute: 1000,
+            output_tokens_per_minute: 100,
+            ..Limits::default()
+        };
+        let cost = Cost {
+            input_tokens: 10,
+            output_tokens: 80,
+        };
+        let start = Instant::now();
+
+        assert!(limiter
+            .check(key("a"), cost, limits, start)
+            .exceeded
+            .is_none());
+        let rejected = limiter.check(key("a"), cost, limits, start + Duration::from_secs(1));
+        assert_eq!(rejected.exceeded, Some(LimitKind::OutputTokens));
+        assert_eq!(rejected.requested_amount(), 80);
+        assert_eq!(rejected.output_tokens.unwrap().remaining, 20);
+        assert_eq!(rejected.input_tokens.unwrap().remaining, 990);
+        assert!(rejected.requests.is_none());
+    }
+
+    #[test]
+    fn test_headers() {
+        let limiter = RateLimiter::new();
+        let limits = Limits {
+            requests_per_minute: 60,
+            tokens_per_minute: 1000,
+            input_tokens_per_minute: 500,
+            output_tokens_per_minute: 200,
+        };
+        let cost = Cost {
+            input_tokens: 10,
+            output_tokens: 50,
+        };
+        let status = limiter.check(key("a"), cost, limits, Instant::now());
+
+        let openai = status.headers(Provider::OpenAI);
+        assert!(openai.contains(&("x-ratelimit-remaining-requests".into(), "59".into())));
+        assert!(openai.contains(&("x-ratelimit-remaining-tokens".into(), "940".into())));
+        assert!(!openai.iter().any(|(name, _)| name.contains("input")));
+        assert!(openai.contains(&("x-ratelimit-reset-tokens".into(), "1m0s".into())));
+
+        let claude = status.headers(Provider::Claude);
+        let reset = &claude
+            .iter()
+            .find(|(name, _)| name == "anthropic-ratelimit-requests-reset")
+            .unwrap()
+            .1;
+        assert!(reset.ends_with('Z') && reset.len() == 20);
+        assert!(claude.contains(&(
+            "anthropic-ratelimit-input-tokens-remaining".into(),
+            "490".into()
+        )));
+        assert!(claude.contains(&(
+            "anthropic-ratelimit-output-tokens-remaining".into(),
+            "150".into()
+        )));
+
+        assert!(status.headers(Provider::Gemini).is_empty());
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_millis(20)), "20ms");
+        assert_eq!(format_duration(Duration::from_secs(1)), "1s");
+        assert_eq!(format_duration(Duration::from_millis(1500)), "1.5s");
+        assert_eq!(format_duration(Duration::from_mins(6)), "6m0s");
+        assert_eq!(format_duration(Duration::from_secs(3723)), "1h2m3s");
+    }
+
+    #[test]
+    fn test_rfc3339() {
+        assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        let time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(rfc3339(time), "2023-11-14T22:13:20Z");
+        let leap = UNIX_EPOCH + Duration::from_hours(264_396);
+        assert_eq!(rfc3339(leap), "2000-02-29T12:00:00Z");
+    }
+}
@@ -0,0 +1,434 @@
+//! Tool call arguments generated from a tool's JSON Schema.
+//!
+//! Arguments validate against the declared parameters: required properties
+//! are present, types, enums and bounds are respected, nested objects and
+//! arrays are populated and common string formats are honoured. `$ref`s into
+//! `$defs` / `definitions` are resolved against the root schema, and Gemini's
+//! upper-case type names are accepted.
+
+use serde_json::{json, Map, Value};
+
+use crate::generator::ContentGenerator;
+
+/// Depth after which only required properties and the minimum number of
+/// array items are generated, so recursive schemas terminate.
+const MAX_DEPTH: usize = 6;
+
+/// Most items generated for an array, whatever `minItems` / `maxItems` ask.
+const MAX_ITEMS: usize = 16;
+
+/// Longest free-form string generated, whatever `minLength` asks.
+const MAX_LENGTH: usize = 1024;
+
+/// Generate arguments for a tool whose parameters are `schema`.
+/// `hint` (e.g. a word from the prompt) fills the first free-form string.
+/// Schemas that don't describe an object (e.g. `{}`) get `{"location": hint}`.
+pub fn arguments(schema: &Value, hint: &str, gen: &mut ContentGenerator) -> Value {
+    let args = Arguments {
+        root: schema,
+        hint: Some(hint),
+        gen,
+    }
+    .value(schema, 0);
+    if args.is_object() {
+        args
+    } else {
+        json!({ "location": hint })
+    }
+}
+
+struct Arguments<'a> {
+    root: &'a Value,
+    hint: Option<&'a str>,
+    gen: &'a mut ContentGenerator,
+}
+
+impl Arguments<'_> {
+    fn value(&mut self, schema: &Value, depth: usize) -> Value {
+        let schema = resolve(self.root, schema);
+        if let Some(value) = schema.get("const") {
+            return value.clone();
+        }
+        if let Some(values) = schema["enum"].as_array().filter(|v| !v.is_empty()) {
+            return values[self.gen.rng().usize(..values.len())].clone();
+        }
+        for key in ["anyOf", "oneOf"] {
+            if let Some(options) = schema[key].as_array() {
+                if let Some(option) = options
+                    .iter()
+                    .find(|o| schema_type(resolve(self.root, o)) != "null")
+                    .or(options.first())
+                {
+                    return self.value(option, depth);
+                }
+            }
+        }
+        if let Some(parts) = schema["allOf"].as_array() {
+            let merged = merge(self.root, schema, parts);
+            return self.object(&merged, depth);
+        }
+
+        match schema_type(schema).as_str() {
+            "object" => self.object(schema, depth),
+            "array" => self.array(schema, depth),
+            "integer" => json!(self.integer(schema)),
+            "number" => json!(self.number(schema)),
+            "boolean" => json!(self.gen.rng().bool()),
+            "null" => Value::Null,
+            _ => json!(self.string(schema)),
+        }
+    }
+
+    fn object(&mut self, schema: &Value, depth: usize) -> Value {
+        let required: Vec<&str> = schema["required"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .collect();
+        let mut object = Map::new();
+        if let Some(properties) = schema["properties"].as_object() {
+            for (name, property) in properties {
+                if depth >= MAX_DEPTH && !required.contains(&name.as_str()) {
+                    continue;
+                }
+                // Recursive required properties can't terminate; give up on them.
+                let value = if depth >= 2 * MAX_DEPTH {
+                    Value::Null
+                } else {
+                    self.value(property, depth + 1)
+                };
+                object.insert(name.clone(), value);
+            }
+        }
+        for name in required {
+            if !object.contains_key(name) {
+                object.insert(name.to_string(), json!(self.gen.word()));
+            }
+        }
+        Value::Object(object)
+    }
+
+    fn array(&mut self, schema: &Value, depth: usize) -> Value {
+        let prefix = schema["prefixItems"]
+            .as_array()
+            .map_or(&[][..], Vec::as_slice);
+        let min = usize_of(&schema["minItems"])
+            .unwrap_or(0)
+            .max(prefix.len())
+            .min(MAX_ITEMS);
+        let max = usize_of(&schema["maxItems"])
+            .unwrap_or(min.max(3))
+            .clamp(min, MAX_ITEMS);
+        let count = if depth >= MAX_DEPTH {
+            min
+        } else {
+            self.gen.rng().usize(min.max(1).min(max)..=max)
+        };
+        let unique = schema["uniqueItems"].as_bool().unwrap_or(false);
+
+        let mut items: Vec<Value> = Vec::with_capacity(count);
+        for _ in 0..count * 4 {
+            if items.len() == count {
+                break;
+            }
+            let item = match prefix.get(items.len()) {
+                Some(item_schema) => self.value(item_schema, depth + 1),
+                None if depth >= 2 * MAX_DEPTH => Value::Null,
+                None => self.value(&schema["items"], depth + 1),
+            };
+            if !unique || !items.contains(&item) {
+                items.push(item);
+            }
+        }
+        Value::Array(items)
+    }
+
+    fn string(&mut self, schema: &Value) -> String {
+        let rng = self.gen.rng();
+        let (year, month, day) = (rng.u32(2020..=2026), rng.u32(1..=12), rng.u32(1..=28));
+        let (hour, minute, second) = (rng.u32(0..24), rng.u32(0..60), rng.u32(0..60));
+        match schema["format"].as_str().unwrap_or_default() {
+            "date" => return format!("{year:04}-{month:02}-{day:02}"),
+            "date-time" => {
+                return format!("{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}Z")
+            }
+            "time" => return format!("{hour:02}:{minute:02}:{second:02}"),
+            "email" => return format!("{}@example.com", self.gen.word().to_lowercase()),
+            "uri" | "url" => return format!("https://example.com/{}", self.gen.word()),
+            "hostname" => return format!("{}.example.com", self.gen.word().to_lowercase()),
+            "uuid" => {
+                let bytes = self.gen.rng().u128(..).to_le_bytes();
+                return uuid::Builder::from_random_bytes(bytes)
+                    .into_uuid()
+                    .to_string();
+            }
+            "ipv4" => return format!("192.0.2.{}", self.gen.rng().u8(1..=254)),
+            "ipv6" => return format!("2001:db8::{:x}", self.gen.rng().u16(1..)),
+            _ => {}
+        }
+
+        let mut text = match self.hint.take() {
+            Some(hint) => hint.to_string(),
+            None => {
+                let count = self.gen.rng().usize(1..=3);
+                self.gen.words(count)
+            }
+        };
+        let min = usize_of(&schema["minLength"]).unwrap_or(0).min(MAX_LENGTH);
+        while text.chars().count() < min {
+            text.push(' ');
+            text.push_str(self.gen.word());
+        }
+        if let Some(max) = usize_of(&schema["maxLength"]) {
+            text = text.chars().take(max).collect();
+        }
+        text
+    }
+
+    fn integer(&mut self, schema: &Value) -> i64 {
+        let (low, high) = bounds(schema, 1.0);
+        let (mut low, mut high) = (low.ceil() as i64, high.floor() as i64);
+        if let Some(step) = schema["multipleOf"].as_i64().filter(|s| *s > 0) {
+            let first = low.div_euclid(step) + i64::from(low.rem_euclid(step) != 0);
+            let last = high.div_euclid(step);
+            if first <= last {
+                return self.gen.rng().i64(first..=last) * step;
+            }
+        }
+        if low > high {
+            std::mem::swap(&mut low, &mut high);
+        }
+        self.gen.rng().i64(low..=high)
+    }
+
+    fn number(&mut self, schema: &Value) -> f64 {
+        let (low, high) = bounds(schema, 0.01);
+        if let Some(step) = schema["multipleOf"].as_f64().filter(|s| *s > 0.0) {
+            let (first, last) = ((low / step).ceil() as i64, (high / step).floor() as i64);
+            if first <= last {
+                return self.gen.rng().i64(first..=last) as f64 * step;
+            }
+        }
+        let value = ((low + self.gen.rng().f64() * (high - low)) * 100.0).round() / 100.0;
+        if (low..=high).contains(&value) {
+            value
+        } else {
+            f64::midpoint(low, high)
+        }
+    }
+}
+
+/// Follow `$ref`s to definitions in the root schema.
+fn resolve<'s>(root: &'s Value, mut schema: &'s Value) -> &'s Value {
+    for _ in 0..16 {
+        let Some(pointer) = schema["$ref"].as_str().and_then(|r| r.strip_prefix('#')) else {
+            break;
+        };
+        match root.pointer(pointer) {
+            Some(target) => schema = target,
+            None => break,
+        }
+    }
+    schema
+}
+
+/// Combine the object parts of an `allOf` into one object schema.
+fn merge(root: &Value, schema: &Value, parts: &[Value]) -> Value {
+    let mut properties = Map::new();
+    let mut required = Vec::new();
+    for part in std::iter::once(schema).chain(parts.iter().map(|p| resolve(root, p))) {
+        if let Some(props) = part["properties"].as_object() {
+            properties.extend(props.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+        if let Some(names) = part["required"].as_array() {
+            required.extend(names.iter().cloned());
+        }
+    }
+    json!({ "type": "object", "properties": properties, "required": required })
+}
+
+/// Inclusive numeric bounds of a schema, with `step` inside exclusive bounds.
+fn bounds(schema: &Value, step: f64) -> (f64, f64) {
+    let exclusive = |key: &str, inclusive: &str, sign: f64| match &schema[key] {
+        Value::Number(n) => n.as_f64().map(|n| n + sign * step),
+        Value::Bool(true) => schema[inclusive].as_f64().map(|n| n + sign * step),
+        _ => None,
+    };
+    let low = exclusive("exclusiveMinimum", "minimum", 1.0).or(schema["minimum"].as_f64());
+    let high = exclusive("exclusiveMaximum", "maximum", -1.0).or(schema["maximum"].as_f64());
+    match (low, high) {
+        (Some(low), Some(high)) => (low, high),
+        (Some(low), None) => (low, low + 100.0),
+        (None, Some(high)) => (high - 100.0, high),
+        (None, None) => (0.0, 100.0),
+    }
+}
+
+/// The schema's type, inferred from its keywords when not declared.
+fn schema_type(schema: &Value) -> String {
+    let declared = match &schema["type"] {
+        Value::String(t) => Some(t.as_str()),
+        Value::Array(types) => types
+            .iter()
+            .filter_map(Value::as_str)
+            .find(|t| !t.eq_ignore_ascii_case("null"))
+            .or_else(|| types.first().and_then(Value::as_str)),
+        _ => None,
+    };
+    match declared {
+        Some(t) => t.to_ascii_lowercase(),
+        None if schema.get("properties").is_some() => "object".to_string(),
+        None if schema.get("items").is_some() => "array".to_string(),
+        None => "string".to_string(),
+    }
+}
+
+fn usize_of(value: &Value) -> Option<usize> {
+    value.as_u64().and_then(|n| usize::try_from(n).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generate(schema: &Value, seed: u64) -> Value {
+        arguments(schema, "Tokyo", &mut ContentGenerator::with_seed(seed))
+    }
+
+    #[test]
+    fn test_types_and_enums() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "location": {"type": "string"},
+                "unit": {"type": "string", "enum": ["celsius", "fahrenheit"]},
+                "days": {"type": "integer", "minimum": 1, "maximum": 7},
+                "threshold": {"type": "number", "exclusiveMinimum": 0, "maximum": 1},
+                "step": {"type": "integer", "multipleOf": 5, "minimum": 1, "maximum": 12},
+                "detailed": {"type": "boolean"},
+                "kind": {"const": "forecast"}
+            },
+            "required": ["location", "unit"]
+        });
+        for seed in 0..50 {
+            let args = generate(&schema, seed);
+            assert_eq!(args["location"], "Tokyo");
+            assert!(["celsius", "fahrenheit"].contains(&args["unit"].as_str().unwrap()));
+            assert!((1..=7).contains(&args["days"].as_i64().unwrap()));
+            let threshold = args["threshold"].as_f64().unwrap();
+            assert!(threshold > 0.0 && threshold <= 1.0);
+            assert!([5, 10].contains(&args["step"].as_i64().unwrap()));
+            assert!(args["detailed"].is_boolean());
+            assert_eq!(args["kind"], "forecast");
+        }
+    }
+
+    #[test]
+    fn test_nested_objects_and_arrays() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "attendees": {
+                    "type": "array",
+                    "minItems": 2,
+                    "maxItems": 4,
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "email": {"type": "string", "format": "email"},
+                            "name": {"type": "string", "minLength": 10, "maxLength": 20}
+                        },
+                        "required": ["email"]
+                    }
+                },
+                "tags": {"type": "array", "items": {"type": "string", "enum": ["a", "b"]}, "uniqueItems": true},
+                "when": {"type": "string", "format": "date"},
+                "at": {"type": "string", "format": "date-time"},
+                "id": {"type": "string", "format": "uuid"}
+            },
+            "required": ["attendees", "when"]
+        });
+        let args = generate(&schema, 7);
+        let attendees = args["attendees"].as_array().unwrap();
+        assert!((2..=4).contains(&attendees.len()));
+        for attendee in attendees {
+            assert!(attendee["email"]
+                .as_str()
+                .unwrap()
+                .ends_with("@example.com"));
+            let name = attendee["name"].as_str().unwrap();
+            assert!((10..=20).contains(&name.len()));
+        }
+        let tags = args["tags"].as_array().unwrap();
+        assert!(!tags.is_empty() && tags.len() <= 2);
+        assert!(tags.len() < 2 || tags[0] != tags[1]);
+
+        let date = args["when"].as_str().unwrap();
+        assert_eq!(date.len(), 10);
+        assert_eq!(&date[4..5], "-");
+        assert!(args["at"].as_str().unwrap().ends_with('Z'));
+        assert!(uuid::Uuid::parse_str(args["id"].as_str().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_huge_bounds_are_capped() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "ids": {"type": "array", "minItems": 1_000_000_000_000_u64, "items": {"type": "integer"}},
+                "tags": {"type": "array", "maxItems": 1_000_000_000_000_u64, "items": {"type": "string"}},
+                "note": {"type": "string", "minLength": 1_000_000_000_000_u64}
+            },
+            "required": ["ids", "tags", "note"]
+        });
+        let args = generate(&schema, 1);
+        assert_eq!(args["ids"].as_array().unwrap().len(), MAX_ITEMS);
+        assert!(args["tags"].as_array().unwrap().len() <= MAX_ITEMS);
+        let note = args["note"].as_str().unwrap().chars().count();
+        assert!((MAX_LENGTH..MAX_LENGTH + 32).contains(&note));
+    }
+
+    #[test]
+    fn test_refs_unions_and_gemini_types() {
+        let schema = json!({
+            "type": "object",
+            "$defs": {
+                "Node": {
+                    "type": "object",
+                    "properties": {
+                        "value": {"type": "integer"},
+                        "children": {"type": "array", "items": {"$ref": "#/$defs/Node"}}
+                    },
+                    "required": ["value"]
+                }
+            },
+            "properties": {
+                "tree": {"$ref": "#/$defs/Node"},
+                "note": {"anyOf": [{"type": "null"}, {"type": "string"}]},
+                "limit": {"type": ["integer", "null"]},
+                "merged": {"allOf": [
+                    {"properties": {"a": {"type": "string"}}, "required": ["a"]},
+                    {"properties": {"b": {"type": "boolean"}}}
+                ]}
+            }
+        });
+        let args = generate(&schema, 3);
+        assert!(args["tree"]["value"].is_i64());
+        assert!(args["tree"]["children"].is_array());
+        assert!(args["note"].is_string());
+        assert!(args["limit"].is_i64());
+        assert!(args["merged"]["a"].is_string());
+        assert!(args["merged"]["b"].is_boolean());
+
+        let gemini = json!({
+            "type": "OBJECT",
+            "properties": {"count": {"type": "INTEGER"}, "city": {"type": "STRING"}}
+        });
+        let args = generate(&gemini, 1);
+        assert!(args["count"].is_i64());
+        assert_eq!(args["city"], "Tokyo");
+
+        assert_eq!(generate(&json!({}), 1), json!({"location": "Tokyo"}));
+    }
+}
@@ -0,0 +1,399 @@
+//! Mid-stream fault injection for SSE responses.
+//!
+//! Errors from the middleware are sent before a handler runs, so on their own
+//! they never exercise a client's streaming parser. A stream fault lets the
+//! first events of a response through and then fails the way real streams
+//! do: a provider error event, a dropped connection, a truncated `data:`
+//! line, or a stream that stops sending without closing.
+
+use std::io;
+use std::str::FromStr;
+
+use axum::{body::Bytes, response::Response};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::errors::Provider;
+use crate::proxy::event_end;
+
+/// Request header that injects a fault into a single streamed response:
+/// `kind` or `kind:after_events`, e.g. `error:5`.
+pub const STREAM_FAULT_HEADER: &str = "x-tokenipsum-stream-fault";
+
+/// How a stream fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamFaultKind {
+    #[default]
+    None,
+    /// The provider's in-stream error event (Anthropic `overloaded_error`,
+    /// OpenAI `error` and `response.failed`, an error chunk for the others).
+    Error,
+    /// Close the connection without finishing the response.
+    Disconnect,
+    /// Send half of the next event, cutting its JSON short, then end the stream.
+    Truncate,
+    /// Stop sending and keep the connection open forever.
+    Stall,
+}
+
+/// Stream fault settings in the main config.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StreamFaultConfig {
+    pub fault: StreamFaultKind,
+    /// Events sent before the fault. Shorter streams complete normally.
+    pub after_events: usize,
+    /// Probability (0.0 - 1.0) that a stream gets the fault.
+    pub rate: f32,
+}
+
+impl Default for StreamFaultConfig {
+    fn default() -> Self {
+        Self {
+            fault: StreamFaultKind::None,
+            after_events: 3,
+            rate: 1.0,
+        }
+    }
+}
+
+/// A fault to inject into one response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamFault {
+    pub kind: StreamFaultKind,
+    pub after_events: usize,
+}
+
+impl FromStr for StreamFaultKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "none" => Ok(Self::None),
+            "error" => Ok(Self::Error),
+            "disconnect" => Ok(Self::Disconnect),
+            "truncate" => Ok(Self::Truncate),
+            "stall" => Ok(Self::Stall),
+            other => Err(format!("unknown stream fault: {other}")),
+        }
+    }
+}
+
+impl StreamFault {
+    /// Parse a [`STREAM_FAULT_HEADER`] value. `default_after` is used when the
+    /// value doesn't give an event count.
+    pub fn parse(value: &str, default_after: usize) -> Result<Self, String> {
+        let (kind, after) = match value.split_once(':') {
+            Some((kind, after)) => (
+                kind,
+                after
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("invalid event count: {after}"))?,
+            ),
+            None => (value, default_after),
+        };
+        Ok(Self {
+            kind: kind.parse()?,
+            after_events: after,
+        })
+    }
+}
+
+/// Wrap a streamed response so it fails with `fault` after `fault.after_events` events.
+pub fn inject(response: Response, provider: Provider, fault: StreamFault) -> Response {
+    if fault.kind == StreamFaultKind::None {
+        return response;
+    }
+    let (parts, body) = response.into_parts();
+    let state = FaultStream {
+        body: body.into_data_stream(),
+        buffer: Vec::new(),
+        sent: 0,
+        fault,
+        provider,
+        openai_response: None,
+        sequence: 0,
+    };
+    let body = futures::stream::unfold(Some(state), |state| async move {
+        let mut state = state?;
+        loop {
+            if let Some(end) = event_end(&state.buffer) {
+                let event: Vec<u8> = state.buffer.drain(..end).collect();
+                if state.sent == state.fault.after_events {
+                    return state.fail(&event).await.map(|chunk| (chunk, None));
+                }
+                state.sent += 1;
+                state.observe(&event);
+                return Some((Ok(Bytes::from(event)), Some(state)));
+            }
+            match state.body.next().await {
+                Some(Ok(chunk)) => state.buffer.extend_from_slice(&chunk),
+                Some(Err(e)) => return Some((Err(e), None)),
+                None if state.buffer.is_empty() => return None,
+                None => {
+                    let rest = std::mem::take(&mut state.buffer);
+                    return Some((Ok(Bytes::from(rest)), None));
+                }
+            }
+        }
+    });
+    Response::from_parts(parts, axum::body::Body::from_stream(body))
+}
+
+/// State of a response body being passed through until its fault.
+struct FaultStream {
+    body: axum::body::BodyDataStream,
+    buffer: Vec<u8>,
+    sent: usize,
+    fault: StreamFault,
+    provider: Provider,
+    /// OpenAI response object from `response.created`, echoed in `response.failed`.
+    openai_response: Option<Value>,
+    /// Last OpenAI `sequence_number` seen.
+    sequence: u64,
+}
+
+impl FaultStream {
+    fn observe(&mut self, event: &[u8]) {
+        if self.provider != Provider::OpenAI {
+            return;
+        }
+        let Some(data) = event_data(event) else {
+            return;
+        };
+        if let Some(seq) = data["sequence_number"].as_u64() {
+            self.sequence = seq;
+        }
+        if data["type"] == "response.created" {
+            self.openai_response = Some(data["response"].clone());
+        }
+    }
+
+    /// The final chunk sent instead of `event`, or `None` for no more output.
+    async fn fail(self, event: &[u8]) -> Option<Result<Bytes, axum::Error>> {
+        match self.fault.kind {
+            StreamFaultKind::None => Some(Ok(Bytes::copy_from_slice(event))),
+            StreamFaultKind::Error => Some(Ok(Bytes::from(self.error_events()))),
+            StreamFaultKind::Disconnect => Some(Err(axum::Error::new(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "stream fault: connection closed mid-stream",
+            )))),
+            StreamFaultKind::Truncate => Some(Ok(Bytes::from(truncate(event)))),
+            StreamFaultKind::Stall => std::future::pending().await,
+        }
+    }
+
+    fn error_events(&self) -> String {
+        let message = "The server had an error while processing your request. Sorry about that!";
+        match self.provider {
+            Provider::Claude => format!(
+                "event: error\ndata: {}\n\n",
+                json!({
+                    "type": "error",
+                    "error": {"type": "overloaded_error", "message": "Overloaded"}
+                })
+            ),
+            Provider::OpenAI => {
+                let mut response = self
+                    .openai_response
+                    .clone()
+                    .unwrap_or_else(|| json!({"object": "response"}));
+                response["status"] = json!("failed");
+                response["error"] = json!({"code": "server_error", "message": message});
+                format!(
+                    "event: error\ndata: {}\n\nevent: response.failed\ndata: {}\n\n",
+                    json!({
+                        "type": "error",
+                        "code": "server_error",
+                        "message": message,
+                        "param": null,
+                        "sequence_number": self.sequence + 1
+                    }),
+                    json!({
+                        "type": "response.failed",
+                        "sequence_number": self.sequence + 2,
+                        "response": response
+                    })
+                )
+            }
+            Provider::Cerebras => format!(
+                "data: {}\n\n",
+                json!({
+                    "error": {
+                        "message": message,
+                        "type": "server_error",
+                        "param": null,
+                        "code": null
+                    }
+                })
+            ),
+            Provider::Gemini => format!(
+                "data: {}\n\n",
+                json!({
+                    "error": {
+                        "code": 503,
+                        "message": "The model is overloaded. Please try again later.",
+                        "status": "UNAVAILABLE"
+                    }
+                })
+            ),
+        }
+    }
+}
+
+/// Parsed JSON of an event's `data:` line.
+fn event_data(event: &[u8]) -> Option<Value> {
+    String::from_utf8_lossy(event)
+        .lines()
+        .find_map(|line| line.strip_prefix("data:"))
+        .and_then(|data| serde_json::from_str(data.trim()).ok())
+}
+
+/// Cut an event halfway through its `data:` payload, keeping the event
+/// terminator so the parser sees a complete event with broken JSON.
+fn truncate(event: &[u8]) -> Vec<u8> {
+    let text = String::from_utf8_lossy(event);
+    let start = text.find("data:").map_or(0, |i| i + "data:".len());
+    let body = text[start..].trim_end();
+    let mut cut = start + body.len() / 2;
+    while !text.is_char_boundary(cut) {
+        cut -= 1;
+    }
+    format!("{}\n\n", &text[..cut]).into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use http_body_util::BodyExt;
+    use std::time::Duration;
+
+    fn stream(events: &[&str]) -> Response {
+        // Deliver the events split across odd chunk boundaries.
+        let joined = events.concat().into_bytes();
+        let chunks: Vec<Result<Bytes, io::Error>> = joined
+            .chunks(7)
+            .map(|c| Ok(Bytes::copy_from_slice(c)))
+            .collect();
+        Response::new(Body::from_stream(futures::stream::iter(chunks)))
+    }
+
+    fn fault(kind: StreamFaultKind, after_events: usize) -> StreamFault {
+        StreamFault { kind, after_events }
+    }
+
+    const CLAUDE: [&str; 3] = [
+        "event: message_start\ndata: {\"type\":\"message_start\"}\n\n",
+        "event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"delta\":{\"text\":\"Hi\"}}\n\n",
+        "event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n",
+    ];
+
+    async fn body_text(response: Response) -> String {
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_header() {
+        assert_eq!(
+            StreamFault::parse("error:5", 3),
+            Ok(fault(StreamFaultKind::Error, 5))
+        );
+        assert_eq!(
+            StreamFault::parse("stall", 3),
+            Ok(fault(StreamFaultKind::Stall, 3))
+        );
+        assert!(StreamFault::parse("explode", 3).is_err());
+        assert!(StreamFault::parse("error:x", 3).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_error_event() {
+        let response = inject(
+            stream(&CLAUDE),
+            Provider::Claude,
+            fault(StreamFaultKind::Error, 2),
+        );
+        let text = body_text(response).await;
+        assert!(text.starts_with(CLAUDE[0]));
+        assert!(text.contains("Hi"));
+        assert!(text.ends_with(
+            "event: error\ndata: {\"error\":{\"message\":\"Overloaded\",\"type\":\"overloaded_error\"},\"type\":\"error\"}\n\n"
+        ));
+        assert!(!text.contains("message_stop"));
+    }
+
+    #[tokio::test]
+    async fn test_openai_response_failed() {
+        let events = [
+            "event: response.created\ndata: {\"type\":\"response.created\",\"sequence_number\":0,\"response\":{\"id\":\"resp_1\",\"status\":\"in_progress\"}}\n\n",
+            "event: response.output_text.delta\ndata: {\"type\":\"response.output_text.delta\",\"sequence_number\":1,\"delta\":\"Hi\"}\n\n",
+        ];
+        let response = inject(
+            stream(&events),
+            Provider::OpenAI,
+            fault(StreamFaultKind::Error, 1),
+        );
+        let text = body_text(response).await;
+        let failed = text.split("\n\n").find_map(|e| {
+            e.strip_prefix("event: response.failed\ndata: ")
+                .map(|d| serde_json::from_str::<Value>(d).unwrap())
+        });
+        let failed = failed.unwrap();
+        assert_eq!(failed["response"]["id"], "resp_1");
+        assert_eq!(failed["response"]["status"], "failed");
+        assert_eq!(failed["sequence_number"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_disconnect() {
+        let response = inject(
+            stream(&CLAUDE),
+            Provider::Claude,
+            fault(StreamFaultKind::Disconnect, 1),
+        );
+        assert!(response.into_body().collect().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_truncate() {
+        let response = inject(
+            stream(&CLAUDE),
+            Provider::Claude,
+            fault(StreamFaultKind::Truncate, 1),
+        );
+        let text = body_text(response).await;
+        let last = text.trim_end().rsplit("\n\n").next().unwrap();
+        let data = last.split_once("data: ").unwrap().1;
+        assert!(serde_json::from_str::<Value>(data).is_err());
+        assert!(!text.contains("message_stop"));
+    }
+
+    #[tokio::test]
+    async fn test_stall() {
+        let response = inject(
+            stream(&CLAUDE),
+            Provider::Claude,
+            fault(StreamFaultKind::Stall, 1),
+        );
+        let mut body = response.into_body();
+        let first = body.frame().await.unwrap().unwrap();
+        assert_eq!(first.into_data().unwrap(), CLAUDE[0].as_bytes());
+        let next = tokio::time::timeout(Duration::from_millis(100), body.frame()).await;
+        assert!(next.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_short_stream_completes() {
+        let response = inject(
+            stream(&CLAUDE),
+            Provider::Claude,
+            fault(StreamFaultKind::Error, 10),
+        );
+        assert_eq!(body_text(response).await, CLAUDE.concat());
+    }
+}
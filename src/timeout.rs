@@ -0,0 +1,220 @@
+//! Timeouts that make the client wait instead of returning an error.
+//!
+//! `force_error = "timeout"` answers immediately with a 504, which never trips
+//! a client's own timeouts. These modes hold the connection instead: no
+//! response at all, headers followed by nothing, or the real response sent
+//! one byte at a time.
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+use axum::{body::Bytes, response::Response};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::time::sleep;
+
+use crate::errors::Provider;
+
+/// Request header that applies a timeout mode to a single request:
+/// `mode` or `drip:interval_ms`.
+pub const TIMEOUT_HEADER: &str = "x-tokenipsum-timeout";
+
+/// How a request times out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeoutMode {
+    #[default]
+    None,
+    /// Never send a response, not even headers.
+    Hang,
+    /// Send the response headers, then never send the body.
+    Stall,
+    /// Send the response one byte per `drip_interval_ms`.
+    Drip,
+}
+
+/// When and how requests time out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimeoutRule {
+    pub mode: TimeoutMode,
+    /// Probability (0.0 - 1.0) that a request times out.
+    pub rate: f32,
+    /// Delay between bytes in `drip` mode.
+    pub drip_interval_ms: u64,
+}
+
+impl Default for TimeoutRule {
+    fn default() -> Self {
+        Self {
+            mode: TimeoutMode::None,
+            rate: 1.0,
+            drip_interval_ms: 1000,
+        }
+    }
+}
+
+/// Timeout settings in the main config.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimeoutConfig {
+    /// Rule for all providers.
+    #[serde(flatten)]
+    pub default: TimeoutRule,
+    /// Rules replacing the default for individual providers.
+    pub providers: BTreeMap<Provider, TimeoutRule>,
+}
+
+impl TimeoutConfig {
+    /// The rule that applies to `provider`.
+    pub fn rule(&self, provider: Provider) -> &TimeoutRule {
+        self.providers.get(&provider).unwrap_or(&self.default)
+    }
+}
+
+/// A timeout to apply to one request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeout {
+    pub mode: TimeoutMode,
+    pub drip_interval: Duration,
+}
+
+impl FromStr for TimeoutMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "none" => Ok(Self::None),
+            "hang" => Ok(Self::Hang),
+            "stall" => Ok(Self::Stall),
+            "drip" => Ok(Self::Drip),
+            other => Err(format!("unknown timeout mode: {other}")),
+        }
+    }
+}
+
+impl Timeout {
+    /// Parse a [`TIMEOUT_HEADER`] value. `default_interval` is used when a
+    /// `drip` value doesn't give one.
+    pub fn parse(value: &str, default_interval: Duration) -> Result<Self, String> {
+        let (mode, interval) = match value.split_once(':') {
+            Some((mode, ms)) => (
+                mode,
+                Duration::from_millis(
+                    ms.trim()
+                        .parse()
+                        .map_err(|_| format!("invalid drip interval: {ms}"))?,
+                ),
+            ),
+            None => (value, default_interval),
+        };
+        Ok(Self {
+            mode: mode.parse()?,
+            drip_interval: interval,
+        })
+    }
+
+    /// Wait forever in `hang` mode; otherwise return immediately.
+    pub async fn before_response(&self) {
+        if self.mode == TimeoutMode::Hang {
+            std::future::pending::<()>().await;
+        }
+    }
+
+    /// Apply `stall` or `drip` to a response.
+    pub fn apply(&self, response: Response) -> Response {
+        let (parts, body) = response.into_parts();
+        let body = match self.mode {
+            TimeoutMode::None | TimeoutMode::Hang => return Response::from_parts(parts, body),
+            TimeoutMode::Stall => axum::body::Body::from_stream(futures::stream::pending::<
+                Result<Bytes, axum::Error>,
+            >()),
+            TimeoutMode::Drip => {
+                let interval = self.drip_interval;
+                let bytes = body.into_data_stream().flat_map(|chunk| {
+                    let bytes: Vec<Result<Bytes, axum::Error>> = match chunk {
+                        Ok(chunk) => (0..chunk.len()).map(|i| Ok(chunk.slice(i..=i))).collect(),
+                        Err(e) => vec![Err(e)],
+                    };
+                    futures::stream::iter(bytes)
+                });
+                let mut first = true;
+                axum::body::Body::from_stream(bytes.then(move |byte| {
+                    let wait = !std::mem::take(&mut first);
+                    async move {
+                        if wait {
+                            sleep(interval).await;
+                        }
+                        byte
+                    }
+                }))
+            }
+        };
+        Response::from_parts(parts, body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use http_body_util::BodyExt;
+
+    fn timeout(mode: TimeoutMode, interval_ms: u64) -> Timeout {
+        Timeout {
+            mode,
+            drip_interval: Duration::from_millis(interval_ms),
+        }
+    }
+
+    #[test]
+    fn test_parse_header() {
+        let default = Duration::from_secs(1);
+        assert_eq!(
+            Timeout::parse("drip:50", default),
+            Ok(timeout(TimeoutMode::Drip, 50))
+        );
+        assert_eq!(
+            Timeout::parse("hang", default),
+            Ok(timeout(TimeoutMode::Hang, 1000))
+        );
+        assert!(Timeout::parse("forever", default).is_err());
+    }
+
+    #[test]
+    fn test_provider_rules() {
+        let config: TimeoutConfig = toml::from_str(
+            r#"
+            mode = "stall"
+            [providers.claude]
+            mode = "drip"
+            drip_interval_ms = 10
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.rule(Provider::OpenAI).mode, TimeoutMode::Stall);
+        assert_eq!(config.rule(Provider::Claude).mode, TimeoutMode::Drip);
+        assert_eq!(config.rule(Provider::Claude).drip_interval_ms, 10);
+    }
+
+    #[tokio::test]
+    async fn test_drip() {
+        let response = timeout(TimeoutMode::Drip, 1).apply(Response::new(Body::from("hello")));
+        let mut body = response.into_body();
+        let mut frames = Vec::new();
+        while let Some(frame) = body.frame().await {
+            frames.push(frame.unwrap().into_data().unwrap());
+        }
+        assert_eq!(frames.len(), 5);
+        assert_eq!(frames.concat(), b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_stall() {
+        let response = timeout(TimeoutMode::Stall, 1).apply(Response::new(Body::from("hello")));
+        let mut body = response.into_body();
+        let next = tokio::time::timeout(Duration::from_millis(50), body.frame()).await;
+        assert!(next.is_err());
+    }
+}
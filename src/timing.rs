@@ -0,0 +1,256 @@
+//! Pacing of streamed responses.
+//!
+//! Each stream is a list of SSE events tagged with the output tokens they
+//! carry. Events before the first token go out at once; the first token
+//! arrives after the time-to-first-token, and every later event after the
+//! time its tokens take at the model's rate, with optional jitter and pauses.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use axum::{
+    body::Body,
+    http::{header, StatusCode},
+    response::Response,
+};
+use fastrand::Rng;
+use futures::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::time::sleep;
+
+use crate::errors::Provider;
+
+/// How the time between events varies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JitterDistribution {
+    /// Uniform within ±`jitter` of the delay.
+    #[default]
+    Uniform,
+    /// Normal with a standard deviation of `jitter` times the delay.
+    Normal,
+}
+
+/// Streaming speed of a provider or model.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimingModel {
+    /// Delay before the first output token.
+    pub time_to_first_token_ms: u64,
+    /// Output tokens per second after the first (0 = no delay).
+    pub tokens_per_second: f64,
+    /// Relative variation of each delay (0.0 = none, 0.3 = ±30%).
+    pub jitter: f64,
+    pub jitter_distribution: JitterDistribution,
+    /// Chance (0.0 - 1.0) of a pause before an event.
+    pub pause_probability: f64,
+    pub pause_ms: u64,
+}
+
+impl Default for TimingModel {
+    fn default() -> Self {
+        Self {
+            time_to_first_token_ms: 20,
+            tokens_per_second: 200.0,
+            jitter: 0.0,
+            jitter_distribution: JitterDistribution::Uniform,
+            pause_probability: 0.0,
+            pause_ms: 500,
+        }
+    }
+}
+
+/// Timing settings in the main config.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimingConfig {
+    /// Model for everything without a more specific one.
+    #[serde(flatten)]
+    pub default: TimingModel,
+    /// Models for individual providers.
+    pub providers: BTreeMap<Provider, TimingModel>,
+    /// Models keyed by model name prefix; the longest matching prefix wins
+    /// over the provider's model.
+    pub models: BTreeMap<String, TimingModel>,
+}
+
+impl TimingConfig {
+    /// The timing model for a request.
+    pub fn model(&self, provider: Provider, model: &str) -> &TimingModel {
+        self.models
+            .iter()
+            .filter(|(prefix, _)| model.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, timing)| timing)
+            .or_else(|| self.providers.get(&provider))
+            .unwrap_or(&self.default)
+    }
+}
+
+impl TimingModel {
+    /// Delay before each event, given the output tokens each carries.
+    pub fn delays(&self, tokens: &[u32], rng: &mut Rng) -> Vec<Duration> {
+        let mut first = true;
+        tokens
+            .iter()
+            .map(|&tokens| {
+                if tokens == 0 {
+                    return Duration::ZERO;
+                }
+                let base_ms = if std::mem::take(&mut first) {
+                    self.time_to_first_token_ms as f64
+                } else if self.tokens_per_second > 0.0 {
+                    f64::from(tokens) * 1000.0 / self.tokens_per_second
+                } else {
+                    0.0
+                };
+                let mut ms = self.jittered(base_ms, rng);
+                if self.pause_probability > 0.0 && rng.f64() < self.pause_probability {
+                    ms += self.pause_ms as f64;
+                }
+                Duration::from_secs_f64(ms / 1000.0)
+            })
+            .collect()
+    }
+
+    fn jittered(&self, ms: f64, rng: &mut Rng) -> f64 {
+        if self.jitter <= 0.0 || ms <= 0.0 {
+            return ms;
+        }
+        let offset = match self.jitter_distribution {
+            JitterDistribution::Uniform => (rng.f64() * 2.0 - 1.0) * self.jitter,
+            JitterDistribution::Normal => {
+                // Box-Muller transform.
+                let u1 = rng.f64().max(f64::MIN_POSITIVE);
+                let u2 = rng.f64();
+                (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos() * self.jitter
+            }
+        };
+        (ms * (1.0 + offset)).max(0.0)
+    }
+}
+
+/// SSE events of a response, with the output tokens each carries.
+#[derive(Debug, Default)]
+pub struct Events {
+    events: Vec<(String, u32)>,
+}
+
+impl Events {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an event without output tokens, sent as soon as possible.
+    pub fn push(&mut self, event: String) {
+        self.events.push((event, 0));
+    }
+
+    /// Add an event carrying `tokens` output tokens.
+    pub fn push_tokens(&mut self, event: String, tokens: u32) {
+        self.events.push((event, tokens));
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Stream the events as an SSE response, each after its delay.
+    pub fn into_response(self, delays: Vec<Duration>) -> Response {
+        let stream = stream::iter(self.events.into_iter().zip(delays))
+            .then(|((event, _), delay)| async move {
+                if !delay.is_zero() {
+                    sleep(delay).await;
+                }
+                event
+            })
+            .map(Ok::<_, std::convert::Infallible>);
+
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "text/event-stream")
+            .header(header::CACHE_CONTROL, "no-cache")
+            .header(header::CONNECTION, "keep-alive")
+            .body(Body::from_stream(stream))
+            .unwrap()
+    }
+
+    /// Output tokens of each event, in order.
+    pub fn tokens(&self) -> Vec<u32> {
+        self.events.iter().map(|(_, tokens)| *tokens).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model(ttft: u64, tps: f64) -> TimingModel {
+        TimingModel {
+            time_to_first_token_ms: ttft,
+            tokens_per_second: tps,
+            ..TimingModel::default()
+        }
+    }
+
+    #[test]
+    fn test_delays() {
+        let delays = model(300, 50.0).delays(&[0, 5, 10, 0], &mut Rng::with_seed(1));
+        assert_eq!(
+            delays,
+            vec![
+                Duration::ZERO,
+                Duration::from_millis(300),
+                Duration::from_millis(200),
+                Duration::ZERO
+            ]
+        );
+    }
+
+    #[test]
+    fn test_jitter_and_pauses() {
+        let timing = TimingModel {
+            jitter: 0.5,
+            jitter_distribution: JitterDistribution::Normal,
+            pause_probability: 1.0,
+            pause_ms: 1000,
+            ..model(100, 10.0)
+        };
+        let delays = timing.delays(&[1; 20], &mut Rng::with_seed(7));
+        assert!(delays.iter().all(|d| *d >= Duration::from_secs(1)));
+        assert!(delays.windows(2).any(|w| w[0] != w[1]));
+
+        let uniform = TimingModel {
+            jitter: 0.2,
+            ..model(100, 10.0)
+        };
+        for delay in uniform.delays(&[1; 50], &mut Rng::with_seed(3)) {
+            assert!((80..=120).contains(&delay.as_millis()));
+        }
+    }
+
+    #[test]
+    fn test_model_lookup() {
+        let config: TimingConfig = toml::from_str(
+            r"
+            tokens_per_second = 100.0
+            [providers.claude]
+            tokens_per_second = 60.0
+            [models.claude-3-5-haiku]
+            tokens_per_second = 150.0
+            [models.claude-3]
+            tokens_per_second = 80.0
+            ",
+        )
+        .unwrap();
+        let tps = |provider, name| config.model(provider, name).tokens_per_second;
+        assert!((tps(Provider::OpenAI, "gpt-4o") - 100.0).abs() < f64::EPSILON);
+        assert!((tps(Provider::Claude, "claude-sonnet-4") - 60.0).abs() < f64::EPSILON);
+        assert!((tps(Provider::Claude, "claude-3-opus") - 80.0).abs() < f64::EPSILON);
+        assert!((tps(Provider::Claude, "claude-3-5-haiku-latest") - 150.0).abs() < f64::EPSILON);
+    }
+}
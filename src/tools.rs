@@ -0,0 +1,486 @@
+//! Tool choice, call triggers and argument streaming shared by the provider
+//! mocks.
+//!
+//! Each provider parses its own `tool_choice` / `tool_config` format into a
+//! [`ToolChoice`], which decides whether a reply calls tools and which of the
+//! declared tools it may call. When the choice is left to the model, the
+//! configured [`ToolTrigger`] decides from the prompt. [`ArgumentStreaming`]
+//! cuts the argument JSON of streamed calls into deltas.
+
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use fastrand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::fixtures::Pattern;
+
+/// Shortest word of a tool's name or description that `match_tools` looks for.
+const MIN_TOOL_WORD_LEN: usize = 4;
+
+/// Whether a reply calls tools.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ToolMode {
+    /// The mock decides, from the prompt.
+    #[default]
+    Auto,
+    /// Never call a tool.
+    None,
+    /// Always call a tool.
+    Required,
+}
+
+/// Tool calling constraints of a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolChoice {
+    pub mode: ToolMode,
+    /// Names of the tools that may be called (`None` = any declared tool).
+    pub allowed: Option<Vec<String>>,
+    /// Whether a reply may make several calls at once.
+    pub parallel: bool,
+}
+
+impl Default for ToolChoice {
+    fn default() -> Self {
+        Self {
+            mode: ToolMode::Auto,
+            allowed: None,
+            parallel: true,
+        }
+    }
+}
+
+impl ToolChoice {
+    /// Choice with the given mode, allowing any tool.
+    pub fn new(mode: ToolMode) -> Self {
+        Self {
+            mode,
+            ..Self::default()
+        }
+    }
+
+    /// Choice forcing a call to the named tool.
+    pub fn function(name: impl Into<String>) -> Self {
+        Self::new(ToolMode::Required).allowing(vec![name.into()])
+    }
+
+    /// Restrict calls to the named tools.
+    #[must_use]
+    pub fn allowing(mut self, names: Vec<String>) -> Self {
+        self.allowed = Some(names);
+        self
+    }
+
+    /// Whether to reply with tool calls, asking `auto` when the mock decides.
+    pub fn should_call(&self, auto: impl FnOnce() -> bool) -> bool {
+        match self.mode {
+            ToolMode::Auto => auto(),
+            ToolMode::None => false,
+            ToolMode::Required => true,
+        }
+    }
+
+    /// Whether the named tool may be called.
+    pub fn allows(&self, name: &str) -> bool {
+        self.allowed
+            .as_ref()
+            .is_none_or(|allowed| allowed.iter().any(|n| n == name))
+    }
+
+    /// Check the choice against the names of the declared tools, as the real
+    /// APIs do before generating: every allowed tool must be declared, and a
+    /// required call needs a tool to call.
+    pub fn check(&self, declared: &[&str]) -> Result<(), ToolChoiceError> {
+        let undeclared = self
+            .allowed
+            .iter()
+            .flatten()
+            .find(|name| !declared.contains(&name.as_str()));
+        if let Some(name) = undeclared {
+            return Err(ToolChoiceError::Undeclared(name.clone()));
+        }
+        if self.mode == ToolMode::Required && declared.is_empty() {
+            return Err(ToolChoiceError::NoTools);
+        }
+        Ok(())
+    }
+}
+
+/// Why a request's tool choice is rejected with an invalid request error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ToolChoiceError {
+    /// The choice names a tool the request doesn't declare.
+    Undeclared(String),
+    /// The choice requires a call but the request declares no tools.
+    NoTools,
+}
+
+/// When a reply calls tools in `auto` mode. Any matching condition triggers
+/// a call.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ToolTrigger {
+    /// Call whenever the request declares tools.
+    pub always: bool,
+    /// Words or phrases of the prompt that trigger a call (case-insensitive).
+    pub keywords: Vec<String>,
+    /// Regular expressions matched against the prompt.
+    pub patterns: Vec<Pattern>,
+    /// Call when the prompt contains a word of a declared tool's name or
+    /// description.
+    pub match_tools: bool,
+    /// Chance (0.0 - 1.0) of a call when nothing else triggers one.
+    pub probability: f64,
+}
+
+impl Default for ToolTrigger {
+    fn default() -> Self {
+        Self {
+            always: false,
+            keywords: ["weather", "search", "calculate", "what is", "find"]
+                .map(String::from)
+                .to_vec(),
+            patterns: Vec::new(),
+            match_tools: false,
+            probability: 0.0,
+        }
+    }
+}
+
+impl ToolTrigger {
+    /// Whether `prompt` triggers a call to the tools described by
+    /// `tool_texts` (their names and descriptions).
+    pub fn fires<'a>(
+        &self,
+        prompt: Option<&str>,
+        tool_texts: impl IntoIterator<Item = &'a str>,
+        rng: &mut Rng,
+    ) -> bool {
+        if self.always {
+            return true;
+        }
+        if let Some(prompt) = prompt {
+            let lower = prompt.to_lowercase();
+            let keyword = self
+                .keywords
+                .iter()
+                .filter(|keyword| !keyword.is_empty())
+                .any(|keyword| lower.contains(&keyword.to_lowercase()));
+            if keyword || self.patterns.iter().any(|p| p.0.is_match(prompt)) {
+                return true;
+            }
+            if self.match_tools {
+                let prompt_words: HashSet<&str> = words(&lower).collect();
+                let tool_word = tool_texts
+                    .into_iter()
+                    .any(|text| words(&text.to_lowercase()).any(|w| prompt_words.contains(w)));
+                if tool_word {
+                    return true;
+                }
+            }
+        }
+        self.probability > 0.0 && rng.f64() < self.probability
+    }
+}
+
+/// Words of `text` long enough to tell tools apart, split at anything but
+/// letters and digits (so `get_weather` has the word `weather`).
+fn words(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|ch: char| !ch.is_alphanumeric())
+        .filter(|word| word.chars().count() >= MIN_TOOL_WORD_LEN)
+}
+
+/// Where streamed tool call arguments are cut into deltas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArgumentSplit {
+    /// The whole argument JSON in one delta.
+    Whole,
+    /// Deltas of random length.
+    #[default]
+    Random,
+    /// Deltas of random length that end inside escape sequences, keys and
+    /// string values whenever one is in reach.
+    Adversarial,
+}
+
+/// How the argument JSON of streamed tool calls is written and cut into deltas.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ArgumentStreaming {
+    pub split: ArgumentSplit,
+    /// Fewest characters in a delta.
+    pub min_chars: usize,
+    /// Most characters in a delta.
+    pub max_chars: usize,
+    /// Write non-ASCII characters as `\uXXXX` escapes (surrogate pairs above
+    /// U+FFFF), so cuts can land inside them.
+    pub escape_unicode: bool,
+}
+
+impl Default for ArgumentStreaming {
+    fn default() -> Self {
+        Self {
+            split: ArgumentSplit::Random,
+            min_chars: 1,
+            max_chars: 16,
+            escape_unicode: false,
+        }
+    }
+}
+
+impl ArgumentStreaming {
+    /// Argument JSON of a call.
+    pub fn encode(&self, arguments: &Value) -> String {
+        let json = arguments.to_string();
+        if !self.escape_unicode {
+            return json;
+        }
+        // Non-ASCII characters only occur inside strings in serialized JSON.
+        let mut escaped = String::with_capacity(json.len());
+        for ch in json.chars() {
+            if ch.is_ascii() {
+                escaped.push(ch);
+            } else {
+                for unit in ch.encode_utf16(&mut [0; 2]) {
+                    let _ = write!(escaped, "\\u{unit:04x}");
+                }
+            }
+        }
+        escaped
+    }
+
+    /// Cut argument JSON into deltas that concatenate back to `arguments`.
+    pub fn split(&self, arguments: &str, rng: &mut Rng) -> Vec<String> {
+        if self.split == ArgumentSplit::Whole || arguments.is_empty() {
+            return vec![arguments.to_string()];
+        }
+        let offsets: Vec<usize> = arguments
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain([arguments.len()])
+            .collect();
+        let chars = offsets.len() - 1;
+        let (escapes, strings) = if self.split == ArgumentSplit::Adversarial {
+            inner_cuts(arguments)
+        } else {
+            (Vec::new(), Vec::new())
+        };
+        let min = self.min_chars.max(1);
+        let max = self.max_chars.max(min);
+
+        let mut deltas = Vec::new();
+        let mut start = 0;
+        while start < chars {
+            let range = (start + min).min(chars)..=(start + max).min(chars);
+            let pick = |cuts: &[usize], rng: &mut Rng| {
+                let within: Vec<usize> = cuts
+                    .iter()
+                    .copied()
+                    .filter(|cut| range.contains(cut))
+                    .collect();
+                (!within.is_empty()).then(|| within[rng.usize(..within.len())])
+            };
+            let end = pick(&escapes, rng)
+                .or_else(|| pick(&strings, rng))
+                .unwrap_or_else(|| rng.usize(range.clone()));
+            deltas.push(arguments[offsets[start]..offsets[end]].to_string());
+            start = end;
+        }
+        deltas
+    }
+}
+
+/// Cut positions (in characters) inside escape sequences, including between
+/// the halves of a surrogate pair, and inside strings (keys and values).
+fn inner_cuts(json: &str) -> (Vec<usize>, Vec<usize>) {
+    let mut escapes = Vec::new();
+    let mut strings = Vec::new();
+    let mut in_string = false;
+    let mut after_backslash = false;
+    let mut hex_left = 0;
+    let mut after_unicode_escape = false;
+    for (i, ch) in json.chars().enumerate() {
+        if after_backslash {
+            escapes.push(i);
+            after_backslash = false;
+            if ch == 'u' {
+                hex_left = 4;
+            }
+            continue;
+        }
+        if hex_left > 0 {
+            escapes.push(i);
+            hex_left -= 1;
+            after_unicode_escape = hex_left == 0;
+            continue;
+        }
+        if in_string {
+            if ch == '\\' && after_unicode_escape {
+                escapes.push(i);
+            } else {
+                strings.push(i);
+            }
+            match ch {
+                '\\' => after_backslash = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+        } else if ch == '"' {
+            in_string = true;
+        }
+        after_unicode_escape = false;
+    }
+    (escapes, strings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_should_call() {
+        assert!(ToolChoice::default().should_call(|| true));
+        assert!(!ToolChoice::default().should_call(|| false));
+        assert!(!ToolChoice::new(ToolMode::None).should_call(|| true));
+        assert!(ToolChoice::new(ToolMode::Required).should_call(|| false));
+    }
+
+    #[test]
+    fn test_allows() {
+        assert!(ToolChoice::default().allows("anything"));
+        let choice = ToolChoice::function("get_time");
+        assert_eq!(choice.mode, ToolMode::Required);
+        assert!(choice.allows("get_time"));
+        assert!(!choice.allows("get_weather"));
+    }
+
+    #[test]
+    fn test_check() {
+        let declared = ["get_time", "get_weather"];
+        assert_eq!(ToolChoice::default().check(&[]), Ok(()));
+        assert_eq!(ToolChoice::function("get_time").check(&declared), Ok(()));
+        assert_eq!(
+            ToolChoice::function("get_news").check(&declared),
+            Err(ToolChoiceError::Undeclared("get_news".to_string()))
+        );
+        assert_eq!(
+            ToolChoice::default()
+                .allowing(vec!["get_time".to_string(), "get_news".to_string()])
+                .check(&declared),
+            Err(ToolChoiceError::Undeclared("get_news".to_string()))
+        );
+        assert_eq!(
+            ToolChoice::new(ToolMode::Required).check(&[]),
+            Err(ToolChoiceError::NoTools)
+        );
+    }
+
+    #[test]
+    fn test_trigger_keywords_and_patterns() {
+        let mut rng = Rng::with_seed(1);
+        let trigger = ToolTrigger::default();
+        assert!(trigger.fires(Some("What is the WEATHER?"), [], &mut rng));
+        assert!(!trigger.fires(Some("Wie ist das Wetter?"), [], &mut rng));
+        assert!(!trigger.fires(None, [], &mut rng));
+
+        let trigger: ToolTrigger = toml::from_str(
+            r#"
+            keywords = ["wetter"]
+            patterns = ["(?i)^such"]
+            "#,
+        )
+        .unwrap();
+        assert!(trigger.fires(Some("Wie ist das Wetter?"), [], &mut rng));
+        assert!(trigger.fires(Some("Suche nach Berlin"), [], &mut rng));
+        assert!(!trigger.fires(Some("Hallo"), [], &mut rng));
+    }
+
+    #[test]
+    fn test_trigger_match_tools() {
+        let mut rng = Rng::with_seed(1);
+        let trigger = ToolTrigger {
+            keywords: Vec::new(),
+            match_tools: true,
+            ..ToolTrigger::default()
+        };
+        let tools = ["get_wetter", "Liefert die Uhrzeit einer Stadt"];
+        assert!(trigger.fires(Some("Wetter in Berlin?"), tools, &mut rng));
+        assert!(trigger.fires(Some("Welche Uhrzeit ist es?"), tools, &mut rng));
+        assert!(!trigger.fires(Some("Hallo, wie geht es?"), tools, &mut rng));
+    }
+
+    #[test]
+    fn test_trigger_always_and_probability() {
+        let mut rng = Rng::with_seed(1);
+        let always = ToolTrigger {
+            always: true,
+            ..ToolTrigger::default()
+        };
+        assert!(always.fires(None, [], &mut rng));
+
+        let sometimes = ToolTrigger {
+            keywords: Vec::new(),
+            probability: 0.5,
+            ..ToolTrigger::default()
+        };
+        let calls = (0..1000)
+            .filter(|_| sometimes.fires(Some("Hello"), [], &mut rng))
+            .count();
+        assert!((400..600).contains(&calls), "{calls}");
+    }
+
+    #[test]
+    fn test_argument_encode() {
+        let arguments = serde_json::json!({"city": "München 😀"});
+        let mut streaming = ArgumentStreaming::default();
+        assert_eq!(streaming.encode(&arguments), r#"{"city":"München 😀"}"#);
+        streaming.escape_unicode = true;
+        let escaped = streaming.encode(&arguments);
+        assert_eq!(escaped, r#"{"city":"M\u00fcnchen \ud83d\ude00"}"#);
+        assert_eq!(serde_json::from_str::<Value>(&escaped).unwrap(), arguments);
+    }
+
+    #[test]
+    fn test_argument_split() {
+        let mut rng = Rng::with_seed(7);
+        let json = r#"{"query":"a \"quoted\" word","emoji":"😀"}"#;
+
+        let whole = ArgumentStreaming {
+            split: ArgumentSplit::Whole,
+            ..ArgumentStreaming::default()
+        };
+        assert_eq!(whole.split(json, &mut rng), [json]);
+
+        let random = ArgumentStreaming {
+            min_chars: 2,
+            max_chars: 5,
+            ..ArgumentStreaming::default()
+        };
+        let deltas = random.split("{\"city\":\"Zürich\"}", &mut rng);
+        assert_eq!(deltas.concat(), "{\"city\":\"Zürich\"}");
+        let (last, rest) = deltas.split_last().unwrap();
+        assert!(rest.iter().all(|d| (2..=5).contains(&d.chars().count())));
+        assert!(last.chars().count() <= 5);
+
+        let adversarial = ArgumentStreaming {
+            split: ArgumentSplit::Adversarial,
+            min_chars: 1,
+            max_chars: 8,
+            ..ArgumentStreaming::default()
+        };
+        let deltas = adversarial.split(json, &mut rng);
+        assert_eq!(deltas.concat(), json);
+        assert!(deltas.iter().any(|d| d.ends_with('\\')), "{deltas:?}");
+    }
+
+    #[test]
+    fn test_inner_cuts() {
+        let (escapes, strings) = inner_cuts(r#"{"a":"\n\u00fc\ud83d\ude00"}"#);
+        // Inside `\n` and the three `\u` escapes, and between the escapes.
+        assert_eq!(escapes.len(), 1 + 5 + 1 + 5 + 1 + 5);
+        assert!(escapes.contains(&20)); // `\ud83d|\ude00`
+        assert_eq!(strings[..2], [2, 3]); // `"|a|"`
+    }
+}
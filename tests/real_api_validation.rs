@@ -10,6 +10,8 @@
 //! - GEMINI_API_KEY
 //! - ANTHROPIC_API_KEY
 
+#![allow(clippy::uninlined_format_args)]
+
 use serde_json::Value;
 use std::collections::HashSet;
 